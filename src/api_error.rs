@@ -0,0 +1,376 @@
+use std::fmt;
+use actix_web::{HttpMessage, HttpResponse, ResponseError};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{CONTENT_LENGTH, CONTENT_TYPE, HeaderName, RETRY_AFTER, WWW_AUTHENTICATE};
+use actix_web::middleware::Next;
+use serde::Serialize;
+use crate::api_key_service::ApiKeyError;
+use crate::content_negotiation::NegotiationError;
+use crate::user_client::UserClientError;
+use crate::user_service::DatabaseError;
+
+/// Media type of problem detail responses.
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Header carrying the request id.
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Id of the current request, stored in the request extensions.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// Single error type for all REST apis. Rendered as RFC 7807 problem details.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum ApiError {
+    UserNotFound(String),
+    ApiKeyNotFound(String),
+    RevisionNotFound(String, u32),
+    BadRequest(String),
+    /// Missing or invalid credentials.
+    Unauthorized(String),
+    /// Caller lacks the required roles.
+    Forbidden(String),
+    /// Rate limit exceeded. Seconds until the next request is allowed.
+    TooManyRequests(u64),
+    NotAcceptable(Vec<&'static str>),
+    UnsupportedMediaType(Vec<&'static str>),
+    /// JsonPlaceholder did not answer properly.
+    UpstreamFailed(String),
+    DatabaseUnavailable,
+    Internal(String),
+    /// Any other error status, e.g. from extractors or unknown routes.
+    Http(StatusCode, String)
+}
+
+/// RFC 7807 problem details.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>
+}
+
+impl ApiError {
+
+    /// Identifier of the problem type, used in the `type` member.
+    fn slug(&self) -> &'static str {
+        match self {
+            ApiError::UserNotFound(_) => "user-not-found",
+            ApiError::ApiKeyNotFound(_) => "api-key-not-found",
+            ApiError::RevisionNotFound(_, _) => "revision-not-found",
+            ApiError::BadRequest(_) => "bad-request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::TooManyRequests(_) => "too-many-requests",
+            ApiError::NotAcceptable(_) => "not-acceptable",
+            ApiError::UnsupportedMediaType(_) => "unsupported-media-type",
+            ApiError::UpstreamFailed(_) => "upstream-failed",
+            ApiError::DatabaseUnavailable => "database-unavailable",
+            ApiError::Internal(_) => "internal-error",
+            ApiError::Http(_, _) => "http-error"
+        }
+    }
+
+    /// Short, human readable summary of the problem type.
+    fn title(&self) -> String {
+        match self {
+            ApiError::UserNotFound(_) => "User not found".to_string(),
+            ApiError::ApiKeyNotFound(_) => "API key not found".to_string(),
+            ApiError::RevisionNotFound(_, _) => "Revision not found".to_string(),
+            ApiError::UpstreamFailed(_) => "JsonPlaceholder request failed".to_string(),
+            ApiError::DatabaseUnavailable => "Database unavailable".to_string(),
+            _ => self.status_code().canonical_reason().unwrap_or("Error").to_string()
+        }
+    }
+
+    /// Explanation specific to this occurrence of the problem.
+    fn detail(&self) -> String {
+        match self {
+            ApiError::UserNotFound(id) => format!("User with id {id} was not found."),
+            ApiError::ApiKeyNotFound(id) => format!("API key with id {id} was not found."),
+            ApiError::RevisionNotFound(id, revision) => format!("User with id {id} has no revision {revision}."),
+            ApiError::NotAcceptable(supported) => format!("Supported media types: {}", supported.join(", ")),
+            ApiError::UnsupportedMediaType(supported) => format!("Supported content types: {}", supported.join(", ")),
+            ApiError::DatabaseUnavailable => "Could not connect to the database.".to_string(),
+            ApiError::TooManyRequests(retry_after) => format!("Rate limit exceeded. Retry after {retry_after} seconds."),
+            ApiError::BadRequest(detail)
+            | ApiError::Unauthorized(detail)
+            | ApiError::Forbidden(detail)
+            | ApiError::UpstreamFailed(detail)
+            | ApiError::Internal(detail)
+            | ApiError::Http(_, detail) => detail.clone()
+        }
+    }
+
+    /// Build problem details for this error.
+    ///
+    /// ## Arguments.
+    /// * `instance` - Path of the request that failed.
+    /// * `request_id` - Id of the request that failed.
+    pub fn to_problem(&self, instance: Option<String>, request_id: Option<String>) -> ProblemDetails {
+        ProblemDetails {
+            problem_type: format!("/problems/{}", self.slug()),
+            title: self.title(),
+            status: self.status_code().as_u16(),
+            detail: self.detail(),
+            instance,
+            request_id
+        }
+    }
+
+    /// Render problem details as a response.
+    ///
+    /// ## Arguments.
+    /// * `instance` - Path of the request that failed.
+    /// * `request_id` - Id of the request that failed.
+    pub fn to_response(&self, instance: Option<String>, request_id: Option<String>) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        match self {
+            ApiError::Unauthorized(_) => {
+                response.insert_header((WWW_AUTHENTICATE, "Bearer"));
+            },
+            ApiError::TooManyRequests(retry_after) => {
+                response.insert_header((RETRY_AFTER, retry_after.to_string()));
+            },
+            _ => {}
+        }
+
+        response
+            .content_type(PROBLEM_CONTENT_TYPE)
+            .body(serde_json::to_vec(&self.to_problem(instance, request_id)).unwrap())
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.title(), self.detail())
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::UserNotFound(_) | ApiError::ApiKeyNotFound(_) | ApiError::RevisionNotFound(_, _) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::UpstreamFailed(_) => StatusCode::BAD_GATEWAY,
+            ApiError::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Http(status, _) => *status
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        self.to_response(None, None)
+    }
+}
+
+impl From<DatabaseError> for ApiError {
+    fn from(e: DatabaseError) -> Self {
+        match e {
+            DatabaseError::UserNotFound(id) => ApiError::UserNotFound(id),
+            DatabaseError::RevisionNotFound(id, revision) => ApiError::RevisionNotFound(id, revision),
+            DatabaseError::MongoConnectionFailed => ApiError::DatabaseUnavailable,
+            DatabaseError::RevisionConflict | DatabaseError::OperationFailed => ApiError::Internal("Database operation failed.".to_string())
+        }
+    }
+}
+
+impl From<ApiKeyError> for ApiError {
+    fn from(e: ApiKeyError) -> Self {
+        match e {
+            ApiKeyError::ApiKeyNotFound(id) => ApiError::ApiKeyNotFound(id),
+            ApiKeyError::OperationFailed => ApiError::Internal("Database operation failed.".to_string())
+        }
+    }
+}
+
+impl From<UserClientError> for ApiError {
+    fn from(e: UserClientError) -> Self {
+        match e {
+            UserClientError::UserNotFound(id) => ApiError::UserNotFound(id),
+            UserClientError::RestError(status) => ApiError::UpstreamFailed(format!("JsonPlaceholder responded with {status}.")),
+            UserClientError::SerdeError => ApiError::UpstreamFailed("JsonPlaceholder response could not be read.".to_string()),
+            UserClientError::UrlParseError => ApiError::Internal("JsonPlaceholder url is invalid.".to_string()),
+            UserClientError::_NoIdError => ApiError::BadRequest("User has no id.".to_string())
+        }
+    }
+}
+
+impl From<NegotiationError> for ApiError {
+    fn from(e: NegotiationError) -> Self {
+        match e {
+            NegotiationError::NotAcceptable => ApiError::NotAcceptable(crate::content_negotiation::supported_media_types()),
+            NegotiationError::UnsupportedMediaType => ApiError::UnsupportedMediaType(crate::content_negotiation::supported_request_media_types()),
+            NegotiationError::DeserializationFailed(detail) => ApiError::BadRequest(format!("Request body could not be read: {detail}")),
+            NegotiationError::SerializationFailed(detail) => ApiError::Internal(format!("Response could not be serialized: {detail}"))
+        }
+    }
+}
+
+/// Get the request id from the `X-Request-Id` header or generate a new one.
+///
+/// ## Arguments.
+/// * `req` - Incoming request.
+pub fn request_id(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|header| header.to_str().ok())
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Middleware rendering every error response, including extractor failures and unknown routes,
+/// as problem details with `instance` and `request_id`.
+pub async fn problem_details(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    // Keep the id given by `logging::log_requests`, if it runs first.
+    let existing = req.extensions().get::<RequestId>().map(|request_id| request_id.0.clone());
+    let request_id = existing.unwrap_or_else(|| request_id(&req));
+    let instance = req.path().to_string();
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+
+    let res = next.call(req).await?;
+    let status = res.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return Ok(res.map_into_boxed_body())
+    }
+
+    // Keep our own errors as they are and wrap everything else.
+    let error = match res.response().error() {
+        Some(e) => match e.as_error::<ApiError>() {
+            Some(api_error) => api_error.clone(),
+            None => ApiError::Http(status, e.to_string())
+        },
+        None => ApiError::Http(status, status.canonical_reason().unwrap_or("Error").to_string())
+    };
+
+    let mut response = error.to_response(Some(instance), Some(request_id.clone()));
+
+    // Keep headers set by the original response, e.g. `Retry-After`.
+    for (name, value) in res.headers() {
+        if name != CONTENT_TYPE && name != CONTENT_LENGTH && !response.headers().contains_key(name) {
+            response.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+    response.headers_mut().insert(REQUEST_ID_HEADER, request_id.parse().unwrap());
+
+    Ok(res.into_response(response).map_into_boxed_body())
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{App, get, web};
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::middleware::from_fn;
+    use serde_json::Value;
+    use super::*;
+
+    #[get("/fail")]
+    async fn fail() -> Result<HttpResponse, ApiError> {
+        Err(DatabaseError::UserNotFound("666".to_string()).into())
+    }
+
+    #[get("/query")]
+    async fn query(_query: web::Query<std::collections::HashMap<String, u32>>) -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[test]
+    fn test_from_database_error() {
+        assert_eq!(ApiError::UserNotFound("1".to_string()), DatabaseError::UserNotFound("1".to_string()).into());
+        assert_eq!(ApiError::DatabaseUnavailable, DatabaseError::MongoConnectionFailed.into());
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, ApiError::from(DatabaseError::OperationFailed).status_code());
+    }
+
+    #[test]
+    fn test_from_user_client_error() {
+        assert_eq!(ApiError::UserNotFound("1".to_string()), UserClientError::UserNotFound("1".to_string()).into());
+        assert_eq!(StatusCode::BAD_GATEWAY, ApiError::from(UserClientError::RestError(StatusCode::BAD_REQUEST)).status_code());
+        assert_eq!(StatusCode::BAD_GATEWAY, ApiError::from(UserClientError::SerdeError).status_code());
+    }
+
+    #[test]
+    fn test_to_problem() {
+        let problem = ApiError::UserNotFound("1".to_string()).to_problem(Some("/users/1".to_string()), Some("ID".to_string()));
+
+        assert_eq!(
+            serde_json::json!({
+                "type": "/problems/user-not-found",
+                "title": "User not found",
+                "status": 404,
+                "detail": "User with id 1 was not found.",
+                "instance": "/users/1",
+                "request_id": "ID"
+            }),
+            serde_json::to_value(problem).unwrap()
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_error_response_without_middleware() {
+        let response = ApiError::BadRequest("BAD".to_string()).error_response();
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!(PROBLEM_CONTENT_TYPE, response.headers().get(CONTENT_TYPE).unwrap());
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_handler_error() {
+        let app = init_service(App::new().wrap(from_fn(problem_details)).service(fail)).await;
+
+        let req = TestRequest::get()
+            .uri("/fail")
+            .insert_header((REQUEST_ID_HEADER, "TEST_ID"))
+            .to_request();
+        let response = call_service(&app, req).await;
+
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert_eq!(PROBLEM_CONTENT_TYPE, response.headers().get(CONTENT_TYPE).unwrap());
+        assert_eq!("TEST_ID", response.headers().get(REQUEST_ID_HEADER).unwrap());
+
+        let body: Value = read_body_json(response).await;
+        assert_eq!("/problems/user-not-found", body["type"]);
+        assert_eq!("/fail", body["instance"]);
+        assert_eq!("TEST_ID", body["request_id"]);
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_extractor_error() {
+        let app = init_service(App::new().wrap(from_fn(problem_details)).service(query)).await;
+
+        let req = TestRequest::get().uri("/query?number=NOT_A_NUMBER").to_request();
+        let response = call_service(&app, req).await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let body: Value = read_body_json(response).await;
+        assert_eq!("/problems/http-error", body["type"]);
+        assert_eq!(400, body["status"]);
+        assert!(body["request_id"].is_string());
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_unknown_route() {
+        let app = init_service(App::new().wrap(from_fn(problem_details)).service(query)).await;
+
+        let req = TestRequest::get().uri("/MADE_UP_PATH").to_request();
+        let response = call_service(&app, req).await;
+
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        let body: Value = read_body_json(response).await;
+        assert_eq!("Not Found", body["detail"]);
+        assert_eq!("/MADE_UP_PATH", body["instance"]);
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashSet;
+use mongodb::bson::DateTime;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::authorization::Principal;
+
+/// Prefix of issued API keys, making them easy to spot in logs and secret scanners.
+pub const KEY_PREFIX: &str = "shk_";
+
+/// Scopes an API key can be issued with. Scopes are checked exactly like token roles.
+pub const SCOPES: [&str; 2] = ["admin", "user"];
+
+/// API key as stored in the database. Only the hash of the key itself is stored.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ApiKey {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub name: String,
+    /// SHA-256 of the key, hex encoded.
+    pub hash: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime,
+    pub expires_at: Option<DateTime>,
+    pub last_used_at: Option<DateTime>,
+    pub revoked_at: Option<DateTime>
+}
+
+/// Request body for issuing a new API key.
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// Key never expires when missing.
+    pub expires_in_days: Option<u32>
+}
+
+/// API key as shown by the REST apis. Timestamps are RFC 3339.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// Neither expired nor revoked.
+    pub active: bool,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+    pub revoked_at: Option<String>
+}
+
+/// Newly issued API key. The key is only ever shown here.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct IssuedApiKey {
+    pub key: String,
+    pub api_key: ApiKeyInfo
+}
+
+impl NewApiKey {
+
+    /// Check that the request is valid.
+    ///
+    /// ## Returns.
+    /// A result containing either nothing or every problem found.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = vec![];
+
+        if self.name.trim().is_empty() {
+            errors.push("Field name must not be empty.".to_string());
+        }
+
+        if self.scopes.is_empty() {
+            errors.push("At least one scope is required.".to_string());
+        }
+
+        self.scopes.iter()
+            .filter(|scope| !SCOPES.contains(&scope.as_str()))
+            .for_each(|scope| errors.push(format!("Unknown scope {scope}. Supported scopes: {}", SCOPES.join(", "))))
+        ;
+
+        if self.expires_in_days == Some(0) {
+            errors.push("Field expires_in_days must be positive.".to_string());
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+}
+
+impl ApiKey {
+
+    /// Create a new API key.
+    ///
+    /// ## Arguments.
+    /// * `new_key` - Validated request.
+    /// * `now` - Creation time.
+    ///
+    /// ## Returns.
+    /// The key to be stored and the key itself.
+    pub fn issue(new_key: NewApiKey, now: DateTime) -> (ApiKey, String) {
+        let key = generate_key();
+        let expires_at = new_key.expires_in_days.map(|days| {
+            DateTime::from_millis(now.timestamp_millis() + i64::from(days) * 24 * 60 * 60 * 1000)
+        });
+
+        let api_key = ApiKey {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: new_key.name,
+            hash: hash_key(&key),
+            scopes: new_key.scopes,
+            created_at: now,
+            expires_at,
+            last_used_at: None,
+            revoked_at: None
+        };
+
+        (api_key, key)
+    }
+
+    /// Check if the key can be used at the given time.
+    ///
+    /// ## Arguments.
+    /// * `now` - Time of use.
+    pub fn is_active(&self, now: DateTime) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+
+    /// Principal for requests made with this key. Scopes become roles.
+    pub fn principal(&self) -> Principal {
+        Principal {
+            subject: format!("api-key:{}", self.id),
+            roles: self.scopes.iter().cloned().collect::<HashSet<_>>(),
+            user_id: None,
+            client_certificate: None,
+            unrestricted: false
+        }
+    }
+
+    /// Public view of the key without the hash.
+    pub fn info(&self) -> ApiKeyInfo {
+        let format = |time: &DateTime| time.try_to_rfc3339_string().unwrap_or_default();
+        ApiKeyInfo {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            scopes: self.scopes.clone(),
+            active: self.is_active(DateTime::now()),
+            created_at: format(&self.created_at),
+            expires_at: self.expires_at.as_ref().map(format),
+            last_used_at: self.last_used_at.as_ref().map(format),
+            revoked_at: self.revoked_at.as_ref().map(format)
+        }
+    }
+}
+
+/// Generate a new random key with 256 bits of entropy.
+fn generate_key() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{KEY_PREFIX}{}", hex::encode(bytes))
+}
+
+/// Hash of the key used for storage and lookups.
+///
+/// ## Arguments.
+/// * `key` - API key as given by the caller.
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn new_key(scopes: &[&str], expires_in_days: Option<u32>) -> NewApiKey {
+        NewApiKey {
+            name: "TEST_JOB".to_string(),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+            expires_in_days
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(new_key(&["user"], Some(30)).validate().is_ok());
+
+        let errors = new_key(&["root"], Some(0)).validate().unwrap_err();
+        assert_eq!(2, errors.len());
+        assert!(errors[0].contains("root"));
+
+        assert!(new_key(&[], None).validate().is_err());
+    }
+
+    #[test]
+    fn test_issue() {
+        let now = DateTime::from_millis(1_000_000);
+        let (api_key, key) = ApiKey::issue(new_key(&["user"], Some(1)), now);
+
+        assert!(key.starts_with(KEY_PREFIX));
+        assert_eq!(hash_key(&key), api_key.hash);
+        assert_ne!(key, api_key.hash);
+        assert_eq!(Some(DateTime::from_millis(1_000_000 + 86_400_000)), api_key.expires_at);
+
+        let (other, other_key) = ApiKey::issue(new_key(&["user"], None), now);
+        assert_ne!(key, other_key);
+        assert_ne!(api_key.id, other.id);
+    }
+
+    #[test]
+    fn test_is_active() {
+        let now = DateTime::from_millis(1_000_000);
+        let (mut api_key, _) = ApiKey::issue(new_key(&["user"], Some(1)), now);
+
+        assert!(api_key.is_active(now));
+        assert!(!api_key.is_active(DateTime::from_millis(1_000_000 + 86_400_000)));
+
+        api_key.revoked_at = Some(now);
+        assert!(!api_key.is_active(now));
+    }
+
+    #[test]
+    fn test_principal() {
+        let (api_key, _) = ApiKey::issue(new_key(&["user"], None), DateTime::now());
+        let principal = api_key.principal();
+
+        assert!(principal.has_any_role(&["user"]));
+        assert!(!principal.has_any_role(&["admin"]));
+        assert_eq!(format!("api-key:{}", api_key.id), principal.subject);
+    }
+
+    #[test]
+    fn test_info() {
+        let (api_key, _) = ApiKey::issue(new_key(&["admin"], None), DateTime::from_millis(0));
+        let info = api_key.info();
+
+        assert_eq!("1970-01-01T00:00:00Z", info.created_at);
+        assert_eq!(None, info.expires_at);
+        assert!(info.active);
+        assert!(!serde_json::to_string(&info).unwrap().contains(&api_key.hash));
+    }
+}
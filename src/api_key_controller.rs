@@ -0,0 +1,101 @@
+use actix_web::{delete, get, HttpRequest, HttpResponse, post, web};
+use actix_web::http::header::CONTENT_TYPE;
+use log::{info, warn};
+use mongodb::bson::DateTime;
+use crate::api_error::ApiError;
+use crate::api_key::{ApiKeyInfo, NewApiKey};
+use crate::api_key_service::ApiKeyService;
+use crate::authorization::{Authorized, RoleRequirement};
+use crate::content_negotiation;
+use crate::user_controller::{negotiate_response_format, respond_with};
+
+/// Operations allowed for role "admin" only.
+pub struct Admin;
+
+impl RoleRequirement for Admin {
+    const ROLES: &'static [&'static str] = &["admin"];
+}
+
+#[post("/api-keys")]
+pub async fn issue_api_key(authorized: Authorized<Admin>, req: HttpRequest, body: web::Bytes, service: web::Data<ApiKeyService>) -> Result<HttpResponse, ApiError> {
+    info!("Incoming request to issue an API key from {}.", authorized.principal.subject);
+    let format = negotiate_response_format(&req)?;
+    let content_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|header| header.to_str().ok())
+    ;
+
+    let new_key: NewApiKey = content_negotiation::deserialize(content_type, &body).map_err(|e| {
+        warn!("Request body could not be read: {e:?}.");
+        ApiError::from(e)
+    })?;
+
+    new_key.validate().map_err(|errors| {
+        warn!("Invalid API key request. Responding with 400.");
+        ApiError::BadRequest(errors.join(" "))
+    })?;
+
+    let issued = service.issue_api_key(new_key).await.map_err(|e| {
+        warn!("API key could not be issued: {e:?}.");
+        ApiError::from(e)
+    })?;
+
+    info!("API key {} issued. Responding with 201.", issued.api_key.id);
+    respond_with(HttpResponse::Created(), format, content_negotiation::serialize(format, "issued_api_key", &issued))
+}
+
+#[get("/api-keys")]
+pub async fn get_api_keys(authorized: Authorized<Admin>, req: HttpRequest, service: web::Data<ApiKeyService>) -> Result<HttpResponse, ApiError> {
+    info!("Incoming request for all API keys from {}.", authorized.principal.subject);
+    let format = negotiate_response_format(&req)?;
+
+    let api_keys: Vec<ApiKeyInfo> = service.get_api_keys().await
+        .map_err(|e| {
+            warn!("Could not get API keys: {e:?}.");
+            ApiError::from(e)
+        })?
+        .iter()
+        .map(|api_key| api_key.info())
+        .collect()
+    ;
+
+    info!("Found {} API keys. Responding with 200.", api_keys.len());
+    respond_with(HttpResponse::Ok(), format, content_negotiation::serialize_list(format, "api_keys", "api_key", &api_keys))
+}
+
+#[delete("/api-keys/{id}")]
+pub async fn revoke_api_key(authorized: Authorized<Admin>, id: web::Path<String>, service: web::Data<ApiKeyService>) -> Result<HttpResponse, ApiError> {
+    info!("Incoming request to revoke API key {id} from {}.", authorized.principal.subject);
+
+    service.revoke_api_key(&id, DateTime::now()).await.map_err(|e| {
+        warn!("Could not revoke API key {id}: {e:?}.");
+        ApiError::from(e)
+    })?;
+
+    info!("API key {id} revoked. Responding with 204.");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::App;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use crate::user_repository::get_client;
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_issue_api_key_invalid_scope() {
+        // Never connected to, the request is rejected first.
+        let database = get_client("mongodb://localhost:27017").await.unwrap().database("showcase_test");
+        let app = init_service(App::new().app_data(web::Data::new(ApiKeyService::new(database))).service(issue_api_key)).await;
+
+        let req = TestRequest::post()
+            .uri("/api-keys")
+            .insert_header((CONTENT_TYPE, "application/json"))
+            .set_payload(r#"{"name": "nightly-export", "scopes": ["root"]}"#)
+            .to_request();
+        assert_eq!(StatusCode::BAD_REQUEST, call_service(&app, req).await.status());
+    }
+}
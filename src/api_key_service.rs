@@ -0,0 +1,205 @@
+use futures::TryStreamExt;
+use log::warn;
+use mongodb::{Collection, Database, IndexModel};
+use mongodb::bson::{doc, DateTime};
+use mongodb::options::{FindOneAndUpdateOptions, IndexOptions, ReturnDocument};
+use crate::api_key::{ApiKey, hash_key, IssuedApiKey, NewApiKey};
+
+/// Possible errors thrown by `ApiKeyService`.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum ApiKeyError {
+    ApiKeyNotFound(String),
+    OperationFailed
+}
+
+/// API keys stored in the "api_keys" collection of MongoDB. Created once at startup and shared
+/// with handlers and the authentication middleware as app data.
+pub struct ApiKeyService {
+    database: Database
+}
+
+impl ApiKeyService {
+    pub fn new(database: Database) -> Self {
+        ApiKeyService { database }
+    }
+
+    /// Create the indexes the service relies on. Existing indexes are left as is.
+    ///
+    /// ## Returns.
+    /// A result containing possible `ApiKeyError`.
+    pub async fn create_indexes(&self) -> Result<(), ApiKeyError> {
+        // Keys are looked up by hash on every request, and no two keys may share one.
+        let index = IndexModel::builder()
+            .keys(doc! { "hash": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build()
+        ;
+        self.api_keys().create_index(index, None).await.map(|_| ()).map_err(|e| {
+            warn!("Could not create API key index: {e:?}");
+            ApiKeyError::OperationFailed
+        })
+    }
+
+    /// Collection with name "api_keys".
+    fn api_keys(&self) -> Collection<ApiKey> {
+        self.database.collection("api_keys")
+    }
+
+    /// Create and store a new API key.
+    ///
+    /// ## Arguments.
+    /// * `new_key` - Validated request.
+    ///
+    /// ## Returns.
+    /// A result containing either the issued key or an error.
+    pub async fn issue_api_key(&self, new_key: NewApiKey) -> Result<IssuedApiKey, ApiKeyError> {
+        let (api_key, key) = ApiKey::issue(new_key, DateTime::now());
+
+        match self.api_keys().insert_one(&api_key, None).await {
+            Ok(_) => Ok(IssuedApiKey { key, api_key: api_key.info() }),
+            Err(e) => {
+                warn!("Could not store API key: {e:?}");
+                Err(ApiKeyError::OperationFailed)
+            }
+        }
+    }
+
+    /// Get all API keys, including revoked and expired ones.
+    pub async fn get_api_keys(&self) -> Result<Vec<ApiKey>, ApiKeyError> {
+        let cursor = self.api_keys().find(None, None).await.map_err(|_| ApiKeyError::OperationFailed)?;
+        cursor.try_collect().await.map_err(|_| ApiKeyError::OperationFailed)
+    }
+
+    /// Revoke API key with id. Revoking an already revoked key succeeds.
+    ///
+    /// ## Arguments.
+    /// * `id` - API key id.
+    /// * `now` - Time of revocation.
+    pub async fn revoke_api_key(&self, id: &str, now: DateTime) -> Result<(), ApiKeyError> {
+        let collection = self.api_keys();
+
+        let update_result = collection.update_one(
+            doc! { "_id": id, "revoked_at": null },
+            doc! { "$set": { "revoked_at": now } },
+            None
+        ).await.map_err(|_| ApiKeyError::OperationFailed)?;
+
+        if update_result.matched_count > 0 {
+            return Ok(())
+        }
+
+        // Nothing to update, either already revoked or missing.
+        match collection.count_documents(doc! { "_id": id }, None).await {
+            Ok(0) => Err(ApiKeyError::ApiKeyNotFound(id.to_string())),
+            Ok(_) => Ok(()),
+            Err(_) => Err(ApiKeyError::OperationFailed)
+        }
+    }
+
+    /// Find the active API key matching the given key and record its use.
+    ///
+    /// ## Arguments.
+    /// * `key` - API key as given by the caller.
+    /// * `now` - Time of use.
+    ///
+    /// ## Returns.
+    /// A result containing the key if it exists and is neither expired nor revoked.
+    pub async fn verify_api_key(&self, key: &str, now: DateTime) -> Result<Option<ApiKey>, ApiKeyError> {
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build()
+        ;
+
+        self.api_keys().find_one_and_update(
+            doc! {
+                "hash": hash_key(key),
+                "revoked_at": null,
+                "$or": [{ "expires_at": null }, { "expires_at": { "$gt": now } }]
+            },
+            doc! { "$set": { "last_used_at": now } },
+            options
+        ).await.map_err(|e| {
+            warn!("Could not verify API key: {e:?}");
+            ApiKeyError::OperationFailed
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use testcontainers::GenericImage;
+    use testcontainers::clients::Cli;
+    use super::*;
+    use crate::user_repository::get_client;
+
+    // Database name used in tests.
+    const DB_NAME: &str = "showcase_test";
+    // Connection string -template.
+    const C_STRING: &str = "mongodb://localhost:";
+
+    fn new_key(expires_in_days: Option<u32>) -> NewApiKey {
+        NewApiKey {
+            name: "TEST_JOB".to_string(),
+            scopes: vec!["user".to_string()],
+            expires_in_days
+        }
+    }
+
+    #[tokio::test]
+    async fn test_issue_verify_and_revoke_api_key() {
+        let client = Cli::default();
+        let container = client.run(get_mongo_image());
+
+        let port = container.get_host_port_ipv4(27017);
+        let service = ApiKeyService::new(get_client(&format!("{}{}", C_STRING, port)).await.unwrap().database(DB_NAME));
+        service.create_indexes().await.unwrap();
+
+        let issued = service.issue_api_key(new_key(None)).await.unwrap();
+        let now = DateTime::now();
+
+        let api_key = service.verify_api_key(&issued.key, now).await.unwrap().unwrap();
+        assert_eq!(issued.api_key.id, api_key.id);
+        assert_eq!(Some(now), api_key.last_used_at);
+
+        assert_eq!(None, service.verify_api_key("shk_WRONG", now).await.unwrap());
+
+        service.revoke_api_key(&api_key.id, now).await.unwrap();
+        assert_eq!(None, service.verify_api_key(&issued.key, now).await.unwrap());
+
+        // Revoking twice is fine, unknown keys are not.
+        assert_eq!(Ok(()), service.revoke_api_key(&api_key.id, now).await);
+        assert_eq!(
+            Err(ApiKeyError::ApiKeyNotFound("666".to_string())),
+            service.revoke_api_key("666", now).await
+        );
+
+        let api_keys = service.get_api_keys().await.unwrap();
+        assert_eq!(1, api_keys.len());
+        assert!(api_keys[0].revoked_at.is_some());
+
+        container.stop();
+    }
+
+    #[tokio::test]
+    async fn test_verify_expired_api_key() {
+        let client = Cli::default();
+        let container = client.run(get_mongo_image());
+
+        let port = container.get_host_port_ipv4(27017);
+        let service = ApiKeyService::new(get_client(&format!("{}{}", C_STRING, port)).await.unwrap().database(DB_NAME));
+        service.create_indexes().await.unwrap();
+
+        let issued = service.issue_api_key(new_key(Some(1))).await.unwrap();
+        let later = DateTime::from_millis(DateTime::now().timestamp_millis() + 2 * 86_400_000);
+
+        assert_eq!(None, service.verify_api_key(&issued.key, later).await.unwrap());
+
+        container.stop();
+    }
+
+    fn get_mongo_image() -> GenericImage {
+        GenericImage::new("mongo", "latest")
+            .with_env_var("MONGO_INITDB_DATABASE", "showcase_test")
+            .with_exposed_port(27017)
+    }
+}
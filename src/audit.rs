@@ -0,0 +1,179 @@
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::user::User;
+
+/// Kind of change made to a user.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+    Restore
+}
+
+/// Who made a change and within which request.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct AuditContext {
+    /// Token subject, API key or `cli`.
+    pub actor: String,
+    pub request_id: Option<String>
+}
+
+/// Change of a single top-level user field. Missing values mean the field did not exist.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>
+}
+
+/// Audit record as stored in the `audit` collection.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct AuditEntry {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub user_id: String,
+    pub action: AuditAction,
+    pub actor: String,
+    pub request_id: Option<String>,
+    pub timestamp: DateTime,
+    pub changes: Vec<FieldChange>
+}
+
+/// Audit record as shown by the REST apis. Timestamp is RFC 3339.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct AuditEntryInfo {
+    pub user_id: String,
+    pub action: AuditAction,
+    pub actor: String,
+    pub request_id: Option<String>,
+    pub timestamp: String,
+    pub changes: Vec<FieldChange>
+}
+
+impl AuditContext {
+
+    /// Context for changes made from the command line.
+    pub fn cli() -> Self {
+        AuditContext {
+            actor: "cli".to_string(),
+            request_id: None
+        }
+    }
+}
+
+impl AuditEntry {
+
+    /// Create an audit record of a change made now.
+    ///
+    /// ## Arguments.
+    /// * `action` - Kind of change.
+    /// * `user_id` - Id of the changed user.
+    /// * `context` - Who made the change.
+    /// * `changes` - Changed fields, see `diff_users`.
+    pub fn new(action: AuditAction, user_id: &str, context: &AuditContext, changes: Vec<FieldChange>) -> Self {
+        AuditEntry {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id: user_id.to_string(),
+            action,
+            actor: context.actor.clone(),
+            request_id: context.request_id.clone(),
+            timestamp: DateTime::now(),
+            changes
+        }
+    }
+
+    /// Public view of the record.
+    pub fn info(&self) -> AuditEntryInfo {
+        AuditEntryInfo {
+            user_id: self.user_id.clone(),
+            action: self.action,
+            actor: self.actor.clone(),
+            request_id: self.request_id.clone(),
+            timestamp: self.timestamp.try_to_rfc3339_string().unwrap_or_default(),
+            changes: self.changes.clone()
+        }
+    }
+}
+
+/// Change of the soft delete marker.
+///
+/// ## Arguments.
+/// * `before` - Deletion time before the change.
+/// * `after` - Deletion time after the change.
+pub fn deleted_at_change(before: Option<DateTime>, after: Option<DateTime>) -> Vec<FieldChange> {
+    let to_value = |time: DateTime| Value::String(time.try_to_rfc3339_string().unwrap_or_default());
+    vec![FieldChange {
+        field: "deleted_at".to_string(),
+        before: before.map(to_value),
+        after: after.map(to_value)
+    }]
+}
+
+/// Compare top-level fields of two users.
+///
+/// ## Arguments.
+/// * `before` - User before the change, `None` for new users.
+/// * `after` - User after the change.
+///
+/// ## Returns.
+/// Every field that differs.
+pub fn diff_users(before: Option<&User>, after: &User) -> Vec<FieldChange> {
+    let before_json = before.map(|user| serde_json::to_value(user).unwrap()).unwrap_or(Value::Null);
+    let after_json = serde_json::to_value(after).unwrap();
+
+    after_json.as_object().unwrap().iter()
+        .filter(|(field, after_value)| before_json.get(field.as_str()) != Some(*after_value))
+        .map(|(field, after_value)| FieldChange {
+            field: field.clone(),
+            before: before_json.get(field.as_str()).cloned(),
+            after: Some(after_value.clone())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diff_users_create() {
+        let user = User::_create_test_user(Some("101".to_string()));
+        let changes = diff_users(None, &user);
+
+        assert_eq!(8, changes.len());
+        assert!(changes.iter().all(|change| change.before.is_none() && change.after.is_some()));
+    }
+
+    #[test]
+    fn test_diff_users_update() {
+        let before = User::_create_test_user(Some("101".to_string()));
+        let mut after = before.clone();
+        after.name = "NEW NAME".to_string();
+        after.address.city = "Testburg".to_string();
+
+        let changes = diff_users(Some(&before), &after);
+        assert_eq!(2, changes.len());
+        assert!(changes.contains(&FieldChange {
+            field: "name".to_string(),
+            before: Some(Value::String("TESTER".to_string())),
+            after: Some(Value::String("NEW NAME".to_string()))
+        }));
+        assert!(changes.iter().any(|change| change.field == "address"));
+
+        assert!(diff_users(Some(&before), &before).is_empty());
+    }
+
+    #[test]
+    fn test_info() {
+        let context = AuditContext { actor: "TEST_SUBJECT".to_string(), request_id: Some("REQUEST".to_string()) };
+        let entry = AuditEntry::new(AuditAction::Update, "101", &context, vec![]);
+        let info = serde_json::to_value(entry.info()).unwrap();
+
+        assert_eq!("update", info["action"]);
+        assert_eq!("TEST_SUBJECT", info["actor"]);
+        assert_eq!("REQUEST", info["request_id"]);
+    }
+}
@@ -0,0 +1,647 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use actix_web::{HttpMessage, web};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{AUTHORIZATION, HeaderName};
+use actix_web::middleware::Next;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use jsonwebtoken::jwk::JwkSet;
+use log::{info, warn};
+use mongodb::bson::DateTime;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::sync::{Mutex, RwLock};
+use tracing::Span;
+use crate::api_error::ApiError;
+use crate::api_key_service::ApiKeyService;
+use crate::authorization::Principal;
+use crate::configuration::Authentication;
+use crate::metrics::Metrics;
+use crate::tls::ClientCertificate;
+
+/// Name of the signing key cache in metrics.
+const JWKS_CACHE: &str = "jwks";
+
+/// Header carrying an API key, as an alternative to `Authorization: ApiKey <key>`.
+pub const API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+/// Possible errors thrown by `authentication` functions.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum AuthError {
+    MissingToken,
+    InvalidToken(String),
+    /// API key is unknown, expired or revoked.
+    InvalidApiKey,
+    /// Signing keys could not be loaded.
+    KeysUnavailable(String),
+    ConfigurationError(String)
+}
+
+/// Claims of a validated access token.
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+pub struct Claims {
+    pub sub: String,
+    pub iss: String,
+    pub exp: u64,
+    /// All other claims, e.g. `aud`, `realm_access` or `resource_access`.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>
+}
+
+/// Where signing keys are loaded from.
+#[derive(Eq, PartialEq, Debug, Clone)]
+enum JwksSource {
+    Url(String),
+    File(PathBuf)
+}
+
+/// Signing keys with the time they were loaded.
+struct CachedKeys {
+    keys: JwkSet,
+    loaded_at: Option<Instant>
+}
+
+/// Cache for signing keys. Keys are reloaded when they get older than `ttl`, or when a token
+/// is signed with an unknown key id, which is how key rotation shows up.
+pub struct JwksCache {
+    source: JwksSource,
+    /// `ttl` and minimum time between reloads caused by unknown key ids. Changed when configuration is reloaded.
+    durations: std::sync::RwLock<(Duration, Duration)>,
+    state: RwLock<CachedKeys>,
+    /// Held while keys are reloaded, so that concurrent requests share a single reload.
+    reloading: Mutex<()>,
+    metrics: Arc<Metrics>
+}
+
+/// Validates bearer tokens against the configured issuer, audience and signing keys.
+pub struct Authenticator {
+    issuer: String,
+    audience: String,
+    leeway: u64,
+    role_claims: Vec<String>,
+    user_id_claim: String,
+    /// Roles of callers authenticated by a TLS client certificate alone. Empty means certificates
+    /// are not accepted as credentials.
+    client_certificate_roles: Vec<String>,
+    keys: JwksCache
+}
+
+/// Asymmetric algorithms accepted for access tokens.
+const ALLOWED_ALGORITHMS: [Algorithm; 8] = [
+    Algorithm::RS256, Algorithm::RS384, Algorithm::RS512,
+    Algorithm::PS256, Algorithm::PS384, Algorithm::PS512,
+    Algorithm::ES256, Algorithm::ES384,
+];
+
+impl JwksCache {
+
+    /// Create an empty cache. Keys are loaded on first use.
+    ///
+    /// ## Arguments.
+    /// * `source` - Where keys are loaded from.
+    /// * `ttl` - How long loaded keys are used before reloading.
+    /// * `min_refresh_interval` - Minimum time between reloads caused by unknown key ids.
+    fn new(source: JwksSource, ttl: Duration, min_refresh_interval: Duration) -> Self {
+        JwksCache {
+            source,
+            durations: std::sync::RwLock::new((ttl, min_refresh_interval)),
+            state: RwLock::new(CachedKeys { keys: JwkSet { keys: vec![] }, loaded_at: None }),
+            reloading: Mutex::new(()),
+            metrics: Arc::new(Metrics::new())
+        }
+    }
+
+    /// Find the decoding key for the given key id.
+    ///
+    /// ## Arguments.
+    /// * `kid` - Key id from the token header.
+    /// * `algorithm` - Algorithm from the token header.
+    ///
+    /// ## Returns.
+    /// A result containing the decoding key or an error.
+    async fn decoding_key(&self, kid: &str, algorithm: Algorithm) -> Result<DecodingKey, AuthError> {
+        let (ttl, min_refresh_interval) = *self.durations.read().unwrap();
+
+        // Reload expired keys.
+        let loaded_at = self.state.read().await.loaded_at;
+        let expired = loaded_at.is_none_or(|loaded_at| loaded_at.elapsed() >= ttl);
+        if expired {
+            self.reload_once(loaded_at).await?;
+        }
+
+        if let Some(key) = self.find(kid, algorithm).await {
+            self.metrics.record_cache(JWKS_CACHE, !expired);
+            return key
+        }
+        self.metrics.record_cache(JWKS_CACHE, false);
+
+        // Unknown key id. The keys may have been rotated, so reload once if allowed.
+        let loaded_at = self.state.read().await.loaded_at;
+        let can_refresh = loaded_at.is_none_or(|loaded_at| loaded_at.elapsed() >= min_refresh_interval);
+        if can_refresh {
+            info!("Unknown signing key id {kid}, reloading keys.");
+            self.reload_once(loaded_at).await?;
+            if let Some(key) = self.find(kid, algorithm).await {
+                return key
+            }
+        }
+
+        Err(AuthError::InvalidToken(format!("Unknown signing key id {kid}.")))
+    }
+
+    /// Look up a key from the currently cached keys.
+    async fn find(&self, kid: &str, algorithm: Algorithm) -> Option<Result<DecodingKey, AuthError>> {
+        let state = self.state.read().await;
+        let jwk = state.keys.find(kid)?;
+
+        // The key may restrict which algorithm it is used with.
+        if let Some(key_algorithm) = jwk.common.key_algorithm {
+            if key_algorithm.to_string() != format!("{algorithm:?}") {
+                return Some(Err(AuthError::InvalidToken("Token algorithm does not match signing key.".to_string())))
+            }
+        }
+
+        Some(DecodingKey::from_jwk(jwk).map_err(|e| AuthError::KeysUnavailable(e.to_string())))
+    }
+
+    /// Reload keys, unless another request reloaded them while this one waited for its turn.
+    ///
+    /// ## Arguments.
+    /// * `loaded_at` - Load time of the keys this request found stale.
+    async fn reload_once(&self, loaded_at: Option<Instant>) -> Result<(), AuthError> {
+        let _reloading = self.reloading.lock().await;
+        if self.state.read().await.loaded_at != loaded_at {
+            return Ok(())
+        }
+        self.reload().await
+    }
+
+    /// Load keys from the source and replace the cached ones.
+    async fn reload(&self) -> Result<(), AuthError> {
+        let keys = match &self.source {
+            JwksSource::Url(url) => {
+                let response = reqwest::get(url).await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| AuthError::KeysUnavailable(e.to_string()))?
+                ;
+                let content = response.bytes().await.map_err(|e| AuthError::KeysUnavailable(e.to_string()))?;
+                serde_json::from_slice::<JwkSet>(&content).map_err(|e| AuthError::KeysUnavailable(e.to_string()))?
+            },
+            JwksSource::File(path) => {
+                let content = tokio::fs::read(path).await.map_err(|e| AuthError::KeysUnavailable(e.to_string()))?;
+                serde_json::from_slice::<JwkSet>(&content).map_err(|e| AuthError::KeysUnavailable(e.to_string()))?
+            }
+        };
+
+        let mut state = self.state.write().await;
+        state.keys = keys;
+        state.loaded_at = Some(Instant::now());
+        Ok(())
+    }
+}
+
+impl Authenticator {
+
+    /// Create authenticator from configuration.
+    ///
+    /// ## Arguments.
+    /// * `config` - Authentication configuration.
+    pub fn new(config: &Authentication) -> Result<Self, AuthError> {
+        let source = match (&config.jwks_url, &config.jwks_file) {
+            (Some(url), _) => JwksSource::Url(url.clone()),
+            (None, Some(path)) => JwksSource::File(PathBuf::from(path)),
+            (None, None) => return Err(AuthError::ConfigurationError("Either jwks_url or jwks_file is required.".to_string()))
+        };
+
+        Ok(Authenticator {
+            issuer: config.issuer.clone(),
+            audience: config.audience.clone(),
+            leeway: config.leeway_seconds,
+            role_claims: config.role_claims.clone(),
+            user_id_claim: config.user_id_claim.clone(),
+            client_certificate_roles: vec![],
+            keys: JwksCache::new(
+                source,
+                Duration::from_secs(config.jwks_cache_seconds),
+                Duration::from_secs(config.jwks_min_refresh_seconds)
+            )
+        })
+    }
+
+    /// Accept verified TLS client certificates as credentials.
+    ///
+    /// ## Arguments.
+    /// * `roles` - Roles granted to callers authenticated by a certificate alone.
+    pub fn with_client_certificate_roles(mut self, roles: Vec<String>) -> Self {
+        self.client_certificate_roles = roles;
+        self
+    }
+
+    /// Record signing key cache hits and misses in shared metrics.
+    ///
+    /// ## Arguments.
+    /// * `metrics` - Metrics of the service.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.keys.metrics = metrics;
+        self
+    }
+
+    /// Change how long signing keys are cached, e.g. after configuration was reloaded.
+    ///
+    /// ## Arguments.
+    /// * `config` - Authentication configuration. Only the cache durations are used.
+    pub fn set_cache_durations(&self, config: &Authentication) {
+        *self.keys.durations.write().unwrap() = (
+            Duration::from_secs(config.jwks_cache_seconds),
+            Duration::from_secs(config.jwks_min_refresh_seconds)
+        );
+    }
+
+    /// Validate a bearer token.
+    ///
+    /// ## Arguments.
+    /// * `token` - Encoded JWT.
+    ///
+    /// ## Returns.
+    /// A result containing the token claims or an error.
+    pub async fn authenticate(&self, token: &str) -> Result<Claims, AuthError> {
+        let header = jsonwebtoken::decode_header(token).map_err(|e| AuthError::InvalidToken(e.to_string()))?;
+
+        if !ALLOWED_ALGORITHMS.contains(&header.alg) {
+            return Err(AuthError::InvalidToken(format!("Algorithm {:?} is not allowed.", header.alg)))
+        }
+        let kid = header.kid.ok_or(AuthError::InvalidToken("Token has no key id.".to_string()))?;
+        let key = self.keys.decoding_key(&kid, header.alg).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.leeway;
+        validation.validate_nbf = true;
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        jsonwebtoken::decode::<Claims>(token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| AuthError::InvalidToken(e.to_string()))
+    }
+}
+
+impl From<AuthError> for ApiError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::MissingToken => ApiError::Unauthorized("Missing bearer token.".to_string()),
+            AuthError::InvalidToken(detail) => ApiError::Unauthorized(format!("Invalid bearer token: {detail}")),
+            AuthError::InvalidApiKey => ApiError::Unauthorized("Unknown, expired or revoked API key.".to_string()),
+            AuthError::KeysUnavailable(_) | AuthError::ConfigurationError(_) => ApiError::Internal("Could not validate bearer token.".to_string())
+        }
+    }
+}
+
+/// Read the token from an `Authorization: Bearer <token>` header.
+fn bearer_token(req: &ServiceRequest) -> Option<String> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = header.split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") && !token.trim().is_empty() {
+        Some(token.trim().to_string())
+    } else {
+        None
+    }
+}
+
+/// Read the key from an `Authorization: ApiKey <key>` or `X-Api-Key: <key>` header.
+fn api_key(req: &ServiceRequest) -> Option<String> {
+    let from_authorization = req.headers().get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("apikey"))
+        .map(|(_, key)| key)
+    ;
+    let from_header = || req.headers().get(API_KEY_HEADER).and_then(|header| header.to_str().ok());
+
+    from_authorization.or_else(from_header)
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(str::to_string)
+}
+
+/// Store the principal in the request extensions and add the caller to the request span.
+///
+/// ## Arguments.
+/// * `req` - Authenticated request.
+/// * `principal` - Caller of the request.
+fn authenticated(req: &ServiceRequest, principal: Principal) {
+    Span::current().record("user_id", principal.user_id.as_deref().unwrap_or(&principal.subject));
+    req.extensions_mut().insert(principal);
+}
+
+/// Middleware requiring a valid bearer token, API key or, when enabled, TLS client certificate.
+/// The resulting `Principal` is stored in the request extensions, along with the validated `Claims`
+/// for bearer tokens. It carries the client certificate of the connection, if any.
+/// Requests pass through untouched when no `Authenticator` is registered as app data.
+pub async fn authenticate(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let Some(authenticator) = req.app_data::<web::Data<Authenticator>>().cloned() else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body)
+    };
+    let client_certificate = req.conn_data::<ClientCertificate>().cloned();
+
+    if let Some(key) = api_key(&req) {
+        // No key is valid without a registered `ApiKeyService`.
+        let verified = match req.app_data::<web::Data<ApiKeyService>>().cloned() {
+            Some(api_keys) => api_keys.verify_api_key(&key, DateTime::now()).await,
+            None => Ok(None)
+        };
+        return match verified {
+            Ok(Some(api_key)) => {
+                info!("Authenticated with API key {}.", api_key.id);
+                authenticated(&req, Principal { client_certificate, ..api_key.principal() });
+                next.call(req).await.map(ServiceResponse::map_into_boxed_body)
+            },
+            Ok(None) => {
+                warn!("Authentication failed: {:?}.", AuthError::InvalidApiKey);
+                Ok(req.error_response(ApiError::from(AuthError::InvalidApiKey)))
+            },
+            Err(e) => {
+                warn!("Could not verify API key: {e:?}.");
+                Ok(req.error_response(ApiError::from(e)))
+            }
+        }
+    }
+
+    let token = bearer_token(&req);
+    if token.is_none() && !authenticator.client_certificate_roles.is_empty() {
+        if let Some(client_certificate) = client_certificate {
+            info!("Authenticated with client certificate {}.", client_certificate.subject);
+            authenticated(&req, Principal::from_client_certificate(client_certificate, &authenticator.client_certificate_roles));
+            return next.call(req).await.map(ServiceResponse::map_into_boxed_body)
+        }
+    }
+
+    let result = match token {
+        Some(token) => authenticator.authenticate(&token).await,
+        None => Err(AuthError::MissingToken)
+    };
+
+    match result {
+        Ok(claims) => {
+            let principal = Principal::from_claims(&claims, &authenticator.role_claims, &authenticator.user_id_claim);
+            authenticated(&req, Principal { client_certificate, ..principal });
+            req.extensions_mut().insert(claims);
+            next.call(req).await.map(ServiceResponse::map_into_boxed_body)
+        },
+        Err(e) => {
+            warn!("Authentication failed: {e:?}.");
+            Ok(req.error_response(ApiError::from(e)))
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use actix_web::{App, get, HttpResponse};
+    use actix_web::http::StatusCode;
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use httpmock::Method::GET;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+    use super::*;
+
+    pub const ISSUER: &str = "http://localhost/realms/showcase";
+    pub const AUDIENCE: &str = "showcase-api";
+
+    /// Sign a token with the local test key.
+    ///
+    /// ## Arguments.
+    /// * `claims` - Claims of the token.
+    pub fn sign_token(claims: &Value) -> String {
+        sign_token_with_key(claims, "test-key-1", "testdata/auth/test_private_key.pem")
+    }
+
+    fn sign_token_with_key(claims: &Value, kid: &str, key_path: &str) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+        let key = EncodingKey::from_rsa_pem(&std::fs::read(key_path).unwrap()).unwrap();
+        jsonwebtoken::encode(&header, claims, &key).unwrap()
+    }
+
+    /// Valid claims for the test issuer and audience.
+    pub fn valid_claims() -> Value {
+        json!({
+            "sub": "TEST_SUBJECT",
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "exp": now() + 300
+        })
+    }
+
+    /// Authentication configuration using the local test keys.
+    pub fn test_config() -> Authentication {
+        Authentication {
+            issuer: ISSUER.to_string(),
+            audience: AUDIENCE.to_string(),
+            jwks_url: None,
+            jwks_file: Some("testdata/auth/jwks.json".to_string()),
+            leeway_seconds: 30,
+            jwks_cache_seconds: 300,
+            jwks_min_refresh_seconds: 0,
+            role_claims: vec!["realm_access.roles".to_string()],
+            user_id_claim: "sub".to_string()
+        }
+    }
+
+    fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    #[get("/protected")]
+    async fn protected(req: actix_web::HttpRequest) -> HttpResponse {
+        HttpResponse::Ok().body(req.extensions().get::<Claims>().unwrap().sub.clone())
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_success() {
+        let authenticator = Authenticator::new(&test_config()).unwrap();
+
+        let claims = authenticator.authenticate(&sign_token(&valid_claims())).await.unwrap();
+        assert_eq!("TEST_SUBJECT", claims.sub);
+        assert_eq!(json!(AUDIENCE), claims.extra["aud"]);
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_expired() {
+        let authenticator = Authenticator::new(&test_config()).unwrap();
+
+        let mut claims = valid_claims();
+        claims["exp"] = json!(now() - 60);
+        assert!(authenticator.authenticate(&sign_token(&claims)).await.is_err());
+
+        // Small clock skew is tolerated.
+        claims["exp"] = json!(now() - 10);
+        assert!(authenticator.authenticate(&sign_token(&claims)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_wrong_issuer_and_audience() {
+        let authenticator = Authenticator::new(&test_config()).unwrap();
+
+        let mut claims = valid_claims();
+        claims["iss"] = json!("http://localhost/realms/other");
+        assert!(authenticator.authenticate(&sign_token(&claims)).await.is_err());
+
+        let mut claims = valid_claims();
+        claims["aud"] = json!(["other-api"]);
+        assert!(authenticator.authenticate(&sign_token(&claims)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_unknown_key() {
+        let authenticator = Authenticator::new(&test_config()).unwrap();
+
+        let token = sign_token_with_key(&valid_claims(), "test-key-2", "testdata/auth/test_rotated_private_key.pem");
+        assert_eq!(
+            Err(AuthError::InvalidToken("Unknown signing key id test-key-2.".to_string())),
+            authenticator.authenticate(&token).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_forged_signature() {
+        let authenticator = Authenticator::new(&test_config()).unwrap();
+
+        // Signed with another private key, but claims the id of the trusted key.
+        let token = sign_token_with_key(&valid_claims(), "test-key-1", "testdata/auth/test_rotated_private_key.pem");
+        assert!(authenticator.authenticate(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_key_rotation() {
+        let path = std::env::temp_dir().join(format!("jwks-{}.json", uuid::Uuid::new_v4()));
+        std::fs::copy("testdata/auth/jwks.json", &path).unwrap();
+        let mut config = test_config();
+        config.jwks_file = Some(path.to_string_lossy().to_string());
+        let authenticator = Authenticator::new(&config).unwrap();
+
+        assert!(authenticator.authenticate(&sign_token(&valid_claims())).await.is_ok());
+
+        // Rotate keys. Tokens signed with the new key are accepted after a reload.
+        std::fs::copy("testdata/auth/jwks_rotated.json", &path).unwrap();
+        let token = sign_token_with_key(&valid_claims(), "test-key-2", "testdata/auth/test_rotated_private_key.pem");
+        assert!(authenticator.authenticate(&token).await.is_ok());
+        assert!(authenticator.authenticate(&sign_token(&valid_claims())).await.is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_jwks_url_is_cached() {
+        let mock_server = httpmock::MockServer::start();
+        let jwks_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/certs");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("testdata/auth/jwks.json");
+        });
+
+        let mut config = test_config();
+        config.jwks_url = Some(mock_server.url("/certs"));
+        let metrics = Arc::new(Metrics::new());
+        let authenticator = Authenticator::new(&config).unwrap().with_metrics(metrics.clone());
+
+        assert!(authenticator.authenticate(&sign_token(&valid_claims())).await.is_ok());
+        assert!(authenticator.authenticate(&sign_token(&valid_claims())).await.is_ok());
+        jwks_mock.assert_hits(1);
+        let text = metrics.encode();
+        assert!(text.contains("cache_requests_total{cache=\"jwks\",result=\"hit\"} 1"));
+        assert!(text.contains("cache_requests_total{cache=\"jwks\",result=\"miss\"} 1"));
+
+        // Keys expire right away once caching is turned off.
+        config.jwks_cache_seconds = 0;
+        authenticator.set_cache_durations(&config);
+        assert!(authenticator.authenticate(&sign_token(&valid_claims())).await.is_ok());
+        jwks_mock.assert_hits(2);
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_concurrent_requests_reload_once() {
+        let mock_server = httpmock::MockServer::start();
+        let jwks_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/certs");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("testdata/auth/jwks.json")
+                .delay(Duration::from_millis(100));
+        });
+
+        let mut config = test_config();
+        config.jwks_url = Some(mock_server.url("/certs"));
+        let authenticator = Authenticator::new(&config).unwrap();
+
+        // Keys are loaded once for a burst of requests on a cold cache.
+        let token = sign_token(&valid_claims());
+        let results = futures::future::join_all((0..10).map(|_| authenticator.authenticate(&token))).await;
+        assert!(results.iter().all(Result::is_ok));
+        jwks_mock.assert_hits(1);
+
+        // And reloaded once for a burst of tokens signed with an unknown key.
+        let token = sign_token_with_key(&valid_claims(), "test-key-2", "testdata/auth/test_rotated_private_key.pem");
+        let results = futures::future::join_all((0..10).map(|_| authenticator.authenticate(&token))).await;
+        assert!(results.iter().all(Result::is_err));
+        jwks_mock.assert_hits(2);
+    }
+
+    #[tokio::test]
+    async fn test_authenticator_missing_key_source() {
+        let mut config = test_config();
+        config.jwks_file = None;
+
+        assert!(Authenticator::new(&config).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_middleware() {
+        let authenticator = web::Data::new(Authenticator::new(&test_config()).unwrap());
+        let app = init_service(
+            App::new()
+                .app_data(authenticator)
+                .wrap(from_fn(authenticate))
+                .service(protected)
+        ).await;
+
+        let req = TestRequest::get().uri("/protected").to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+        assert!(response.headers().contains_key("www-authenticate"));
+
+        let req = TestRequest::get()
+            .uri("/protected")
+            .insert_header((AUTHORIZATION, "Bearer NOT_A_TOKEN"))
+            .to_request();
+        assert_eq!(StatusCode::UNAUTHORIZED, call_service(&app, req).await.status());
+
+        let req = TestRequest::get()
+            .uri("/protected")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", sign_token(&valid_claims()))))
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("TEST_SUBJECT", actix_web::test::read_body(response).await);
+    }
+
+    #[test]
+    fn test_api_key_headers() {
+        let req = TestRequest::get().insert_header((AUTHORIZATION, "ApiKey shk_TEST")).to_srv_request();
+        assert_eq!(Some("shk_TEST".to_string()), api_key(&req));
+
+        let req = TestRequest::get().insert_header((API_KEY_HEADER, "shk_TEST")).to_srv_request();
+        assert_eq!(Some("shk_TEST".to_string()), api_key(&req));
+
+        let req = TestRequest::get().insert_header((AUTHORIZATION, "Bearer TOKEN")).to_srv_request();
+        assert_eq!(None, api_key(&req));
+
+        let req = TestRequest::get().insert_header((API_KEY_HEADER, " ")).to_srv_request();
+        assert_eq!(None, api_key(&req));
+    }
+}
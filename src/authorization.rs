@@ -0,0 +1,273 @@
+use std::collections::HashSet;
+use std::future::{Ready, ready};
+use std::marker::PhantomData;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, web};
+use actix_web::dev::Payload;
+use serde_json::Value;
+use crate::api_error::ApiError;
+use crate::authentication::{Authenticator, Claims};
+use crate::tls::ClientCertificate;
+
+/// The caller of a request.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Principal {
+    pub subject: String,
+    pub roles: HashSet<String>,
+    /// Id of the caller's own user, if the token maps to one.
+    pub user_id: Option<String>,
+    /// Verified TLS client certificate of the connection, when mutual TLS is in use.
+    pub client_certificate: Option<ClientCertificate>,
+    /// Set when authentication is disabled. Every role requirement is met.
+    pub unrestricted: bool
+}
+
+/// Roles required by an operation. The caller needs at least one of them.
+pub trait RoleRequirement {
+    const ROLES: &'static [&'static str];
+}
+
+/// Extractor that only succeeds when the caller has one of the roles in `R`.
+///
+/// Responds with 401 when there is no authenticated caller and 403 when the caller lacks the roles.
+pub struct Authorized<R: RoleRequirement> {
+    pub principal: Principal,
+    requirement: PhantomData<R>
+}
+
+impl Principal {
+
+    /// Build principal from token claims.
+    ///
+    /// ## Arguments.
+    /// * `claims` - Validated token claims.
+    /// * `role_claims` - Dotted paths to role arrays in the claims, e.g. `realm_access.roles`.
+    /// * `user_id_claim` - Dotted path to the caller's user id, e.g. `sub`.
+    pub fn from_claims(claims: &Claims, role_claims: &[String], user_id_claim: &str) -> Self {
+        let mut claims_json = Value::Object(claims.extra.clone());
+        claims_json["sub"] = Value::String(claims.sub.clone());
+
+        let roles = role_claims.iter()
+            .filter_map(|path| {
+                path.split('.').try_fold(&claims_json, |value, key| value.get(key))
+            })
+            .filter_map(Value::as_array)
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect()
+        ;
+
+        // User ids are strings but a numeric claim is accepted as well.
+        let user_id = user_id_claim.split('.')
+            .try_fold(&claims_json, |value, key| value.get(key))
+            .and_then(|value| match value {
+                Value::String(id) => Some(id.clone()),
+                Value::Number(id) => Some(id.to_string()),
+                _ => None
+            })
+        ;
+
+        Principal {
+            subject: claims.sub.clone(),
+            roles,
+            user_id,
+            client_certificate: None,
+            unrestricted: false
+        }
+    }
+
+    /// Principal used when authentication is disabled.
+    pub fn unrestricted() -> Self {
+        Principal {
+            subject: "anonymous".to_string(),
+            roles: HashSet::new(),
+            user_id: None,
+            client_certificate: None,
+            unrestricted: true
+        }
+    }
+
+    /// Build principal for a caller authenticated by a client certificate alone.
+    ///
+    /// ## Arguments.
+    /// * `certificate` - Verified client certificate.
+    /// * `roles` - Roles granted to certificate callers.
+    pub fn from_client_certificate(certificate: ClientCertificate, roles: &[String]) -> Self {
+        Principal {
+            subject: format!("cert:{}", certificate.subject),
+            roles: roles.iter().cloned().collect(),
+            user_id: None,
+            client_certificate: Some(certificate),
+            unrestricted: false
+        }
+    }
+
+    /// Check if the principal has at least one of the given roles.
+    ///
+    /// ## Arguments.
+    /// * `roles` - Accepted roles.
+    pub fn has_any_role(&self, roles: &[&str]) -> bool {
+        self.unrestricted || roles.iter().any(|role| self.roles.contains(*role))
+    }
+
+    /// Check if the given user id belongs to the principal.
+    ///
+    /// ## Arguments.
+    /// * `user_id` - Id of a user.
+    pub fn is_self(&self, user_id: Option<&str>) -> bool {
+        user_id.is_some() && self.user_id.as_deref() == user_id
+    }
+}
+
+/// Get the caller of the request.
+///
+/// ## Returns.
+/// The principal stored by the authentication middleware, an unrestricted principal if no `Authenticator`
+/// is registered, which only happens when authentication is disabled explicitly, or `ApiError::Unauthorized`.
+pub fn principal(req: &HttpRequest) -> Result<Principal, ApiError> {
+    if let Some(principal) = req.extensions().get::<Principal>() {
+        return Ok(principal.clone())
+    }
+
+    if req.app_data::<web::Data<Authenticator>>().is_none() {
+        return Ok(Principal {
+            client_certificate: req.conn_data::<ClientCertificate>().cloned(),
+            ..Principal::unrestricted()
+        })
+    }
+
+    Err(ApiError::Unauthorized("Missing bearer token.".to_string()))
+}
+
+impl<R: RoleRequirement> FromRequest for Authorized<R> {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, ApiError>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(principal(req).and_then(|principal| {
+            if principal.has_any_role(R::ROLES) {
+                Ok(Authorized { principal, requirement: PhantomData })
+            } else {
+                Err(ApiError::Forbidden(format!("One of the roles {} is required.", R::ROLES.join(", "))))
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{App, get, HttpResponse};
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use serde_json::json;
+    use super::*;
+    use crate::authentication;
+
+    struct AdminOnly;
+
+    impl RoleRequirement for AdminOnly {
+        const ROLES: &'static [&'static str] = &["admin"];
+    }
+
+    #[get("/admin")]
+    async fn admin_only(_authorized: Authorized<AdminOnly>) -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    fn claims(extra: Value) -> Claims {
+        Claims {
+            sub: "TEST_SUBJECT".to_string(),
+            iss: "TEST_ISSUER".to_string(),
+            exp: 0,
+            extra: extra.as_object().unwrap().clone()
+        }
+    }
+
+    #[test]
+    fn test_from_claims() {
+        let claims = claims(json!({
+            "realm_access": { "roles": ["user", "offline_access"] },
+            "resource_access": { "showcase-api": { "roles": ["admin"] } }
+        }));
+
+        let principal = Principal::from_claims(&claims, &["realm_access.roles".to_string()], "sub");
+        assert_eq!(HashSet::from(["user".to_string(), "offline_access".to_string()]), principal.roles);
+        assert!(!principal.has_any_role(&["admin"]));
+
+        let principal = Principal::from_claims(
+            &claims,
+            &["realm_access.roles".to_string(), "resource_access.showcase-api.roles".to_string()],
+            "sub"
+        );
+        assert!(principal.has_any_role(&["admin"]));
+    }
+
+    #[test]
+    fn test_from_claims_missing_roles() {
+        let principal = Principal::from_claims(&claims(json!({ "realm_access": {} })), &["realm_access.roles".to_string()], "sub");
+        assert!(principal.roles.is_empty());
+    }
+
+    #[test]
+    fn test_from_claims_user_id() {
+        let claims = claims(json!({ "showcase": { "user_id": 7 } }));
+
+        let principal = Principal::from_claims(&claims, &[], "sub");
+        assert!(principal.is_self(Some("TEST_SUBJECT")));
+
+        let principal = Principal::from_claims(&claims, &[], "showcase.user_id");
+        assert!(principal.is_self(Some("7")));
+        assert!(!principal.is_self(Some("8")));
+
+        let principal = Principal::from_claims(&claims, &[], "missing");
+        assert!(!principal.is_self(None));
+    }
+
+    #[test]
+    fn test_unrestricted() {
+        assert!(Principal::unrestricted().has_any_role(&["admin"]));
+    }
+
+    #[actix_web::test]
+    async fn test_authorized_without_authentication() {
+        let app = init_service(App::new().service(admin_only)).await;
+
+        let req = TestRequest::get().uri("/admin").to_request();
+        assert_eq!(StatusCode::OK, call_service(&app, req).await.status());
+    }
+
+    #[actix_web::test]
+    async fn test_authorized_missing_principal() {
+        let authenticator = web::Data::new(Authenticator::new(&authentication::test::test_config()).unwrap());
+        let app = init_service(App::new().app_data(authenticator).service(admin_only)).await;
+
+        let req = TestRequest::get().uri("/admin").to_request();
+        assert_eq!(StatusCode::UNAUTHORIZED, call_service(&app, req).await.status());
+    }
+
+    #[actix_web::test]
+    async fn test_authorized_roles() {
+        let authenticator = web::Data::new(Authenticator::new(&authentication::test::test_config()).unwrap());
+        let app = init_service(
+            App::new()
+                .app_data(authenticator)
+                .wrap(actix_web::middleware::from_fn(authentication::authenticate))
+                .service(admin_only)
+        ).await;
+
+        let mut claims = authentication::test::valid_claims();
+        claims["realm_access"] = json!({ "roles": ["user"] });
+        let req = TestRequest::get()
+            .uri("/admin")
+            .insert_header(("Authorization", format!("Bearer {}", authentication::test::sign_token(&claims))))
+            .to_request();
+        assert_eq!(StatusCode::FORBIDDEN, call_service(&app, req).await.status());
+
+        claims["realm_access"] = json!({ "roles": ["admin"] });
+        let req = TestRequest::get()
+            .uri("/admin")
+            .insert_header(("Authorization", format!("Bearer {}", authentication::test::sign_token(&claims))))
+            .to_request();
+        assert_eq!(StatusCode::OK, call_service(&app, req).await.status());
+    }
+}
@@ -0,0 +1,90 @@
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use crate::configuration::ConfigSources;
+
+/// Command line interface. Without a subcommand the REST api is started.
+#[derive(Parser, Debug)]
+#[command(version, about = "Backend showcase with JsonPlaceholder")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Configuration file. Values can be overridden with `APP__` environment variables,
+    /// e.g. `APP__DATABASE__URL`, and with the flags below.
+    #[arg(long, global = true, default_value = "resources/config.toml")]
+    pub config: String,
+
+    /// Address to listen on, overrides `server.host`.
+    #[arg(long, global = true)]
+    pub host: Option<String>,
+
+    /// Port to listen on, overrides `server.port`.
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+
+    /// Worker threads, overrides `server.workers`.
+    #[arg(long, global = true)]
+    pub workers: Option<usize>,
+
+    /// Override any configuration key, e.g. `--set database.database_name=showcase`.
+    #[arg(long = "set", value_name = "KEY=VALUE", global = true, value_parser = parse_override)]
+    pub overrides: Vec<(String, String)>
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Import users from a JSON array, NDJSON or CSV file.
+    Import {
+        /// File to import.
+        path: PathBuf,
+
+        /// Input format: json, ndjson or csv. Guessed from the file extension when missing.
+        #[arg(long)]
+        format: Option<String>,
+
+        /// Save nothing unless every row can be imported.
+        #[arg(long)]
+        all_or_nothing: bool
+    },
+
+    /// Inspect configuration.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the merged configuration with secrets masked and list every problem.
+    /// Exits with a failure if there are any.
+    Check
+}
+
+impl Cli {
+
+    /// Configuration sources selected on the command line, with the process environment.
+    pub fn config_sources(&self) -> ConfigSources {
+        let mut overrides = self.overrides.clone();
+        let flags = [
+            ("server.host", self.host.clone()),
+            ("server.port", self.port.map(|port| port.to_string())),
+            ("server.workers", self.workers.map(|workers| workers.to_string()))
+        ];
+        overrides.extend(flags.into_iter().filter_map(|(key, value)| value.map(|value| (key.to_string(), value))));
+
+        ConfigSources {
+            path: self.config.clone(),
+            env: std::env::vars().collect(),
+            overrides
+        }
+    }
+}
+
+/// Parse a `KEY=VALUE` override.
+fn parse_override(value: &str) -> Result<(String, String), String> {
+    value.split_once('=')
+        .filter(|(key, _)| !key.trim().is_empty())
+        .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+        .ok_or(format!("expected KEY=VALUE, got `{value}`"))
+}
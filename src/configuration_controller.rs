@@ -0,0 +1,86 @@
+use actix_web::{get, HttpRequest, HttpResponse, post, web};
+use actix_web::http::StatusCode;
+use log::{info, warn};
+use crate::api_error::ApiError;
+use crate::api_key_controller::Admin;
+use crate::authorization::Authorized;
+use crate::configuration_reload::{ConfigurationHandle, ReloadTrigger};
+use crate::content_negotiation;
+use crate::user_controller::{negotiate_response_format, respond_with};
+
+#[get("/admin/config")]
+pub async fn get_config_status(
+    authorized: Authorized<Admin>,
+    req: HttpRequest,
+    handle: web::Data<ConfigurationHandle>
+) -> Result<HttpResponse, ApiError> {
+    info!("Incoming request for configuration status from {}.", authorized.principal.subject);
+    let format = negotiate_response_format(&req)?;
+
+    let status = handle.status();
+
+    info!("Configuration is at version {}. Responding with 200.", status.version);
+    respond_with(HttpResponse::Ok(), format, content_negotiation::serialize(format, "config_status", &status))
+}
+
+#[post("/admin/config:reload")]
+pub async fn reload_config(
+    authorized: Authorized<Admin>,
+    req: HttpRequest,
+    handle: web::Data<ConfigurationHandle>
+) -> Result<HttpResponse, ApiError> {
+    info!("Incoming request to reload configuration from {}.", authorized.principal.subject);
+    let format = negotiate_response_format(&req)?;
+
+    let handle = handle.into_inner();
+    let result = web::block(move || handle.reload(ReloadTrigger::Api)).await.map_err(|e| {
+        warn!("Configuration reload did not finish: {e}.");
+        ApiError::Internal("Configuration reload did not finish.".to_string())
+    })?;
+
+    if !result.success {
+        warn!("Invalid configuration. Responding with 422.");
+        return Err(ApiError::Http(StatusCode::UNPROCESSABLE_ENTITY, result.errors.join(" ")))
+    }
+
+    info!("Configuration reloaded. Responding with 200.");
+    respond_with(HttpResponse::Ok(), format, content_negotiation::serialize(format, "reload_result", &result))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use actix_web::App;
+    use actix_web::test::{call_and_read_body_json, call_service, init_service, TestRequest};
+    use serde_json::Value;
+    use crate::configuration::{ConfigSources, Configuration};
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_reload_config() {
+        let path = std::env::temp_dir().join(format!("config-{}.toml", uuid::Uuid::new_v4()));
+        fs::copy("resources/test/config.toml", &path).unwrap();
+        let sources = ConfigSources { path: path.to_string_lossy().to_string(), ..ConfigSources::default() };
+        let handle = web::Data::new(ConfigurationHandle::new(Configuration::read_from_config_file(&sources).unwrap(), sources));
+        let app = init_service(App::new().app_data(handle.clone()).service(get_config_status).service(reload_config)).await;
+
+        let status: Value = call_and_read_body_json(&app, TestRequest::get().uri("/admin/config").to_request()).await;
+        assert_eq!(1, status["version"]);
+        assert_eq!(Value::Null, status["last_reload"]);
+
+        let result: Value = call_and_read_body_json(&app, TestRequest::post().uri("/admin/config:reload").to_request()).await;
+        assert_eq!(true, result["success"]);
+        assert_eq!("api", result["trigger"]);
+
+        fs::write(&path, "[json_placeholder]\nurl = \"not a url\"\n").unwrap();
+        let response = call_service(&app, TestRequest::post().uri("/admin/config:reload").to_request()).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+
+        let status: Value = call_and_read_body_json(&app, TestRequest::get().uri("/admin/config").to_request()).await;
+        assert_eq!(2, status["version"]);
+        assert_eq!(false, status["last_reload"]["success"]);
+        assert!(!status["last_reload"]["errors"].as_array().unwrap().is_empty());
+
+        fs::remove_file(path).unwrap();
+    }
+}
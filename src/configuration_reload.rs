@@ -0,0 +1,345 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use arc_swap::ArcSwap;
+use log::{info, warn};
+use mongodb::bson::DateTime;
+use serde::Serialize;
+use tokio::signal::unix::{signal, SignalKind};
+use crate::configuration::{ConfigSources, Configuration, ConfigurationError};
+
+/// Keys applied while running. Changes to other keys take effect after a restart.
+const RELOADABLE_KEYS: [&str; 10] = [
+    "json_placeholder", "logging", "redaction", "rate_limit.reads", "rate_limit.writes", "soft_delete",
+    "reload", "health", "authentication.jwks_cache_seconds", "authentication.jwks_min_refresh_seconds"
+];
+
+/// Called with the new configuration after every applied reload.
+type ReloadHook = Box<dyn Fn(&Configuration) + Send + Sync>;
+
+/// What started a reload.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReloadTrigger {
+    /// The configuration file changed.
+    File,
+    /// The process received `SIGHUP`.
+    Signal,
+    /// An admin asked for it.
+    Api
+}
+
+/// Outcome of a single reload.
+#[derive(Eq, PartialEq, Debug, Clone, Serialize)]
+pub struct ReloadResult {
+    pub trigger: ReloadTrigger,
+    /// RFC 3339 time of the reload.
+    pub at: String,
+    /// Whether the new configuration was applied.
+    pub success: bool,
+    /// Problems that kept the new configuration from being applied.
+    pub errors: Vec<String>,
+    /// Changed keys that take effect after a restart.
+    pub restart_required: Vec<String>
+}
+
+/// Version of the configuration in use and how the last reload went.
+#[derive(Eq, PartialEq, Debug, Clone, Serialize)]
+pub struct ReloadStatus {
+    /// Starts at 1 and grows with every applied reload.
+    pub version: u64,
+    /// RFC 3339 time the configuration in use was loaded.
+    pub loaded_at: String,
+    pub last_reload: Option<ReloadResult>
+}
+
+/// Shared handle to the configuration in use. Readers get the current configuration without
+/// blocking, reloads validate the new configuration before swapping it in.
+pub struct ConfigurationHandle {
+    sources: ConfigSources,
+    current: ArcSwap<Configuration>,
+    /// Also held during reloads, so that they run one at a time.
+    status: Mutex<ReloadStatus>,
+    hooks: Mutex<Vec<ReloadHook>>,
+    /// Modification time of the configuration file when last checked.
+    file_modified: Mutex<Option<SystemTime>>
+}
+
+impl ConfigurationHandle {
+
+    /// Create a handle for configuration loaded at startup.
+    ///
+    /// ## Arguments.
+    /// * `configuration` - Validated configuration.
+    /// * `sources` - Where `configuration` was read from. Reloads read the same sources.
+    pub fn new(configuration: Configuration, sources: ConfigSources) -> Self {
+        ConfigurationHandle {
+            file_modified: Mutex::new(modified(&sources.path)),
+            sources,
+            current: ArcSwap::from_pointee(configuration),
+            status: Mutex::new(ReloadStatus { version: 1, loaded_at: now(), last_reload: None }),
+            hooks: Mutex::new(vec![])
+        }
+    }
+
+    /// Configuration in use. Hold on to it for the duration of a request, so that every read sees the same version.
+    pub fn current(&self) -> Arc<Configuration> {
+        self.current.load_full()
+    }
+
+    /// Version of the configuration in use and how the last reload went.
+    pub fn status(&self) -> ReloadStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Run `hook` with the new configuration after every applied reload, e.g. to update limits held elsewhere.
+    ///
+    /// ## Arguments.
+    /// * `hook` - Applies the new configuration.
+    pub fn on_reload(&self, hook: impl Fn(&Configuration) + Send + Sync + 'static) {
+        self.hooks.lock().unwrap().push(Box::new(hook));
+    }
+
+    /// Read and validate the configuration sources again, and swap the result in if it is valid.
+    ///
+    /// ## Arguments.
+    /// * `trigger` - What started the reload.
+    ///
+    /// ## Returns.
+    /// Outcome of the reload. The configuration in use is kept when it failed.
+    pub fn reload(&self, trigger: ReloadTrigger) -> ReloadResult {
+        let mut status = self.status.lock().unwrap();
+
+        let result = match Configuration::read_from_config_file(&self.sources) {
+            Ok(configuration) => {
+                let restart_required = restart_required(&self.current(), &configuration);
+                self.current.store(Arc::new(configuration));
+                let configuration = self.current();
+                self.hooks.lock().unwrap().iter().for_each(|hook| hook(&configuration));
+
+                status.version += 1;
+                status.loaded_at = now();
+                info!("Reloaded configuration, now at version {}.", status.version);
+                if !restart_required.is_empty() {
+                    warn!("Changes to {} take effect after a restart.", restart_required.join(", "));
+                }
+                ReloadResult { trigger, at: status.loaded_at.clone(), success: true, errors: vec![], restart_required }
+            },
+            Err(e) => {
+                warn!("Could not reload configuration, keeping version {}. {e}", status.version);
+                let errors = match e {
+                    ConfigurationError::InvalidKeys(errors) => errors,
+                    e => vec![e.to_string()]
+                };
+                ReloadResult { trigger, at: now(), success: false, errors, restart_required: vec![] }
+            }
+        };
+
+        status.last_reload = Some(result.clone());
+        result
+    }
+
+    /// Reload if the configuration file was modified since the last check.
+    ///
+    /// ## Returns.
+    /// Outcome of the reload, or `None` if the file did not change.
+    pub fn reload_if_changed(&self) -> Option<ReloadResult> {
+        let modified = modified(&self.sources.path);
+        {
+            let mut file_modified = self.file_modified.lock().unwrap();
+            if modified.is_none() || *file_modified == modified {
+                return None
+            }
+            // Do not retry a broken file until it changes again.
+            *file_modified = modified;
+        }
+        Some(self.reload(ReloadTrigger::File))
+    }
+
+    /// Reload on `SIGHUP` and, if enabled, when the configuration file changes, until the server stops.
+    pub async fn watch(self: Arc<Self>) {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => Some(hangup),
+            Err(e) => {
+                warn!("Could not listen for SIGHUP, configuration is only reloaded on file changes: {e}");
+                None
+            }
+        };
+
+        loop {
+            // Read every time, so that changes to the interval apply too.
+            let reload = self.current().reload;
+            let handle = Arc::clone(&self);
+            // Reloads read files and wait for other reloads, which must not block the runtime.
+            let reloaded = tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(reload.watch_interval_seconds)) => {
+                    if !reload.watch_file {
+                        continue
+                    }
+                    tokio::task::spawn_blocking(move || { handle.reload_if_changed(); }).await
+                },
+                Some(()) = async { hangup.as_mut()?.recv().await } => {
+                    info!("Received SIGHUP, reloading configuration.");
+                    tokio::task::spawn_blocking(move || { handle.reload(ReloadTrigger::Signal); }).await
+                }
+            };
+            if let Err(e) = reloaded {
+                warn!("Configuration reload did not finish: {e}");
+            }
+        }
+    }
+}
+
+/// Current time in RFC 3339.
+fn now() -> String {
+    DateTime::now().try_to_rfc3339_string().unwrap_or_default()
+}
+
+/// Modification time of a file, `None` if it can't be read.
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Changed keys that are not applied while running.
+///
+/// ## Arguments.
+/// * `old` - Configuration in use.
+/// * `new` - Configuration about to be swapped in.
+fn restart_required(old: &Configuration, new: &Configuration) -> Vec<String> {
+    let old = toml::Value::try_from(old).unwrap();
+    let new = toml::Value::try_from(new).unwrap();
+    let mut changed = BTreeSet::new();
+    changed_keys("", Some(&old), Some(&new), &mut changed);
+
+    changed.into_iter()
+        .filter(|key| !RELOADABLE_KEYS.iter().any(|reloadable| key == reloadable || key.starts_with(&format!("{reloadable}."))))
+        .collect()
+}
+
+/// Collect dotted keys whose values differ. Tables present on one side only are reported as a whole.
+fn changed_keys(prefix: &str, old: Option<&toml::Value>, new: Option<&toml::Value>, changed: &mut BTreeSet<String>) {
+    match (old, new) {
+        (Some(toml::Value::Table(old)), Some(toml::Value::Table(new))) => {
+            for key in old.keys().chain(new.keys()) {
+                let path = if prefix.is_empty() { key.clone() } else { format!("{prefix}.{key}") };
+                changed_keys(&path, old.get(key), new.get(key), changed);
+            }
+        },
+        (old, new) if old != new => {
+            changed.insert(prefix.to_string());
+        },
+        _ => ()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::*;
+
+    /// Copy of the test configuration that can be changed.
+    fn config_file() -> PathBuf {
+        let path = std::env::temp_dir().join(format!("config-{}.toml", uuid::Uuid::new_v4()));
+        fs::copy("resources/test/config.toml", &path).unwrap();
+        path
+    }
+
+    fn handle(path: &Path) -> ConfigurationHandle {
+        let sources = ConfigSources { path: path.to_string_lossy().to_string(), ..ConfigSources::default() };
+        ConfigurationHandle::new(Configuration::read_from_config_file(&sources).unwrap(), sources)
+    }
+
+    /// Replace a line of the configuration file and move its modification time ahead.
+    fn edit(path: &Path, from: &str, to: &str) {
+        let content = fs::read_to_string(path).unwrap();
+        assert!(content.contains(from));
+        fs::write(path, content.replace(from, to)).unwrap();
+        let modified = SystemTime::now() + Duration::from_secs(60);
+        File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn test_reload() {
+        let path = config_file();
+        let handle = handle(&path);
+        let hook_calls = Arc::new(AtomicUsize::new(0));
+        let calls = hook_calls.clone();
+        handle.on_reload(move |_| {
+            calls.fetch_add(1, Ordering::SeqCst);
+        });
+        let before = handle.current();
+
+        edit(&path, "http://localhost:3000", "http://localhost:3001");
+        edit(&path, "body_limit = 2048", "body_limit = 4096");
+        let result = handle.reload(ReloadTrigger::Api);
+
+        assert!(result.success);
+        assert_eq!(vec!["server.body_limit".to_string()], result.restart_required);
+        assert_eq!("http://localhost:3001", handle.current().json_placeholder.url);
+        assert_eq!("http://localhost:3000", before.json_placeholder.url);
+        assert_eq!(1, hook_calls.load(Ordering::SeqCst));
+
+        let status = handle.status();
+        assert_eq!(2, status.version);
+        assert_eq!(Some(result), status.last_reload);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_reload_invalid() {
+        let path = config_file();
+        let handle = handle(&path);
+
+        edit(&path, "http://localhost:3000", "localhost:3000");
+        let result = handle.reload(ReloadTrigger::Signal);
+
+        assert!(!result.success);
+        assert_eq!(1, result.errors.len());
+        assert!(result.errors[0].starts_with("json_placeholder.url: "));
+        assert_eq!("http://localhost:3000", handle.current().json_placeholder.url);
+        assert_eq!(1, handle.status().version);
+        assert_eq!(Some(result), handle.status().last_reload);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_reload_if_changed() {
+        let path = config_file();
+        let handle = handle(&path);
+        assert_eq!(None, handle.reload_if_changed());
+
+        edit(&path, "retention_days = 7", "retention_days = 14");
+        let result = handle.reload_if_changed().unwrap();
+        assert!(result.success);
+        assert_eq!(ReloadTrigger::File, result.trigger);
+        assert!(result.restart_required.is_empty());
+        assert_eq!(14, handle.current().soft_delete.retention_days);
+
+        // Unchanged until modified again.
+        assert_eq!(None, handle.reload_if_changed());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_restart_required() {
+        let path = config_file();
+        let old = handle(&path).current();
+        edit(&path, "[rate_limit]\nwrites = { capacity = 5, refill_per_second = 0.5 }", "");
+        edit(&path, "required = false", "required = true");
+        edit(&path, "max_age_seconds = 600", "max_age_seconds = 60");
+        let new = handle(&path).current();
+
+        assert_eq!(
+            vec!["cors.max_age_seconds".to_string(), "rate_limit".to_string(), "tls.client_auth.required".to_string()],
+            restart_required(&old, &new)
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,405 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use crate::user_import::insert_dotted;
+
+/// Formats user responses can be serialized to.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ResponseFormat {
+    Json,
+    MessagePack,
+    Cbor,
+    Yaml,
+    Xml
+}
+
+/// Formats request bodies can be deserialized from.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum RequestFormat {
+    Json,
+    MessagePack,
+    Cbor,
+    /// `application/x-www-form-urlencoded` with dotted keys for nested fields, e.g. `address.geo.lat`.
+    Form
+}
+
+/// Possible errors thrown by `content_negotiation` functions.
+#[derive(Eq, PartialEq, Debug)]
+pub enum NegotiationError {
+    /// None of the supported formats is acceptable to the client.
+    NotAcceptable,
+    /// Request body has a missing or unsupported `Content-Type`.
+    UnsupportedMediaType,
+    SerializationFailed(String),
+    DeserializationFailed(String)
+}
+
+/// A single media range of an `Accept` header, e.g. `application/*;q=0.5`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct MediaRange {
+    pub main_type: String,
+    pub sub_type: String,
+    pub quality: f32
+}
+
+/// Supported formats in order of server preference. Used to break ties between equal q-values.
+pub const SUPPORTED_FORMATS: [ResponseFormat; 5] = [
+    ResponseFormat::Json,
+    ResponseFormat::MessagePack,
+    ResponseFormat::Cbor,
+    ResponseFormat::Yaml,
+    ResponseFormat::Xml,
+];
+
+impl ResponseFormat {
+
+    /// Media types accepted for this format. The first one is used as the response `Content-Type`.
+    pub fn media_types(&self) -> &'static [&'static str] {
+        match self {
+            ResponseFormat::Json => &["application/json"],
+            ResponseFormat::MessagePack => &["application/msgpack", "application/x-msgpack", "application/vnd.msgpack"],
+            ResponseFormat::Cbor => &["application/cbor"],
+            ResponseFormat::Yaml => &["application/yaml", "application/x-yaml", "text/yaml"],
+            ResponseFormat::Xml => &["application/xml", "text/xml"]
+        }
+    }
+
+    /// Value for the response `Content-Type` header.
+    pub fn content_type(&self) -> &'static str {
+        self.media_types()[0]
+    }
+}
+
+impl RequestFormat {
+
+    /// Resolve request format from a `Content-Type` header value. Parameters such as `charset` are ignored.
+    ///
+    /// ## Arguments.
+    /// * `content_type` - Value of the `Content-Type` header.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        match mime.as_str() {
+            "application/json" => Some(RequestFormat::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => Some(RequestFormat::MessagePack),
+            "application/cbor" => Some(RequestFormat::Cbor),
+            "application/x-www-form-urlencoded" => Some(RequestFormat::Form),
+            _ => None
+        }
+    }
+}
+
+impl MediaRange {
+
+    /// How well this range matches the given media type. `None` if it does not match at all,
+    /// otherwise a higher number for a more specific match.
+    ///
+    /// ## Arguments.
+    /// * `media_type` - Media type, e.g. `application/json`.
+    fn specificity(&self, media_type: &str) -> Option<u8> {
+        let (main_type, sub_type) = media_type.split_once('/')?;
+        match (self.main_type.as_str(), self.sub_type.as_str()) {
+            ("*", "*") => Some(0),
+            (range_main, "*") if range_main == main_type => Some(1),
+            (range_main, range_sub) if range_main == main_type && range_sub == sub_type => Some(2),
+            _ => None
+        }
+    }
+}
+
+/// List of supported media types, meant for error messages.
+pub fn supported_media_types() -> Vec<&'static str> {
+    SUPPORTED_FORMATS.iter().map(|format| format.content_type()).collect()
+}
+
+/// List of accepted request body media types, meant for error messages.
+pub fn supported_request_media_types() -> Vec<&'static str> {
+    vec!["application/json", "application/msgpack", "application/cbor", "application/x-www-form-urlencoded"]
+}
+
+/// Parse an `Accept` header. Malformed media ranges are skipped.
+///
+/// ## Arguments.
+/// * `header` - Value of the `Accept` header.
+///
+/// ## Returns.
+/// All valid media ranges in the order they were given.
+pub fn parse_accept(header: &str) -> Vec<MediaRange> {
+    header
+        .split(',')
+        .filter_map(|part| {
+            let mut params = part.split(';');
+            let (main_type, sub_type) = params.next()?.trim().split_once('/')?;
+            if main_type.is_empty() || sub_type.is_empty() || (main_type == "*" && sub_type != "*") {
+                return None
+            }
+
+            // Only the q-parameter matters to us. Anything unparseable makes the range invalid.
+            let mut quality = 1.0;
+            for param in params {
+                if let Some((name, value)) = param.trim().split_once('=') {
+                    if name.trim().eq_ignore_ascii_case("q") {
+                        quality = value.trim().parse::<f32>().ok().filter(|q| (0.0..=1.0).contains(q))?;
+                    }
+                }
+            }
+
+            Some(MediaRange {
+                main_type: main_type.to_ascii_lowercase(),
+                sub_type: sub_type.to_ascii_lowercase(),
+                quality
+            })
+        })
+        .collect()
+}
+
+/// Pick the response format based on the `Accept` header.
+///
+/// ## Arguments.
+/// * `accept` - Value of the `Accept` header. A missing or empty header accepts anything.
+///
+/// ## Returns.
+/// The most preferred supported format or `NegotiationError::NotAcceptable`.
+pub fn negotiate(accept: Option<&str>) -> Result<ResponseFormat, NegotiationError> {
+    let ranges = match accept {
+        Some(header) if !header.trim().is_empty() => parse_accept(header),
+        _ => return Ok(ResponseFormat::Json)
+    };
+
+    let mut best: Option<(ResponseFormat, f32)> = None;
+    for format in SUPPORTED_FORMATS {
+        // The most specific matching range decides the quality of a media type.
+        let quality = format.media_types().iter()
+            .filter_map(|media_type| {
+                ranges.iter()
+                    .filter_map(|range| range.specificity(media_type).map(|specificity| (specificity, range.quality)))
+                    .max_by_key(|(specificity, _)| *specificity)
+                    .map(|(_, quality)| quality)
+            })
+            .fold(0.0, f32::max)
+        ;
+
+        if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+            best = Some((format, quality));
+        }
+    }
+
+    best.map(|(format, _)| format).ok_or(NegotiationError::NotAcceptable)
+}
+
+/// Serialize a value to the given format.
+///
+/// ## Arguments.
+/// * `format` - Target format.
+/// * `root` - Name of the root element. Only used for XML.
+/// * `value` - Value to be serialized.
+pub fn serialize<T: Serialize>(format: ResponseFormat, root: &str, value: &T) -> Result<Vec<u8>, NegotiationError> {
+    let error = |e: &dyn std::fmt::Display| NegotiationError::SerializationFailed(e.to_string());
+    match format {
+        ResponseFormat::Json => serde_json::to_vec(value).map_err(|e| error(&e)),
+        ResponseFormat::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| error(&e)),
+        ResponseFormat::Cbor => {
+            let mut buffer = vec![];
+            ciborium::into_writer(value, &mut buffer).map_err(|e| error(&e))?;
+            Ok(buffer)
+        },
+        ResponseFormat::Yaml => serde_yaml::to_string(value).map(String::into_bytes).map_err(|e| error(&e)),
+        ResponseFormat::Xml => quick_xml::se::to_string_with_root(root, value).map(String::into_bytes).map_err(|e| error(&e))
+    }
+}
+
+/// Deserialize a request body.
+///
+/// ## Arguments.
+/// * `content_type` - Value of the `Content-Type` header, if any.
+/// * `body` - Raw request body.
+///
+/// ## Returns.
+/// The deserialized value, `NegotiationError::UnsupportedMediaType` or `NegotiationError::DeserializationFailed`.
+pub fn deserialize<T: DeserializeOwned>(content_type: Option<&str>, body: &[u8]) -> Result<T, NegotiationError> {
+    let format = content_type
+        .and_then(RequestFormat::from_content_type)
+        .ok_or(NegotiationError::UnsupportedMediaType)?
+    ;
+
+    let error = |e: &dyn std::fmt::Display| NegotiationError::DeserializationFailed(e.to_string());
+    match format {
+        RequestFormat::Json => serde_json::from_slice(body).map_err(|e| error(&e)),
+        RequestFormat::MessagePack => rmp_serde::from_slice(body).map_err(|e| error(&e)),
+        RequestFormat::Cbor => ciborium::from_reader(body).map_err(|e| error(&e)),
+        RequestFormat::Form => {
+            let mut object = Map::new();
+            for (key, value) in url::form_urlencoded::parse(body) {
+                insert_dotted(&mut object, &key, Value::String(value.into_owned()));
+            }
+            serde_json::from_value(Value::Object(object)).map_err(|e| error(&e))
+        }
+    }
+}
+
+/// Serialize a list of values to the given format. In XML every value is wrapped in its own `item` element
+/// inside the `root` element.
+///
+/// ## Arguments.
+/// * `format` - Target format.
+/// * `root` - Name of the root element. Only used for XML.
+/// * `item` - Name of a single list element. Only used for XML.
+/// * `values` - Values to be serialized.
+pub fn serialize_list<T: Serialize>(format: ResponseFormat, root: &str, item: &str, values: &[T]) -> Result<Vec<u8>, NegotiationError> {
+    if format != ResponseFormat::Xml {
+        return serialize(format, root, &values)
+    }
+
+    let mut xml = format!("<{root}>");
+    for value in values {
+        xml.push_str(
+            &quick_xml::se::to_string_with_root(item, value)
+                .map_err(|e| NegotiationError::SerializationFailed(e.to_string()))?
+        );
+    }
+    xml.push_str(&format!("</{root}>"));
+    Ok(xml.into_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::user::User;
+
+    #[test]
+    fn test_parse_accept() {
+        let ranges = parse_accept("text/html, application/*;q=0.5, */*;q=0.1, INVALID, application/json;q=2");
+
+        assert_eq!(3, ranges.len());
+        assert_eq!("html", ranges[0].sub_type);
+        assert_eq!(1.0, ranges[0].quality);
+        assert_eq!("*", ranges[1].sub_type);
+        assert_eq!(0.5, ranges[1].quality);
+        assert_eq!(0.1, ranges[2].quality);
+    }
+
+    #[test]
+    fn test_negotiate_exact() {
+        assert_eq!(Ok(ResponseFormat::Json), negotiate(Some("application/json")));
+        assert_eq!(Ok(ResponseFormat::Cbor), negotiate(Some("application/cbor")));
+        assert_eq!(Ok(ResponseFormat::Yaml), negotiate(Some("text/yaml")));
+        assert_eq!(Ok(ResponseFormat::MessagePack), negotiate(Some("application/x-msgpack")));
+    }
+
+    #[test]
+    fn test_negotiate_missing_header() {
+        assert_eq!(Ok(ResponseFormat::Json), negotiate(None));
+        assert_eq!(Ok(ResponseFormat::Json), negotiate(Some("")));
+    }
+
+    #[test]
+    fn test_negotiate_quality() {
+        assert_eq!(Ok(ResponseFormat::Xml), negotiate(Some("application/json;q=0.4, application/xml;q=0.9")));
+        assert_eq!(Ok(ResponseFormat::Yaml), negotiate(Some("*/*;q=0.1, application/yaml")));
+    }
+
+    #[test]
+    fn test_negotiate_wildcards() {
+        assert_eq!(Ok(ResponseFormat::Json), negotiate(Some("*/*")));
+        assert_eq!(Ok(ResponseFormat::Yaml), negotiate(Some("text/*")));
+        // More specific range overrides the wildcard.
+        assert_eq!(Ok(ResponseFormat::MessagePack), negotiate(Some("application/*, application/json;q=0")));
+    }
+
+    #[test]
+    fn test_negotiate_not_acceptable() {
+        assert_eq!(Err(NegotiationError::NotAcceptable), negotiate(Some("text/html")));
+        assert_eq!(Err(NegotiationError::NotAcceptable), negotiate(Some("application/json;q=0")));
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let user = User::_create_test_user(Some("1".to_string()));
+
+        let json = serialize(ResponseFormat::Json, "user", &user).unwrap();
+        assert_eq!(user, serde_json::from_slice(&json).unwrap());
+
+        let msgpack = serialize(ResponseFormat::MessagePack, "user", &user).unwrap();
+        assert_eq!(user, rmp_serde::from_slice::<User>(&msgpack).unwrap());
+
+        let yaml = serialize(ResponseFormat::Yaml, "user", &user).unwrap();
+        assert_eq!(user, serde_yaml::from_slice::<User>(&yaml).unwrap());
+
+        let cbor = serialize(ResponseFormat::Cbor, "user", &user).unwrap();
+        assert_eq!(user, ciborium::from_reader::<User, _>(cbor.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn test_request_format_from_content_type() {
+        assert_eq!(Some(RequestFormat::Json), RequestFormat::from_content_type("application/json; charset=utf-8"));
+        assert_eq!(Some(RequestFormat::MessagePack), RequestFormat::from_content_type("application/msgpack"));
+        assert_eq!(Some(RequestFormat::Cbor), RequestFormat::from_content_type("application/cbor"));
+        assert_eq!(Some(RequestFormat::Form), RequestFormat::from_content_type("application/x-www-form-urlencoded"));
+        assert_eq!(None, RequestFormat::from_content_type("text/plain"));
+    }
+
+    #[test]
+    fn test_deserialize_unsupported() {
+        assert_eq!(Err(NegotiationError::UnsupportedMediaType), deserialize::<User>(None, b"{}"));
+        assert_eq!(Err(NegotiationError::UnsupportedMediaType), deserialize::<User>(Some("text/plain"), b"{}"));
+    }
+
+    #[test]
+    fn test_deserialize_faulty_body() {
+        assert!(matches!(
+            deserialize::<User>(Some("application/json"), b"THIS IS NOT JSON"),
+            Err(NegotiationError::DeserializationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_round_trip() {
+        let user = User::_create_test_user(None);
+
+        let json = serialize(ResponseFormat::Json, "user", &user).unwrap();
+        assert_eq!(Ok(user.clone()), deserialize(Some("application/json"), &json));
+
+        let msgpack = serialize(ResponseFormat::MessagePack, "user", &user).unwrap();
+        assert_eq!(Ok(user.clone()), deserialize(Some("application/x-msgpack"), &msgpack));
+
+        let cbor = serialize(ResponseFormat::Cbor, "user", &user).unwrap();
+        assert_eq!(Ok(user), deserialize(Some("application/cbor"), &cbor));
+    }
+
+    #[test]
+    fn test_deserialize_form() {
+        let body = "name=TESTER&username=TESTER_69&email=testlover%40testing.gov\
+            &address.street=Totallyrealstreet+6&address.suite=a+12&address.city=Testington\
+            &address.geo.lat=12&address.geo.lon=15&phone=123456789&website=testing.gov\
+            &company.name=Testing&company.catchPhrase=Truly+we+are+testing&company.bs=To+test";
+
+        assert_eq!(
+            Ok(User::_create_test_user(None)),
+            deserialize(Some("application/x-www-form-urlencoded"), body.as_bytes())
+        );
+    }
+
+    #[test]
+    fn test_serialize_xml() {
+        let user = User::_create_test_user(Some("1".to_string()));
+
+        let xml = String::from_utf8(serialize(ResponseFormat::Xml, "user", &user).unwrap()).unwrap();
+        assert!(xml.starts_with("<user><id>1</id>"));
+        assert!(xml.contains("<catchPhrase>Truly we are testing</catchPhrase>"));
+    }
+
+    #[test]
+    fn test_serialize_list() {
+        let users = vec![
+            User::_create_test_user(Some("1".to_string())),
+            User::_create_test_user(Some("2".to_string()))
+        ];
+
+        let xml = String::from_utf8(serialize_list(ResponseFormat::Xml, "users", "user", &users).unwrap()).unwrap();
+        assert!(xml.starts_with("<users><user><id>1</id>"));
+        assert!(xml.contains("</user><user><id>2</id>"));
+        assert!(xml.ends_with("</user></users>"));
+
+        let json = serialize_list(ResponseFormat::Json, "users", "user", &users).unwrap();
+        assert_eq!(users, serde_json::from_slice::<Vec<User>>(&json).unwrap());
+    }
+}
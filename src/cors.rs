@@ -0,0 +1,142 @@
+use actix_cors::Cors;
+use crate::configuration;
+
+/// Build the CORS middleware from configuration.
+///
+/// ## Arguments.
+/// * `config` - CORS configuration.
+///
+/// ## Returns.
+/// Middleware answering preflight requests and adding CORS headers for allowed origins. Requests
+/// from other origins are processed without CORS headers, so browsers block them.
+pub fn cors(config: &configuration::Cors) -> Cors {
+    let allowed_origins = config.allowed_origins.clone();
+    let cors = Cors::default()
+        .allowed_origin_fn(move |origin, _| {
+            origin.to_str().is_ok_and(|origin| allowed_origins.iter().any(|pattern| origin_matches(pattern, origin)))
+        })
+        .allowed_methods(config.allowed_methods.iter().map(String::as_str))
+        .allowed_headers(config.allowed_headers.iter().map(String::as_str))
+        .max_age(config.max_age_seconds)
+    ;
+
+    if config.allow_credentials {
+        cors.supports_credentials()
+    } else {
+        cors
+    }
+}
+
+/// Check an `Origin` header against an allowed origin.
+///
+/// ## Arguments.
+/// * `pattern` - `*` for any origin, `https://*.example.com` for any subdomain, or an exact origin.
+/// * `origin` - Value of the `Origin` header, e.g. `https://admin.example.com:8443`.
+pub fn origin_matches(pattern: &str, origin: &str) -> bool {
+    if pattern == "*" {
+        return true
+    }
+
+    let Some((scheme, host)) = pattern.split_once("://*.") else {
+        return pattern.eq_ignore_ascii_case(origin)
+    };
+
+    // Only subdomains match, not the domain itself.
+    let origin = origin.to_ascii_lowercase();
+    origin.strip_prefix(&format!("{}://", scheme.to_ascii_lowercase()))
+        .and_then(|origin_host| origin_host.strip_suffix(&format!(".{}", host.to_ascii_lowercase())))
+        .is_some_and(|subdomain| !subdomain.is_empty() && !subdomain.contains(['/', ':']))
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{App, get, HttpResponse};
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::{
+        ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+        ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+        ACCESS_CONTROL_REQUEST_METHOD, ORIGIN
+    };
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use super::*;
+
+    #[get("/users")]
+    async fn users() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    fn config() -> configuration::Cors {
+        configuration::Cors {
+            allowed_origins: vec!["https://admin.example.com".to_string(), "https://*.example.org".to_string()],
+            allowed_methods: vec!["GET".to_string(), "PATCH".to_string()],
+            allowed_headers: vec!["authorization".to_string(), "content-type".to_string()],
+            allow_credentials: true,
+            max_age_seconds: Some(600)
+        }
+    }
+
+    fn preflight(origin: &str, method: &str) -> TestRequest {
+        TestRequest::default()
+            .method(actix_web::http::Method::OPTIONS)
+            .uri("/users")
+            .insert_header((ORIGIN, origin))
+            .insert_header((ACCESS_CONTROL_REQUEST_METHOD, method))
+            .insert_header((ACCESS_CONTROL_REQUEST_HEADERS, "authorization"))
+    }
+
+    #[test]
+    fn test_origin_matches() {
+        assert!(origin_matches("*", "https://anything.test"));
+        assert!(origin_matches("https://admin.example.com", "https://admin.example.com"));
+        assert!(!origin_matches("https://admin.example.com", "http://admin.example.com"));
+
+        assert!(origin_matches("https://*.example.org", "https://admin.example.org"));
+        assert!(origin_matches("https://*.example.org", "https://a.b.EXAMPLE.org"));
+        assert!(!origin_matches("https://*.example.org", "https://example.org"));
+        assert!(!origin_matches("https://*.example.org", "https://evil-example.org"));
+        assert!(!origin_matches("https://*.example.org", "https://admin.example.org.evil.test"));
+        assert!(!origin_matches("https://*.example.org", "https://admin.example.org:8443"));
+        assert!(origin_matches("https://*.example.org:8443", "https://admin.example.org:8443"));
+    }
+
+    #[actix_web::test]
+    async fn test_preflight_allowed() {
+        let app = init_service(App::new().wrap(cors(&config())).service(users)).await;
+
+        let response = call_service(&app, preflight("https://ui.example.org", "PATCH").to_request()).await;
+        assert_eq!(StatusCode::OK, response.status());
+
+        let headers = response.headers();
+        assert_eq!("https://ui.example.org", headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap());
+        assert_eq!("true", headers.get(ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap());
+        assert_eq!("600", headers.get(ACCESS_CONTROL_MAX_AGE).unwrap());
+        assert!(headers.get(ACCESS_CONTROL_ALLOW_METHODS).unwrap().to_str().unwrap().contains("PATCH"));
+        assert!(headers.get(ACCESS_CONTROL_ALLOW_HEADERS).unwrap().to_str().unwrap().contains("authorization"));
+    }
+
+    #[actix_web::test]
+    async fn test_preflight_rejected() {
+        let app = init_service(App::new().wrap(cors(&config())).service(users)).await;
+
+        let response = call_service(&app, preflight("https://evil.test", "GET").to_request()).await;
+        assert!(response.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+        let response = call_service(&app, preflight("https://admin.example.com", "DELETE").to_request()).await;
+        assert!(response.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_simple_request() {
+        let app = init_service(App::new().wrap(cors(&config())).service(users)).await;
+
+        let req = TestRequest::get().uri("/users").insert_header((ORIGIN, "https://admin.example.com")).to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!("https://admin.example.com", response.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap());
+
+        // Other origins are served without CORS headers and blocked by the browser.
+        let req = TestRequest::get().uri("/users").insert_header((ORIGIN, "https://evil.test")).to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert!(response.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+}
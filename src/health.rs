@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use futures::future::{BoxFuture, join_all};
+use log::warn;
+use mongodb::bson::DateTime;
+use serde::Serialize;
+use crate::configuration::DependencyCheck;
+use crate::configuration_reload::ConfigurationHandle;
+
+/// Name of the MongoDB check.
+pub const MONGODB: &str = "mongodb";
+
+/// Name of the JsonPlaceholder check.
+pub const JSON_PLACEHOLDER: &str = "json_placeholder";
+
+/// Something the service needs to answer requests.
+pub trait Dependency: Send + Sync {
+
+    /// Check that the dependency answers.
+    ///
+    /// ## Returns.
+    /// An empty `Ok` or a description of the problem.
+    fn ping(&self) -> BoxFuture<'_, Result<(), String>>;
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Up,
+    Down
+}
+
+/// Outcome of checking a single dependency.
+#[derive(Eq, PartialEq, Debug, Clone, Serialize)]
+pub struct DependencyStatus {
+    pub status: Status,
+    /// The service is not ready while a required dependency is down.
+    pub required: bool,
+    pub latency_ms: u64,
+    /// Latest failure, kept after the dependency recovers.
+    pub last_error: Option<String>,
+    /// RFC 3339 time of `last_error`.
+    pub last_error_at: Option<String>
+}
+
+/// Outcome of a readiness check.
+#[derive(Eq, PartialEq, Debug, Clone, Serialize)]
+pub struct Readiness {
+    /// Down if any required dependency is down.
+    pub status: Status,
+    pub checks: BTreeMap<String, DependencyStatus>
+}
+
+/// Checks dependencies for readiness. Created once at startup and shared with handlers as app data.
+pub struct HealthChecker {
+    config: Arc<ConfigurationHandle>,
+    mongodb: Arc<dyn Dependency>,
+    json_placeholder: Arc<dyn Dependency>,
+    /// Latest failure and its time by dependency name.
+    last_errors: Mutex<HashMap<&'static str, (String, String)>>,
+    /// Set once shutdown starts, the service is never ready again.
+    shutting_down: AtomicBool
+}
+
+impl HealthChecker {
+
+    /// Create a checker.
+    ///
+    /// ## Arguments.
+    /// * `config` - Configuration in use. Timeouts and checked dependencies are read on every check.
+    /// * `mongodb` - Database, always required.
+    /// * `json_placeholder` - Upstream users api.
+    pub fn new(config: Arc<ConfigurationHandle>, mongodb: Arc<dyn Dependency>, json_placeholder: Arc<dyn Dependency>) -> Self {
+        HealthChecker { config, mongodb, json_placeholder, last_errors: Mutex::new(HashMap::new()), shutting_down: AtomicBool::new(false) }
+    }
+
+    /// Report the service as not ready from now on, so that no new traffic is sent while shutting down.
+    pub fn shut_down(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+    }
+
+    /// Check every dependency concurrently, each within the configured timeout.
+    /// Down without checking anything once shutdown started.
+    pub async fn readiness(&self) -> Readiness {
+        if self.shutting_down.load(Ordering::Relaxed) {
+            return Readiness { status: Status::Down, checks: BTreeMap::new() }
+        }
+
+        let health = self.config.current().health;
+        let timeout = Duration::from_millis(health.timeout_ms);
+
+        let mut dependencies = vec![(MONGODB, &self.mongodb, true)];
+        match health.json_placeholder {
+            DependencyCheck::Required => dependencies.push((JSON_PLACEHOLDER, &self.json_placeholder, true)),
+            DependencyCheck::Optional => dependencies.push((JSON_PLACEHOLDER, &self.json_placeholder, false)),
+            DependencyCheck::Off => ()
+        }
+
+        let checks = join_all(dependencies.into_iter().map(|(name, dependency, required)| async move {
+            (name.to_string(), self.check(name, dependency.as_ref(), required, timeout).await)
+        })).await;
+        let ready = checks.iter().all(|(_, check)| !check.required || check.status == Status::Up);
+
+        Readiness {
+            status: if ready { Status::Up } else { Status::Down },
+            checks: checks.into_iter().collect()
+        }
+    }
+
+    /// Check a single dependency.
+    ///
+    /// ## Arguments.
+    /// * `name` - Name of the dependency in the result.
+    /// * `dependency` - Dependency to check.
+    /// * `required` - Whether the service is ready without it.
+    /// * `timeout` - Time allowed for the check.
+    async fn check(&self, name: &'static str, dependency: &dyn Dependency, required: bool, timeout: Duration) -> DependencyStatus {
+        let started = Instant::now();
+        let result = match tokio::time::timeout(timeout, dependency.ping()).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("No answer within {} ms.", timeout.as_millis()))
+        };
+        let latency_ms = started.elapsed().as_millis() as u64;
+
+        let mut last_errors = self.last_errors.lock().unwrap();
+        let status = match result {
+            Ok(()) => Status::Up,
+            Err(e) => {
+                warn!("Readiness check of {name} failed: {e}");
+                last_errors.insert(name, (e, DateTime::now().try_to_rfc3339_string().unwrap_or_default()));
+                Status::Down
+            }
+        };
+        let (last_error, last_error_at) = last_errors.get(name).cloned().unzip();
+
+        DependencyStatus { status, required, latency_ms, last_error, last_error_at }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use futures::FutureExt;
+    use crate::configuration::{ConfigSources, Configuration};
+    use super::*;
+
+    /// Dependency answering with a fixed result after a delay.
+    pub struct FakeDependency {
+        pub result: Result<(), String>,
+        pub delay: Duration
+    }
+
+    impl FakeDependency {
+        pub fn up() -> Arc<Self> {
+            Arc::new(FakeDependency { result: Ok(()), delay: Duration::ZERO })
+        }
+
+        pub fn down() -> Arc<Self> {
+            Arc::new(FakeDependency { result: Err("Connection refused.".to_string()), delay: Duration::ZERO })
+        }
+    }
+
+    impl Dependency for FakeDependency {
+        fn ping(&self) -> BoxFuture<'_, Result<(), String>> {
+            async move {
+                tokio::time::sleep(self.delay).await;
+                self.result.clone()
+            }.boxed()
+        }
+    }
+
+    /// Test configuration with the given overrides.
+    pub fn config(overrides: &[(&str, &str)]) -> Arc<ConfigurationHandle> {
+        let sources = ConfigSources {
+            path: "resources/test/config.toml".to_string(),
+            overrides: overrides.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+            ..ConfigSources::default()
+        };
+        Arc::new(ConfigurationHandle::new(Configuration::read_from_config_file(&sources).unwrap(), sources))
+    }
+
+    #[tokio::test]
+    async fn test_readiness() {
+        let checker = HealthChecker::new(config(&[]), FakeDependency::up(), FakeDependency::down());
+
+        let readiness = checker.readiness().await;
+        assert_eq!(Status::Up, readiness.status);
+        assert_eq!(Status::Up, readiness.checks[MONGODB].status);
+        assert!(readiness.checks[MONGODB].required);
+        assert_eq!(None, readiness.checks[MONGODB].last_error);
+
+        // Optional dependencies are reported but do not matter.
+        let json_placeholder = &readiness.checks[JSON_PLACEHOLDER];
+        assert_eq!(Status::Down, json_placeholder.status);
+        assert!(!json_placeholder.required);
+        assert_eq!(Some("Connection refused.".to_string()), json_placeholder.last_error);
+        assert!(json_placeholder.last_error_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_readiness_required_dependency_down() {
+        let checker = HealthChecker::new(config(&[("health.json_placeholder", "required")]), FakeDependency::up(), FakeDependency::down());
+        assert_eq!(Status::Down, checker.readiness().await.status);
+
+        let checker = HealthChecker::new(config(&[("health.json_placeholder", "off")]), FakeDependency::down(), FakeDependency::down());
+        let readiness = checker.readiness().await;
+        assert_eq!(Status::Down, readiness.status);
+        assert_eq!(vec![MONGODB], readiness.checks.keys().collect::<Vec<&String>>());
+    }
+
+    #[tokio::test]
+    async fn test_readiness_timeout() {
+        let slow = Arc::new(FakeDependency { result: Ok(()), delay: Duration::from_secs(5) });
+        let checker = HealthChecker::new(config(&[("health.timeout_ms", "50")]), slow, FakeDependency::up());
+
+        let readiness = checker.readiness().await;
+        assert_eq!(Status::Down, readiness.status);
+        assert_eq!(Some("No answer within 50 ms.".to_string()), readiness.checks[MONGODB].last_error);
+        assert!(readiness.checks[MONGODB].latency_ms < 5000);
+    }
+
+    #[tokio::test]
+    async fn test_last_error_is_kept() {
+        let mongodb = Arc::new(Mutex::new(FakeDependency::down()));
+        struct Switch(Arc<Mutex<Arc<FakeDependency>>>);
+        impl Dependency for Switch {
+            fn ping(&self) -> BoxFuture<'_, Result<(), String>> {
+                let result = self.0.lock().unwrap().result.clone();
+                futures::future::ready(result).boxed()
+            }
+        }
+        let checker = HealthChecker::new(config(&[]), Arc::new(Switch(mongodb.clone())), FakeDependency::up());
+        assert_eq!(Status::Down, checker.readiness().await.status);
+
+        *mongodb.lock().unwrap() = FakeDependency::up();
+        let readiness = checker.readiness().await;
+        assert_eq!(Status::Up, readiness.status);
+        assert_eq!(Some("Connection refused.".to_string()), readiness.checks[MONGODB].last_error);
+    }
+
+    #[tokio::test]
+    async fn test_readiness_after_shut_down() {
+        let checker = HealthChecker::new(config(&[]), FakeDependency::up(), FakeDependency::up());
+        assert_eq!(Status::Up, checker.readiness().await.status);
+
+        checker.shut_down();
+        let readiness = checker.readiness().await;
+        assert_eq!(Status::Down, readiness.status);
+        assert!(readiness.checks.is_empty());
+    }
+}
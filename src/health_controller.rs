@@ -0,0 +1,71 @@
+use actix_web::{get, HttpResponse, web};
+use log::{info, warn};
+use serde_json::json;
+use crate::health::{HealthChecker, Status};
+
+/// The process is running and able to answer. Does not check dependencies.
+#[get("/health/live")]
+pub async fn live() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": Status::Up }))
+}
+
+/// Every required dependency answers and the service is not shutting down, so it can take traffic.
+#[get("/health/ready")]
+pub async fn ready(checker: web::Data<HealthChecker>) -> HttpResponse {
+    let readiness = checker.readiness().await;
+
+    match readiness.status {
+        Status::Up => {
+            info!("Service is ready. Responding with 200.");
+            HttpResponse::Ok().json(readiness)
+        },
+        Status::Down => {
+            warn!("Service is not ready. Responding with 503.");
+            HttpResponse::ServiceUnavailable().json(readiness)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::App;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_and_read_body_json, call_service, init_service, read_body_json, TestRequest};
+    use serde_json::Value;
+    use crate::health::test::{config, FakeDependency};
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_live() {
+        let app = init_service(App::new().service(live)).await;
+
+        let body: Value = call_and_read_body_json(&app, TestRequest::get().uri("/health/live").to_request()).await;
+        assert_eq!("up", body["status"]);
+    }
+
+    #[actix_web::test]
+    async fn test_ready() {
+        let checker = HealthChecker::new(config(&[]), FakeDependency::up(), FakeDependency::down());
+        let app = init_service(App::new().app_data(web::Data::new(checker)).service(ready)).await;
+
+        let body: Value = call_and_read_body_json(&app, TestRequest::get().uri("/health/ready").to_request()).await;
+        assert_eq!("up", body["status"]);
+        assert_eq!("up", body["checks"]["mongodb"]["status"]);
+        assert_eq!("down", body["checks"]["json_placeholder"]["status"]);
+        assert_eq!(false, body["checks"]["json_placeholder"]["required"]);
+        assert_eq!("Connection refused.", body["checks"]["json_placeholder"]["last_error"]);
+    }
+
+    #[actix_web::test]
+    async fn test_not_ready() {
+        let checker = HealthChecker::new(config(&[]), FakeDependency::down(), FakeDependency::up());
+        let app = init_service(App::new().app_data(web::Data::new(checker)).service(ready)).await;
+
+        let response = call_service(&app, TestRequest::get().uri("/health/ready").to_request()).await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+        let body: Value = read_body_json(response).await;
+        assert_eq!("down", body["status"]);
+        assert_eq!("Connection refused.", body["checks"]["mongodb"]["last_error"]);
+        assert!(body["checks"]["mongodb"]["latency_ms"].is_u64());
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt;
+use std::time::Instant;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderValue;
+use actix_web::HttpMessage;
+use actix_web::middleware::Next;
+use log::LevelFilter;
+use mongodb::bson::DateTime;
+use opentelemetry_sdk::trace::Tracer;
+use serde_json::{Map, Value};
+use tracing::{Event, info, Span, Subscriber, warn};
+use tracing::field::{Field, Visit};
+use tracing_log::{AsTrace, LogTracer, NormalizeEvent};
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+use tracing_subscriber::filter::{filter_fn, FilterExt};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormattedFields, MakeWriter};
+use tracing_subscriber::fmt::format::{JsonFields, Writer};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use crate::api_error::{self, REQUEST_ID_HEADER, RequestId};
+use crate::telemetry::REQUEST_SPAN;
+
+/// Log JSON lines to stdout, through `log` and `tracing` alike. Call once at startup.
+/// Lines above `level` are dropped, `log::set_max_level` changes it at runtime. `RUST_LOG` filters further.
+///
+/// ## Arguments.
+/// * `level` - Initial log level.
+/// * `telemetry` - Layer exporting spans, if tracing is configured.
+pub fn init(level: LevelFilter, telemetry: Option<OpenTelemetryLayer<Registry, Tracer>>) {
+    LogTracer::init().expect("Logging is initialized once.");
+    log::set_max_level(level);
+
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("trace"));
+    let level_filter = filter_fn(|metadata| *metadata.level() <= log::max_level().as_trace());
+    // Spans always pass, so that lines of any level get the request fields.
+    let filter = level_filter.and(env_filter).or(filter_fn(|metadata| metadata.is_span()));
+
+    let subscriber = Registry::default()
+        .with(telemetry)
+        .with(json_layer(std::io::stdout).with_filter(filter));
+    tracing::subscriber::set_global_default(subscriber).expect("Logging is initialized once.");
+}
+
+/// Layer writing every event as a line of JSON.
+///
+/// ## Arguments.
+/// * `make_writer` - Destination of the lines.
+fn json_layer<S, W>(make_writer: W) -> tracing_subscriber::fmt::Layer<S, JsonFields, JsonFormat, W>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + 'static
+{
+    tracing_subscriber::fmt::layer()
+        .fmt_fields(JsonFields::new())
+        .event_format(JsonFormat)
+        .with_writer(make_writer)
+}
+
+/// Formats an event as a single JSON object with timestamp, level, target, the fields of the
+/// request it happened in, e.g. `request_id`, `http.route` and `user_id`, and its own fields.
+pub struct JsonFormat;
+
+impl<S> FormatEvent<S, JsonFields> for JsonFormat
+where
+    S: Subscriber + for<'span> LookupSpan<'span>
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, JsonFields>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        // Events forwarded from `log` carry their origin in fields.
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+
+        let mut line = Map::new();
+        line.insert("timestamp".to_string(), Value::from(DateTime::now().try_to_rfc3339_string().unwrap_or_default()));
+        line.insert("level".to_string(), Value::from(metadata.level().to_string()));
+        line.insert("target".to_string(), Value::from(metadata.target()));
+
+        if let Some(span) = ctx.event_scope().and_then(|mut scope| scope.find(|span| span.name() == REQUEST_SPAN)) {
+            let extensions = span.extensions();
+            let fields = extensions.get::<FormattedFields<JsonFields>>()
+                .and_then(|fields| serde_json::from_str::<Map<String, Value>>(&fields.fields).ok())
+                .unwrap_or_default();
+            line.extend(fields.into_iter().filter(|(name, _)| !name.starts_with("otel.")));
+        }
+        event.record(&mut JsonVisitor(&mut line));
+
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+/// Collects event fields into a JSON object.
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl JsonVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        if !field.name().starts_with("log.") {
+            self.0.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, Value::from(format!("{value:?}")));
+    }
+}
+
+/// Middleware giving every request an id, taken from the `X-Request-Id` header or generated, and echoing it
+/// in the response. Logs a line per request with status and latency.
+/// Wrap it inside `telemetry::trace_requests`, so that the id is added to every line of the request.
+pub async fn log_requests(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = api_error::request_id(&req);
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+    Span::current().record("request_id", request_id.as_str());
+    let started = Instant::now();
+
+    let result = next.call(req).await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+    match result {
+        Ok(mut res) => {
+            let status = res.status().as_u16();
+            if res.status().is_server_error() {
+                warn!(status, latency_ms, "Request failed.");
+            } else {
+                info!(status, latency_ms, "Request finished.");
+            }
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+            Ok(res)
+        },
+        Err(e) => {
+            warn!(status = e.as_response_error().status_code().as_u16(), latency_ms, "Request failed: {e}");
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use actix_web::{App, get, HttpResponse};
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use crate::telemetry::trace_requests;
+    use super::*;
+
+    /// Collects log lines in memory.
+    #[derive(Clone, Default)]
+    struct Lines(Arc<Mutex<Vec<u8>>>);
+
+    impl Lines {
+        fn parsed(&self) -> Vec<Value> {
+            let buffer = self.0.lock().unwrap();
+            String::from_utf8_lossy(&buffer).lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+        }
+    }
+
+    impl io::Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[get("/users/{id}")]
+    async fn user() -> HttpResponse {
+        info!("Getting user.");
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn test_log_requests() {
+        let lines = Lines::default();
+        let writer = lines.clone();
+        let _guard = tracing::subscriber::set_default(Registry::default().with(json_layer(move || writer.clone())));
+        let app = init_service(
+            App::new().wrap(from_fn(log_requests)).wrap(from_fn(trace_requests)).service(user)
+        ).await;
+
+        let response = call_service(&app, TestRequest::get().uri("/users/1").insert_header((REQUEST_ID_HEADER, "TEST_ID")).to_request()).await;
+        assert_eq!("TEST_ID", response.headers().get(REQUEST_ID_HEADER).unwrap());
+        let response = call_service(&app, TestRequest::get().uri("/users/2").to_request()).await;
+        let generated = response.headers().get(REQUEST_ID_HEADER).unwrap().to_str().unwrap().to_string();
+        assert!(uuid::Uuid::parse_str(&generated).is_ok());
+
+        let lines = lines.parsed();
+        let handler_line = lines.iter().find(|line| line["message"] == "Getting user.").unwrap();
+        assert_eq!("INFO", handler_line["level"]);
+        assert_eq!(module_path!(), handler_line["target"]);
+        assert_eq!("TEST_ID", handler_line["request_id"]);
+        assert_eq!("/users/{id}", handler_line["http.route"]);
+        assert!(handler_line.get("otel.kind").is_none());
+        assert!(handler_line.get("log.file").is_none());
+
+        let finished_line = lines.iter().rfind(|line| line["message"] == "Request finished.").unwrap();
+        assert_eq!(generated, finished_line["request_id"]);
+        assert_eq!(200, finished_line["status"]);
+        assert!(finished_line["latency_ms"].is_u64());
+        assert!(finished_line["timestamp"].is_string());
+    }
+}
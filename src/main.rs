@@ -1,38 +1,421 @@
-extern crate lazy_static;
-
-use actix_web::{App, HttpServer};
-use lazy_static::lazy_static;
-use log::{info};
-use crate::configuration::Configuration;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::{App, HttpServer, web};
+use actix_web::middleware::{Condition, from_fn};
+use clap::Parser;
+use log::{error, info, LevelFilter, warn};
+use mongodb::bson::DateTime;
+use crate::api_key_service::ApiKeyService;
+use crate::audit::AuditContext;
+use crate::authentication::Authenticator;
+use crate::cli::{Cli, Command, ConfigCommand};
+use crate::configuration::{Configuration, ConfigurationError};
+use crate::configuration_reload::ConfigurationHandle;
+use crate::health::HealthChecker;
+use crate::metrics::Metrics;
+use crate::rate_limit::{InMemoryStore, RateLimiter};
+use crate::user_client::JsonPlaceholderClient;
+use crate::user_import::ImportFormat;
+use crate::user_repository::{get_client, MongoUserRepository};
+use crate::user_service::UserService;
 
+mod api_error;
+mod api_key;
+mod api_key_controller;
+mod api_key_service;
+mod audit;
+mod authentication;
+mod authorization;
+mod cli;
 mod configuration;
+mod configuration_controller;
+mod configuration_reload;
+mod content_negotiation;
+mod cors;
+mod health;
+mod health_controller;
+mod logging;
+mod metrics;
+mod metrics_controller;
+mod rate_limit;
+mod redaction;
+mod revision;
+mod secret;
+mod shutdown;
+mod telemetry;
+mod tls;
 mod user;
 mod user_service;
 mod user_controller;
 mod user_client;
+mod user_export;
+mod user_import;
+mod user_repository;
 
-lazy_static! {
-    static ref CONFIG: Configuration =
-        Configuration::read_from_config_file("resources/config.toml").unwrap()
-    ;
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if let Some(Command::Config { command: ConfigCommand::Check }) = cli.command {
+        logging::init(LevelFilter::Info, None);
+        return check_config(&cli)
+    }
+
+    let sources = cli.config_sources();
+    let configuration = match Configuration::read_from_config_file(&sources) {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            logging::init(LevelFilter::Info, None);
+            error!("{e}");
+            return ExitCode::FAILURE
+        }
+    };
+    match configuration.tracing.as_ref().map(telemetry::layer).transpose() {
+        Ok(telemetry) => logging::init(configuration.logging.level, telemetry),
+        Err(e) => {
+            logging::init(configuration.logging.level, None);
+            error!("Could not set up tracing: {e}");
+            return ExitCode::FAILURE
+        }
+    }
+    if let Some(tracing) = &configuration.tracing {
+        info!("Exporting traces to {:?}.", tracing.exporter);
+    }
+
+    let handle = Arc::new(ConfigurationHandle::new(configuration, sources));
+    handle.on_reload(|configuration| log::set_max_level(configuration.logging.level));
+
+    let config = handle.current();
+    let mongo_client = match get_client(config.database.connection_string()).await {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Could not connect to MongoDB: {e:?}");
+            return ExitCode::FAILURE
+        }
+    };
+    let database = mongo_client.database(&config.database.database_name);
+    let metrics = Arc::new(Metrics::new());
+    let repository = Arc::new(MongoUserRepository::new(database.clone()));
+    let api_key_service = ApiKeyService::new(database);
+
+    // Uniqueness of revisions and API keys relies on indexes, so do not run without them.
+    if let Err(e) = repository.create_indexes().await {
+        error!("Could not create user indexes in MongoDB: {e:?}");
+        return ExitCode::FAILURE
+    }
+    if let Err(e) = api_key_service.create_indexes().await {
+        error!("Could not create API key indexes in MongoDB: {e:?}");
+        return ExitCode::FAILURE
+    }
+
+    let client = json_placeholder_client(&handle, &metrics);
+    let user_service = UserService::new(repository.clone(), client.clone(), Arc::clone(&handle), Arc::clone(&metrics));
+    let health_checker = HealthChecker::new(Arc::clone(&handle), repository, client);
+
+    let exit_code = match cli.command {
+        Some(Command::Import { path, format, all_or_nothing }) => run_import(&user_service, &path, format, all_or_nothing).await,
+        Some(Command::Config { .. }) => unreachable!("Configuration commands are run before loading configuration."),
+        None => match run_server(handle, user_service, api_key_service, health_checker, metrics).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                error!("Server stopped with error: {e}");
+                ExitCode::FAILURE
+            }
+        }
+    };
+
+    // Requests have finished along with their audit records. Metrics are scraped, so only spans are buffered.
+    telemetry::shutdown();
+    mongo_client.shutdown().await;
+    info!("Stopped.");
+    exit_code
 }
 
-#[tokio::main]
-async fn main() -> std::io::Result<()> {
-    env_logger::init();
+/// Create the JsonPlaceholder client, following reloads of its url.
+///
+/// ## Arguments.
+/// * `handle` - Configuration in use.
+/// * `metrics` - Metrics recording upstream requests.
+fn json_placeholder_client(handle: &ConfigurationHandle, metrics: &Arc<Metrics>) -> Arc<JsonPlaceholderClient> {
+    let client = Arc::new(JsonPlaceholderClient::new(&handle.current().json_placeholder.url).with_metrics(Arc::clone(metrics)));
+    let reloaded_client = Arc::clone(&client);
+    handle.on_reload(move |config| reloaded_client.set_url(&config.json_placeholder.url));
+    client
+}
 
+/// Start the REST api.
+///
+/// ## Arguments.
+/// * `handle` - Configuration in use.
+/// * `user_service` - Users shared by all workers.
+/// * `api_key_service` - API keys shared by all workers.
+/// * `health_checker` - Readiness checks of MongoDB and JsonPlaceholder.
+/// * `metrics` - Metrics shared by all workers.
+async fn run_server(
+    handle: Arc<ConfigurationHandle>,
+    user_service: UserService,
+    api_key_service: ApiKeyService,
+    health_checker: HealthChecker,
+    metrics: Arc<Metrics>
+) -> std::io::Result<()> {
+    // Settings only read here take effect after a restart.
+    let config = handle.current();
     info!("Starting rust-backend-showcase...");
-    info!("Listening on port 8080.");
-    println!();
-
-    HttpServer::new(|| {
-        App::new()
-            .service(user_controller::hello)
-            .service(user_controller::get_all_users)
-            .service(user_controller::get_user_with_id)
-            .service(user_controller::create_new_user)
+    info!("Listening on {}:{}.", config.server.host, config.server.port);
+
+    let authenticator = match &config.authentication {
+        Some(authentication) => {
+            let authenticator = Authenticator::new(authentication).map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid authentication configuration: {e:?}"))
+            })?;
+            let client_certificate_roles = config.tls.as_ref()
+                .and_then(|tls| tls.client_auth.as_ref())
+                .map(|client_auth| client_auth.roles.clone())
+                .unwrap_or_default()
+            ;
+            let authenticator = authenticator
+                .with_client_certificate_roles(client_certificate_roles)
+                .with_metrics(Arc::clone(&metrics))
+            ;
+            Some(web::Data::new(authenticator))
+        },
+        None => {
+            warn!("Authentication is disabled in configuration. All REST apis are open!");
+            None
+        }
+    };
+
+    let rate_limiter = config.rate_limit.as_ref().map(|config| {
+        web::Data::new(RateLimiter::new(config, Arc::new(InMemoryStore::default())))
+    });
+
+    // Apply reloaded settings held outside of the configuration.
+    if let Some(authenticator) = authenticator.clone() {
+        handle.on_reload(move |config| {
+            if let Some(authentication) = &config.authentication {
+                authenticator.set_cache_durations(authentication);
+            }
+        });
+    }
+    if let Some(rate_limiter) = rate_limiter.clone() {
+        handle.on_reload(move |config| {
+            if let Some(rate_limit) = &config.rate_limit {
+                rate_limiter.set_limits(rate_limit);
+            }
+        });
+    }
+    tokio::spawn(Arc::clone(&handle).watch());
+
+    let user_service = web::Data::new(user_service);
+    let api_key_service = web::Data::new(api_key_service);
+    let health_checker = web::Data::new(health_checker);
+    let readiness = health_checker.clone();
+    let metrics = web::Data::from(metrics);
+    tokio::spawn(purge_deleted_users(user_service.clone()));
+
+    let server = HttpServer::new(move || {
+        // CORS wraps everything else so preflight requests are answered before authentication.
+        let config = handle.current();
+        let cors = config.cors.as_ref().map(cors::cors);
+        let app = App::new()
+            .wrap(from_fn(api_error::problem_details))
+            .wrap(Condition::new(cors.is_some(), cors.unwrap_or_default()))
+            .wrap(from_fn(metrics::record_metrics))
+            .wrap(from_fn(logging::log_requests))
+            .wrap(from_fn(telemetry::trace_requests))
+            .app_data(web::PayloadConfig::new(config.server.body_limit))
+            .app_data(web::Data::from(Arc::clone(&handle)))
+            .app_data(user_service.clone())
+            .app_data(api_key_service.clone())
+            .app_data(health_checker.clone())
+            .app_data(metrics.clone())
+        ;
+        let app = match authenticator.clone() {
+            Some(authenticator) => app.app_data(authenticator),
+            None => app
+        };
+        let app = match rate_limiter.clone() {
+            Some(rate_limiter) => app.app_data(rate_limiter),
+            None => app
+        };
+
+        app.configure(routes)
     })
-        .bind(("127.0.0.1", 8080))?
-        .run()
-        .await
+        .on_connect(tls::on_connect)
+        .disable_signals()
+        .shutdown_timeout(config.server.shutdown_timeout_seconds)
+    ;
+
+    let server = match config.server.workers {
+        Some(workers) => server.workers(workers),
+        None => server
+    };
+
+    let address = (config.server.host.as_str(), config.server.port);
+    let server = match &config.tls {
+        Some(config) => {
+            let (server_config, resolver) = tls::server_config(config).map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid TLS configuration: {e:?}"))
+            })?;
+            tokio::spawn(resolver.watch(Duration::from_secs(config.reload_interval_seconds)));
+            info!("Serving HTTPS.");
+            server.bind_rustls_021(address, server_config)?
+        },
+        None => server.bind(address)?
+    };
+
+    shutdown::run_until(server.run(), shutdown::signal_received(), readiness).await
+}
+
+/// Register the routes. Health checks and metrics are left out of authentication and rate limits,
+/// so that probes and scrapes sharing an address are never refused.
+///
+/// ## Arguments.
+/// * `config` - Configuration of the app.
+fn routes(config: &mut web::ServiceConfig) {
+    config
+        .service(user_controller::hello)
+        .service(health_controller::live)
+        .service(health_controller::ready)
+        .service(metrics_controller::get_metrics)
+        .service(
+            web::scope("")
+                .wrap(from_fn(rate_limit::rate_limit_callers))
+                .wrap(from_fn(authentication::authenticate))
+                // Limits by IP apply before authentication, so that failed attempts count too.
+                .wrap(from_fn(rate_limit::rate_limit))
+                .service(user_controller::get_all_users)
+                .service(user_controller::export_users)
+                .service(user_controller::get_user_with_id)
+                .service(user_controller::get_user_audit)
+                .service(user_controller::get_user_revisions)
+                .service(user_controller::restore_user_revision)
+                .service(user_controller::create_new_user)
+                .service(user_controller::bulk_create_users)
+                .service(user_controller::update_user)
+                .service(user_controller::delete_user)
+                .service(user_controller::restore_user)
+                .service(api_key_controller::issue_api_key)
+                .service(api_key_controller::get_api_keys)
+                .service(api_key_controller::revoke_api_key)
+                .service(configuration_controller::get_config_status)
+                .service(configuration_controller::reload_config)
+        );
+}
+
+/// Permanently remove soft-deleted users once their retention has passed. Runs until the server stops.
+///
+/// ## Arguments.
+/// * `user_service` - Users to purge.
+async fn purge_deleted_users(user_service: web::Data<UserService>) {
+    loop {
+        // Read every time, so that reloaded settings apply.
+        let soft_delete = user_service.config().soft_delete;
+        let retention = Duration::from_secs(u64::from(soft_delete.retention_days) * 24 * 60 * 60);
+        let deleted_before = DateTime::from_millis(DateTime::now().timestamp_millis() - retention.as_millis() as i64);
+        match user_service.purge_deleted_users(deleted_before).await {
+            Ok(0) => (),
+            Ok(count) => info!("Purged {count} users deleted over {} days ago.", soft_delete.retention_days),
+            Err(e) => warn!("Could not purge deleted users: {e:?}")
+        }
+        tokio::time::sleep(Duration::from_secs(soft_delete.purge_interval_seconds)).await;
+    }
+}
+
+/// Print the merged configuration and its problems.
+///
+/// ## Arguments.
+/// * `cli` - Command line selecting the configuration sources.
+fn check_config(cli: &Cli) -> ExitCode {
+    let problems = match Configuration::read_unvalidated(&cli.config_sources()) {
+        Ok(configuration) => {
+            println!("{}", configuration.to_masked_toml());
+            configuration.validate()
+        },
+        Err(ConfigurationError::InvalidKeys(problems)) => problems,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE
+        }
+    };
+
+    if problems.is_empty() {
+        eprintln!("Configuration is valid.");
+        ExitCode::SUCCESS
+    } else {
+        eprintln!("{}", ConfigurationError::InvalidKeys(problems));
+        ExitCode::FAILURE
+    }
+}
+
+/// Import users from a file and print the resulting report.
+///
+/// ## Arguments.
+/// * `user_service` - Users to import to.
+/// * `path` - File to import.
+/// * `format` - Input format. Guessed from the file extension when missing.
+/// * `all_or_nothing` - If true, nothing is saved unless every row can be imported.
+async fn run_import(user_service: &UserService, path: &Path, format: Option<String>, all_or_nothing: bool) -> ExitCode {
+    let format_name = format.or_else(|| path.extension().map(|ext| ext.to_string_lossy().to_string()));
+    let Some(format) = format_name.as_deref().and_then(ImportFormat::from_name) else {
+        error!("Unknown import format. Use --format with json, ndjson or csv.");
+        return ExitCode::FAILURE
+    };
+
+    let body = match std::fs::read(path) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Could not read {}: {e}", path.display());
+            return ExitCode::FAILURE
+        }
+    };
+
+    let rows = match user_import::parse_users(&body, format) {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Import document could not be read: {e:?}");
+            return ExitCode::FAILURE
+        }
+    };
+
+    let report = user_service.import_users(rows, all_or_nothing, &AuditContext::cli()).await;
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+
+    if report.failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use crate::configuration::{RateLimit, TokenBucket};
+    use crate::health::test::{config, FakeDependency};
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_health_and_metrics_are_not_rate_limited() {
+        let bucket = TokenBucket { capacity: 1, refill_per_second: 0.01 };
+        let limiter = RateLimiter::new(&RateLimit { reads: bucket, writes: bucket }, Arc::new(InMemoryStore::default()));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(limiter))
+                .app_data(web::Data::new(HealthChecker::new(config(&[]), FakeDependency::up(), FakeDependency::up())))
+                .app_data(web::Data::new(Metrics::new()))
+                .configure(routes)
+        ).await;
+        let request = |uri: &str| TestRequest::get().uri(uri).peer_addr("10.0.0.1:5000".parse().unwrap()).to_request();
+
+        call_service(&app, request("/api-keys")).await;
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, call_service(&app, request("/api-keys")).await.status());
+
+        // Probes and scrapes from the same address still get through.
+        for uri in ["/health/live", "/health/ready", "/metrics"] {
+            assert_eq!(StatusCode::OK, call_service(&app, request(uri)).await.status(), "{uri}");
+        }
+    }
 }
@@ -0,0 +1,197 @@
+use std::time::{Duration, Instant};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder};
+
+/// Route label of requests that matched no route, so that unknown paths don't create new series.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Prometheus metrics of the service. Created once at startup and shared with handlers as app data,
+/// and with the components it measures.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    upstream_request_duration: HistogramVec,
+    upstream_errors: IntCounterVec,
+    database_operation_duration: HistogramVec,
+    cache_requests: IntCounterVec
+}
+
+impl Metrics {
+
+    /// Create and register every metric, including process stats where the platform has them.
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Handled HTTP requests."),
+            &["method", "route", "status"]
+        ).unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Time to answer HTTP requests."),
+            &["method", "route", "status"]
+        ).unwrap();
+        let upstream_request_duration = HistogramVec::new(
+            HistogramOpts::new("jsonplaceholder_request_duration_seconds", "Time of requests to JsonPlaceholder."),
+            &["operation", "status"]
+        ).unwrap();
+        let upstream_errors = IntCounterVec::new(
+            Opts::new("jsonplaceholder_errors_total", "Failed requests to JsonPlaceholder, by status or \"connection\"."),
+            &["operation", "status"]
+        ).unwrap();
+        let database_operation_duration = HistogramVec::new(
+            HistogramOpts::new("mongodb_operation_duration_seconds", "Time of MongoDB operations."),
+            &["operation", "outcome"]
+        ).unwrap();
+        let cache_requests = IntCounterVec::new(
+            Opts::new("cache_requests_total", "Cache lookups by hit or miss."),
+            &["cache", "result"]
+        ).unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(http_request_duration.clone())).unwrap();
+        registry.register(Box::new(upstream_request_duration.clone())).unwrap();
+        registry.register(Box::new(upstream_errors.clone())).unwrap();
+        registry.register(Box::new(database_operation_duration.clone())).unwrap();
+        registry.register(Box::new(cache_requests.clone())).unwrap();
+        #[cfg(target_os = "linux")]
+        registry.register(Box::new(prometheus::process_collector::ProcessCollector::for_self())).unwrap();
+
+        Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            upstream_request_duration,
+            upstream_errors,
+            database_operation_duration,
+            cache_requests
+        }
+    }
+
+    /// Record a handled HTTP request.
+    ///
+    /// ## Arguments.
+    /// * `method` - Request method.
+    /// * `route` - Matched route pattern, e.g. "/users/{id}".
+    /// * `status` - Response status code.
+    /// * `elapsed` - Time to answer.
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        let status = status.to_string();
+        let labels = [method, route, status.as_str()];
+        self.http_requests.with_label_values(&labels).inc();
+        self.http_request_duration.with_label_values(&labels).observe(elapsed.as_secs_f64());
+    }
+
+    /// Record a request to JsonPlaceholder. Anything but a success status counts as an error.
+    ///
+    /// ## Arguments.
+    /// * `operation` - What the request was for, e.g. "get_user".
+    /// * `status` - Response status code, `None` if no response was received.
+    /// * `elapsed` - Time until the response or failure.
+    pub fn observe_upstream(&self, operation: &str, status: Option<u16>, elapsed: Duration) {
+        let status_label = status.map_or("connection".to_string(), |status| status.to_string());
+        self.upstream_request_duration.with_label_values(&[operation, &status_label]).observe(elapsed.as_secs_f64());
+        if !status.is_some_and(|status| (200..300).contains(&status)) {
+            self.upstream_errors.with_label_values(&[operation, &status_label]).inc();
+        }
+    }
+
+    /// Record a MongoDB operation.
+    ///
+    /// ## Arguments.
+    /// * `operation` - Repository operation, e.g. "get_user".
+    /// * `success` - Whether the database answered. Not finding anything counts as success.
+    /// * `elapsed` - Time of the operation.
+    pub fn observe_database(&self, operation: &str, success: bool, elapsed: Duration) {
+        let outcome = if success { "success" } else { "error" };
+        self.database_operation_duration.with_label_values(&[operation, outcome]).observe(elapsed.as_secs_f64());
+    }
+
+    /// Record a cache lookup.
+    ///
+    /// ## Arguments.
+    /// * `cache` - Name of the cache, e.g. "jwks".
+    /// * `hit` - Whether the lookup was answered without loading.
+    pub fn record_cache(&self, cache: &str, hit: bool) {
+        self.cache_requests.with_label_values(&[cache, if hit { "hit" } else { "miss" }]).inc();
+    }
+
+    /// Every metric in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Middleware recording count and latency of every request by method, route and status.
+/// Wrap it around everything else, so that the final status is recorded.
+pub async fn record_metrics(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(metrics) = req.app_data::<web::Data<Metrics>>().cloned() else {
+        return next.call(req).await
+    };
+    let method = req.method().to_string();
+    let started = Instant::now();
+
+    let result = next.call(req).await;
+    let (route, status) = match &result {
+        Ok(res) => (res.request().match_pattern(), res.status()),
+        Err(e) => (None, e.as_response_error().status_code())
+    };
+    metrics.observe_request(&method, route.as_deref().unwrap_or(UNMATCHED_ROUTE), status.as_u16(), started.elapsed());
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{App, get, HttpResponse};
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use super::*;
+
+    #[get("/users/{id}")]
+    async fn user() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn test_record_metrics() {
+        let metrics = web::Data::new(Metrics::new());
+        let app = init_service(
+            App::new().app_data(metrics.clone()).wrap(from_fn(record_metrics)).service(user)
+        ).await;
+
+        call_service(&app, TestRequest::get().uri("/users/1").to_request()).await;
+        call_service(&app, TestRequest::get().uri("/users/2").to_request()).await;
+        call_service(&app, TestRequest::get().uri("/made/up").to_request()).await;
+
+        let text = metrics.encode();
+        assert!(text.contains("http_requests_total{method=\"GET\",route=\"/users/{id}\",status=\"200\"} 2"));
+        assert!(text.contains("http_requests_total{method=\"GET\",route=\"unmatched\",status=\"404\"} 1"));
+        assert!(text.contains("http_request_duration_seconds_count{method=\"GET\",route=\"/users/{id}\",status=\"200\"} 2"));
+    }
+
+    #[test]
+    fn test_observe_upstream() {
+        let metrics = Metrics::new();
+        metrics.observe_upstream("get_user", Some(200), Duration::from_millis(20));
+        metrics.observe_upstream("get_user", Some(404), Duration::from_millis(10));
+        metrics.observe_upstream("get_users", None, Duration::from_millis(5));
+
+        let text = metrics.encode();
+        assert!(text.contains("jsonplaceholder_request_duration_seconds_count{operation=\"get_user\",status=\"200\"} 1"));
+        assert!(!text.contains("jsonplaceholder_errors_total{operation=\"get_user\",status=\"200\"}"));
+        assert!(text.contains("jsonplaceholder_errors_total{operation=\"get_user\",status=\"404\"} 1"));
+        assert!(text.contains("jsonplaceholder_errors_total{operation=\"get_users\",status=\"connection\"} 1"));
+    }
+}
@@ -0,0 +1,32 @@
+use actix_web::{get, HttpResponse, web};
+use actix_web::http::header::ContentType;
+use crate::metrics::Metrics;
+
+/// Metrics in the Prometheus text format, for scraping.
+#[get("/metrics")]
+pub async fn get_metrics(metrics: web::Data<Metrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType(prometheus::TEXT_FORMAT.parse().unwrap()))
+        .body(metrics.encode())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use actix_web::App;
+    use actix_web::http::header::CONTENT_TYPE;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_get_metrics() {
+        let metrics = web::Data::new(Metrics::new());
+        metrics.observe_database("get_user", true, Duration::from_millis(3));
+        let app = init_service(App::new().app_data(metrics).service(get_metrics)).await;
+
+        let response = call_service(&app, TestRequest::get().uri("/metrics").to_request()).await;
+        assert_eq!("text/plain; version=0.0.4", response.headers().get(CONTENT_TYPE).unwrap());
+        let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        assert!(body.contains("mongodb_operation_duration_seconds_count{operation=\"get_user\",outcome=\"success\"} 1"));
+    }
+}
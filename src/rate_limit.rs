@@ -0,0 +1,554 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use actix_web::{HttpMessage, HttpRequest, web};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use futures::future::{BoxFuture, FutureExt};
+use log::warn;
+use crate::api_error::ApiError;
+use crate::authorization::Principal;
+use crate::configuration::{RateLimit, TokenBucket};
+
+/// Headers from the IETF `RateLimit` header fields draft.
+pub const RATE_LIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+pub const RATE_LIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+pub const RATE_LIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+/// Most clients tracked by the in-memory store. The least recently seen half is dropped when it is reached.
+const MAX_BUCKETS: usize = 10_000;
+
+/// How often the in-memory store drops buckets that have refilled, as they are the same as new ones.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Routes sharing the same limits.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum RouteGroup {
+    Reads,
+    Writes
+}
+
+/// Outcome of a single request against its bucket.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Time until the bucket is full again.
+    pub reset: Duration,
+    /// Time until the next request is allowed. Zero when allowed.
+    pub retry_after: Duration
+}
+
+/// Storage of token buckets. The in-memory store suits a single instance, shared stores
+/// such as Redis can be plugged in for multiple instances.
+pub trait RateLimitStore: Send + Sync {
+
+    /// Take a single token from the bucket of `key`, creating a full bucket if missing.
+    ///
+    /// ## Arguments.
+    /// * `key` - Client and route group.
+    /// * `bucket` - Limits of the bucket.
+    /// * `now` - Time of the request.
+    fn take<'a>(&'a self, key: &'a str, bucket: TokenBucket, now: Instant) -> BoxFuture<'a, Decision>;
+
+    /// Decide as `take` would, without taking a token.
+    ///
+    /// ## Arguments.
+    /// * `key` - Client and route group.
+    /// * `bucket` - Limits of the bucket.
+    /// * `now` - Time of the request.
+    fn peek<'a>(&'a self, key: &'a str, bucket: TokenBucket, now: Instant) -> BoxFuture<'a, Decision>;
+}
+
+/// Token buckets kept in process memory.
+#[derive(Default)]
+pub struct InMemoryStore {
+    buckets: Mutex<Buckets>
+}
+
+/// Buckets by key and when they were last swept.
+#[derive(Default)]
+struct Buckets {
+    states: HashMap<String, BucketState>,
+    last_sweep: Option<Instant>
+}
+
+/// Tokens left in a bucket at a point in time.
+#[derive(Debug, Clone, Copy)]
+struct BucketState {
+    tokens: f64,
+    updated: Instant,
+    /// Time the bucket is full again, making it safe to forget. `None` if it never refills.
+    full_at: Option<Instant>
+}
+
+/// Rate limits and the store they are tracked in. Registered as app data.
+pub struct RateLimiter {
+    /// Swapped when configuration is reloaded.
+    limits: RwLock<RateLimit>,
+    store: Arc<dyn RateLimitStore>
+}
+
+impl RouteGroup {
+
+    /// Route group of a request method.
+    ///
+    /// ## Arguments.
+    /// * `method` - Request method.
+    pub fn from_method(method: &Method) -> Self {
+        if [Method::GET, Method::HEAD, Method::OPTIONS].contains(method) {
+            RouteGroup::Reads
+        } else {
+            RouteGroup::Writes
+        }
+    }
+}
+
+impl fmt::Display for RouteGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteGroup::Reads => write!(f, "reads"),
+            RouteGroup::Writes => write!(f, "writes")
+        }
+    }
+}
+
+impl Decision {
+
+    /// Add `RateLimit-*` headers describing this decision.
+    ///
+    /// ## Arguments.
+    /// * `headers` - Response headers.
+    pub fn insert_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(RATE_LIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(RATE_LIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(RATE_LIMIT_RESET, HeaderValue::from(ceil_seconds(self.reset)));
+    }
+}
+
+impl BucketState {
+
+    /// Refill the bucket up to `now` and try to take a single token.
+    fn take(&mut self, bucket: TokenBucket, now: Instant) -> Decision {
+        let capacity = f64::from(bucket.capacity);
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * bucket.refill_per_second).min(capacity);
+        self.updated = now;
+
+        let allowed = self.tokens >= 1.0;
+        if allowed {
+            self.tokens -= 1.0;
+        }
+
+        let seconds_until = |tokens: f64| {
+            if tokens <= 0.0 || bucket.refill_per_second <= 0.0 {
+                Duration::ZERO
+            } else {
+                Duration::from_secs_f64(tokens / bucket.refill_per_second)
+            }
+        };
+
+        let reset = seconds_until(capacity - self.tokens);
+        self.full_at = (bucket.refill_per_second > 0.0).then_some(now + reset);
+
+        Decision {
+            allowed,
+            limit: bucket.capacity,
+            remaining: self.tokens.floor() as u32,
+            reset,
+            retry_after: if allowed { Duration::ZERO } else { seconds_until(1.0 - self.tokens) }
+        }
+    }
+}
+
+impl Buckets {
+
+    /// Drop refilled buckets once per `SWEEP_INTERVAL` and make room when `MAX_BUCKETS` is reached.
+    ///
+    /// ## Arguments.
+    /// * `now` - Time of the request.
+    fn evict(&mut self, now: Instant) {
+        if self.last_sweep.is_none_or(|last_sweep| now.saturating_duration_since(last_sweep) >= SWEEP_INTERVAL) {
+            self.states.retain(|_, state| state.full_at.is_none_or(|full_at| full_at > now));
+            self.last_sweep = Some(now);
+        }
+
+        if self.states.len() >= MAX_BUCKETS {
+            // Dropping half at once keeps the cost per new client low during floods.
+            let mut updated: Vec<Instant> = self.states.values().map(|state| state.updated).collect();
+            let (_, median, _) = updated.select_nth_unstable(MAX_BUCKETS / 2);
+            let cutoff = *median;
+            self.states.retain(|_, state| state.updated > cutoff);
+            warn!("Rate limit store reached {MAX_BUCKETS} clients, forgot the least recently seen half.");
+        }
+    }
+}
+
+impl RateLimitStore for InMemoryStore {
+    fn take<'a>(&'a self, key: &'a str, bucket: TokenBucket, now: Instant) -> BoxFuture<'a, Decision> {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // Forget idle clients so memory stays bounded.
+        if !buckets.states.contains_key(key) {
+            buckets.evict(now);
+        }
+
+        let state = buckets.states
+            .entry(key.to_string())
+            .or_insert(BucketState { tokens: f64::from(bucket.capacity), updated: now, full_at: Some(now) })
+        ;
+
+        let decision = state.take(bucket, now);
+        futures::future::ready(decision).boxed()
+    }
+
+    fn peek<'a>(&'a self, key: &'a str, bucket: TokenBucket, now: Instant) -> BoxFuture<'a, Decision> {
+        let mut state = self.buckets.lock().unwrap().states.get(key).copied()
+            .unwrap_or(BucketState { tokens: f64::from(bucket.capacity), updated: now, full_at: Some(now) })
+        ;
+        futures::future::ready(state.take(bucket, now)).boxed()
+    }
+}
+
+impl RateLimiter {
+
+    /// Create a rate limiter.
+    ///
+    /// ## Arguments.
+    /// * `config` - Limits per route group.
+    /// * `store` - Storage of token buckets.
+    pub fn new(config: &RateLimit, store: Arc<dyn RateLimitStore>) -> Self {
+        RateLimiter {
+            limits: RwLock::new(config.clone()),
+            store
+        }
+    }
+
+    /// Replace the limits. Buckets keep their tokens, so clients are not reset.
+    ///
+    /// ## Arguments.
+    /// * `config` - New limits per route group.
+    pub fn set_limits(&self, config: &RateLimit) {
+        *self.limits.write().unwrap() = config.clone();
+    }
+
+    /// Take a token for the client from the bucket of the route group.
+    ///
+    /// ## Arguments.
+    /// * `client` - Client key, by caller or IP address.
+    /// * `group` - Route group of the request.
+    pub async fn check(&self, client: &str, group: RouteGroup) -> Decision {
+        self.store.take(&format!("{group}:{client}"), self.bucket(group), Instant::now()).await
+    }
+
+    /// Check whether the client has a token left in the bucket of the route group, without taking it.
+    ///
+    /// ## Arguments.
+    /// * `client` - Client key, by caller or IP address.
+    /// * `group` - Route group of the request.
+    pub async fn peek(&self, client: &str, group: RouteGroup) -> Decision {
+        self.store.peek(&format!("{group}:{client}"), self.bucket(group), Instant::now()).await
+    }
+
+    /// Current limits of the route group.
+    fn bucket(&self, group: RouteGroup) -> TokenBucket {
+        let limits = self.limits.read().unwrap();
+        match group {
+            RouteGroup::Reads => limits.reads,
+            RouteGroup::Writes => limits.writes
+        }
+    }
+}
+
+/// Key of the authenticated caller of a request, by token subject or API key.
+/// `None` before authentication and when authentication is disabled.
+fn caller_key(req: &HttpRequest) -> Option<String> {
+    req.extensions()
+        .get::<Principal>()
+        .filter(|principal| !principal.unrestricted)
+        .map(|principal| format!("subject:{}", principal.subject))
+}
+
+/// Key of the peer IP address of a request.
+fn ip_key(req: &ServiceRequest) -> String {
+    match req.peer_addr() {
+        Some(address) => format!("ip:{}", address.ip()),
+        None => "ip:unknown".to_string()
+    }
+}
+
+/// Seconds rounded up, so clients never retry too early.
+fn ceil_seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// Answer a request over the limit of the client with `429 Too Many Requests`.
+fn too_many_requests(req: ServiceRequest, client: &str, decision: Decision) -> ServiceResponse<BoxBody> {
+    warn!("Rate limit exceeded for {client}. Responding with 429.");
+    let mut response = req.error_response(ApiError::TooManyRequests(ceil_seconds(decision.retry_after)));
+    decision.insert_headers(response.headers_mut());
+    response
+}
+
+/// Middleware limiting requests without an authenticated caller by peer IP address. Wrap it around
+/// authentication, so that failed authentications count too. Authenticated requests count against the
+/// limits of their caller instead, see `rate_limit_callers`, so callers behind a shared proxy do not
+/// throttle each other. Once an address used up its limits all of its requests are refused before
+/// authentication. Requests over the limit are answered with `429 Too Many Requests` and `Retry-After`.
+/// Requests pass through untouched when no `RateLimiter` is registered as app data.
+pub async fn rate_limit(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let Some(limiter) = req.app_data::<web::Data<RateLimiter>>().cloned() else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body)
+    };
+    let client = ip_key(&req);
+    let group = RouteGroup::from_method(req.method());
+
+    let decision = limiter.peek(&client, group).await;
+    if !decision.allowed {
+        return Ok(too_many_requests(req, &client, decision))
+    }
+
+    // Whether the request was authenticated is only known once it has been handled.
+    let mut response = next.call(req).await?.map_into_boxed_body();
+    if caller_key(response.request()).is_none() {
+        limiter.check(&client, group).await.insert_headers(response.headers_mut());
+    }
+    Ok(response)
+}
+
+/// Middleware limiting authenticated requests by caller, wrapped inside authentication. Callers spread over
+/// several addresses share their limits. Requests without an authenticated caller pass through.
+pub async fn rate_limit_callers(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let (Some(limiter), Some(client)) = (req.app_data::<web::Data<RateLimiter>>().cloned(), caller_key(req.request())) else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body)
+    };
+
+    let decision = limiter.check(&client, RouteGroup::from_method(req.method())).await;
+    if !decision.allowed {
+        return Ok(too_many_requests(req, &client, decision))
+    }
+
+    let mut response = next.call(req).await?.map_into_boxed_body();
+    decision.insert_headers(response.headers_mut());
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{App, get, HttpResponse, post};
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::RETRY_AFTER;
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use super::*;
+
+    const BUCKET: TokenBucket = TokenBucket { capacity: 2, refill_per_second: 0.5 };
+
+    #[get("/limited")]
+    async fn limited_get() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[post("/limited")]
+    async fn limited_post() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[test]
+    fn test_route_group_from_method() {
+        assert_eq!(RouteGroup::Reads, RouteGroup::from_method(&Method::GET));
+        assert_eq!(RouteGroup::Writes, RouteGroup::from_method(&Method::PATCH));
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_store_take_and_refill() {
+        let store = InMemoryStore::default();
+        let start = Instant::now();
+
+        let first = store.take("client", BUCKET, start).await;
+        assert!(first.allowed);
+        assert_eq!(1, first.remaining);
+        assert_eq!(Duration::from_secs(2), first.reset);
+
+        assert!(store.take("client", BUCKET, start).await.allowed);
+
+        let denied = store.take("client", BUCKET, start).await;
+        assert!(!denied.allowed);
+        assert_eq!(0, denied.remaining);
+        assert_eq!(Duration::from_secs(2), denied.retry_after);
+
+        // Another client has its own bucket.
+        assert!(store.take("other", BUCKET, start).await.allowed);
+
+        // One token is back after two seconds.
+        let refilled = store.take("client", BUCKET, start + Duration::from_secs(2)).await;
+        assert!(refilled.allowed);
+        assert_eq!(0, refilled.remaining);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_store_eviction() {
+        let store = InMemoryStore::default();
+        let start = Instant::now();
+        let tracked = |key: &str| store.buckets.lock().unwrap().states.contains_key(key);
+
+        // Refilled buckets are dropped by the next sweep, others are kept.
+        store.take("refilled", BUCKET, start).await;
+        store.take("slow", TokenBucket { capacity: 2, refill_per_second: 0.001 }, start).await;
+        store.take("new", BUCKET, start + SWEEP_INTERVAL).await;
+        assert!(!tracked("refilled"));
+        assert!(tracked("slow"));
+
+        // The least recently seen half is dropped at the limit.
+        for i in 0..MAX_BUCKETS as u64 {
+            store.take(&format!("client-{i}"), BUCKET, start + SWEEP_INTERVAL + Duration::from_micros(i)).await;
+        }
+        assert!(store.buckets.lock().unwrap().states.len() <= MAX_BUCKETS / 2 + 1);
+        assert!(!tracked("client-0"));
+        assert!(tracked(&format!("client-{}", MAX_BUCKETS - 1)));
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limit_middleware() {
+        let config = RateLimit {
+            reads: BUCKET,
+            writes: TokenBucket { capacity: 1, refill_per_second: 0.1 }
+        };
+        let limiter = web::Data::new(RateLimiter::new(&config, Arc::new(InMemoryStore::default())));
+        let app = init_service(
+            App::new()
+                .app_data(limiter)
+                .wrap(from_fn(rate_limit))
+                .service(limited_get)
+                .service(limited_post)
+        ).await;
+
+        let request = || TestRequest::get().uri("/limited").peer_addr("10.0.0.1:5000".parse().unwrap());
+
+        let response = call_service(&app, request().to_request()).await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("2", response.headers().get(RATE_LIMIT_LIMIT).unwrap());
+        assert_eq!("1", response.headers().get(RATE_LIMIT_REMAINING).unwrap());
+
+        assert_eq!(StatusCode::OK, call_service(&app, request().to_request()).await.status());
+
+        let response = call_service(&app, request().to_request()).await;
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, response.status());
+        assert_eq!("2", response.headers().get(RETRY_AFTER).unwrap());
+        assert_eq!("0", response.headers().get(RATE_LIMIT_REMAINING).unwrap());
+
+        // Writes and other clients are limited separately.
+        let req = TestRequest::post().uri("/limited").peer_addr("10.0.0.1:5000".parse().unwrap()).to_request();
+        assert_eq!(StatusCode::OK, call_service(&app, req).await.status());
+
+        let req = TestRequest::get().uri("/limited").peer_addr("10.0.0.2:5000".parse().unwrap()).to_request();
+        assert_eq!(StatusCode::OK, call_service(&app, req).await.status());
+    }
+
+    #[actix_web::test]
+    async fn test_set_limits() {
+        let config = RateLimit { reads: TokenBucket { capacity: 1, refill_per_second: 0.1 }, writes: BUCKET };
+        let limiter = RateLimiter::new(&config, Arc::new(InMemoryStore::default()));
+        assert!(limiter.check("client", RouteGroup::Reads).await.allowed);
+        assert!(!limiter.check("client", RouteGroup::Reads).await.allowed);
+
+        limiter.set_limits(&RateLimit { reads: TokenBucket { capacity: 5, refill_per_second: 0.1 }, writes: BUCKET });
+        let decision = limiter.check("client", RouteGroup::Reads).await;
+        assert_eq!(5, decision.limit);
+        assert!(!decision.allowed, "Tokens are kept when limits change.");
+        assert_eq!(5, limiter.check("other", RouteGroup::Reads).await.limit);
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limit_callers() {
+        let config = RateLimit { reads: TokenBucket { capacity: 1, refill_per_second: 0.1 }, writes: BUCKET };
+        let limiter = web::Data::new(RateLimiter::new(&config, Arc::new(InMemoryStore::default())));
+        let app = init_service(
+            App::new()
+                .app_data(limiter)
+                .wrap(from_fn(rate_limit_callers))
+                .wrap(from_fn(|req: ServiceRequest, next: Next<BoxBody>| async move {
+                    let subject = req.headers().get("x-test-subject").unwrap().to_str().unwrap().to_string();
+                    req.extensions_mut().insert(Principal { subject, roles: Default::default(), user_id: None, client_certificate: None, unrestricted: false });
+                    next.call(req).await
+                }))
+                .service(limited_get)
+        ).await;
+
+        // Same address, different callers.
+        for subject in ["first", "second"] {
+            let req = TestRequest::get()
+                .uri("/limited")
+                .peer_addr("10.0.0.1:5000".parse().unwrap())
+                .insert_header(("x-test-subject", subject))
+                .to_request();
+            assert_eq!(StatusCode::OK, call_service(&app, req).await.status());
+        }
+
+        let req = TestRequest::get()
+            .uri("/limited")
+            .peer_addr("10.0.0.2:5000".parse().unwrap())
+            .insert_header(("x-test-subject", "first"))
+            .to_request();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, call_service(&app, req).await.status());
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limit_callers_behind_one_address() {
+        let config = RateLimit { reads: TokenBucket { capacity: 1, refill_per_second: 0.1 }, writes: BUCKET };
+        let limiter = web::Data::new(RateLimiter::new(&config, Arc::new(InMemoryStore::default())));
+        let app = init_service(
+            App::new()
+                .app_data(limiter)
+                .wrap(from_fn(rate_limit_callers))
+                .wrap(from_fn(|req: ServiceRequest, next: Next<BoxBody>| async move {
+                    let subject = req.headers().get("x-test-subject").unwrap().to_str().unwrap().to_string();
+                    req.extensions_mut().insert(Principal { subject, roles: Default::default(), user_id: None, client_certificate: None, unrestricted: false });
+                    next.call(req).await
+                }))
+                .wrap(from_fn(rate_limit))
+                .service(limited_get)
+        ).await;
+        let request = |subject: &str| TestRequest::get()
+            .uri("/limited")
+            .peer_addr("10.0.0.1:5000".parse().unwrap())
+            .insert_header(("x-test-subject", subject))
+            .to_request()
+        ;
+
+        // Callers behind the same proxy only spend their own tokens.
+        for subject in ["first", "second", "third"] {
+            assert_eq!(StatusCode::OK, call_service(&app, request(subject)).await.status());
+        }
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, call_service(&app, request("first")).await.status());
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limit_failed_authentication() {
+        let config = RateLimit { reads: BUCKET, writes: BUCKET };
+        let limiter = web::Data::new(RateLimiter::new(&config, Arc::new(InMemoryStore::default())));
+        let app = init_service(
+            App::new()
+                .app_data(limiter)
+                .wrap(from_fn(rate_limit))
+                .service(
+                    web::scope("")
+                        .wrap(from_fn(rate_limit_callers))
+                        .wrap(from_fn(|req: ServiceRequest, _: Next<BoxBody>| async move {
+                            Ok(req.error_response(ApiError::Unauthorized("Invalid API key.".to_string())))
+                        }))
+                        .service(limited_get)
+                )
+        ).await;
+
+        let request = || TestRequest::get().uri("/limited").peer_addr("10.0.0.1:5000".parse().unwrap()).to_request();
+        assert_eq!(StatusCode::UNAUTHORIZED, call_service(&app, request()).await.status());
+        assert_eq!(StatusCode::UNAUTHORIZED, call_service(&app, request()).await.status());
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, call_service(&app, request()).await.status());
+    }
+}
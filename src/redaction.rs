@@ -0,0 +1,144 @@
+use serde_json::Value;
+use crate::authorization::Principal;
+use crate::configuration::{Redaction, RedactionAction, RedactionRule};
+use crate::user::User;
+
+/// Placeholder for redacted values.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// View of the user as seen by the principal, with the fields it may not see redacted or hidden.
+///
+/// ## Arguments.
+/// * `policy` - Redaction rules.
+/// * `user` - User to be shown.
+/// * `principal` - Caller of the request.
+///
+/// ## Returns.
+/// The user as JSON value ready for serialization.
+pub fn redact_user(policy: &Redaction, user: &User, principal: &Principal) -> Value {
+    let mut value = serde_json::to_value(user).unwrap();
+
+    for rule in policy.rules.iter().filter(|rule| !is_exempt(rule, user, principal)) {
+        apply_rule(&mut value, rule);
+    }
+
+    value
+}
+
+/// Redact every user of a list.
+///
+/// ## Arguments.
+/// * `policy` - Redaction rules.
+/// * `users` - Users to be shown.
+/// * `principal` - Caller of the request.
+pub fn redact_users(policy: &Redaction, users: &[User], principal: &Principal) -> Vec<Value> {
+    users.iter().map(|user| redact_user(policy, user, principal)).collect()
+}
+
+/// Check if the rule does not apply to the principal.
+fn is_exempt(rule: &RedactionRule, user: &User, principal: &Principal) -> bool {
+    let roles: Vec<&str> = rule.exempt_roles.iter().map(String::as_str).collect();
+    principal.unrestricted
+        || principal.has_any_role(&roles)
+        || (rule.exempt_self && principal.is_self(user.id.as_deref()))
+}
+
+/// Apply a single rule to the user value. Rules pointing to missing fields are ignored.
+fn apply_rule(value: &mut Value, rule: &RedactionRule) {
+    let (parent_path, field) = match rule.field.rsplit_once('.') {
+        Some((parent_path, field)) => (Some(parent_path), field),
+        None => (None, rule.field.as_str())
+    };
+
+    let parent = match parent_path {
+        Some(path) => path.split('.').try_fold(value, |value, key| value.get_mut(key)),
+        None => Some(value)
+    };
+    let Some(parent) = parent.and_then(Value::as_object_mut) else {
+        return
+    };
+
+    match rule.action {
+        RedactionAction::Hide => {
+            parent.remove(field);
+        },
+        RedactionAction::Redact => {
+            if let Some(target) = parent.get_mut(field) {
+                redact_value(target);
+            }
+        }
+    }
+}
+
+/// Replace every leaf of the value with the placeholder, keeping the shape of objects and arrays.
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::Object(object) => object.values_mut().for_each(redact_value),
+        Value::Array(array) => array.iter_mut().for_each(redact_value),
+        Value::Null => {},
+        _ => *value = Value::String(REDACTED.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use super::*;
+
+    fn principal(roles: &[&str], user_id: Option<&str>) -> Principal {
+        Principal {
+            subject: "TEST_SUBJECT".to_string(),
+            roles: roles.iter().map(|role| role.to_string()).collect::<HashSet<_>>(),
+            user_id: user_id.map(str::to_string),
+            client_certificate: None,
+            unrestricted: false
+        }
+    }
+
+    #[test]
+    fn test_redact_user_default_policy() {
+        let user = User::_create_test_user(Some("7".to_string()));
+        let value = redact_user(&Redaction::default(), &user, &principal(&["user"], Some("8")));
+
+        assert_eq!(REDACTED, value["phone"]);
+        assert_eq!(REDACTED, value["email"]);
+        assert_eq!(REDACTED, value["address"]["geo"]["lat"]);
+        assert_eq!("Testington", value["address"]["city"]);
+        assert_eq!("TESTER", value["name"]);
+    }
+
+    #[test]
+    fn test_redact_user_exemptions() {
+        let user = User::_create_test_user(Some("7".to_string()));
+        let expected = serde_json::to_value(&user).unwrap();
+        let policy = Redaction::default();
+
+        assert_eq!(expected, redact_user(&policy, &user, &principal(&["admin"], None)));
+        assert_eq!(expected, redact_user(&policy, &user, &principal(&["user"], Some("7"))));
+        assert_eq!(expected, redact_user(&policy, &user, &Principal::unrestricted()));
+    }
+
+    #[test]
+    fn test_redact_user_hide() {
+        let policy = Redaction {
+            rules: vec![
+                RedactionRule { field: "address.geo".to_string(), action: RedactionAction::Hide, exempt_roles: vec![], exempt_self: false },
+                RedactionRule { field: "company.missing.field".to_string(), action: RedactionAction::Hide, exempt_roles: vec![], exempt_self: false }
+            ]
+        };
+        let user = User::_create_test_user(Some("7".to_string()));
+        let value = redact_user(&policy, &user, &principal(&["admin"], Some("7")));
+
+        assert!(value["address"].get("geo").is_none());
+        assert_eq!("Testington", value["address"]["city"]);
+        assert_eq!("123456789", value["phone"]);
+    }
+
+    #[test]
+    fn test_redact_user_without_id_is_not_self() {
+        let user = User::_create_test_user(None);
+        let value = redact_user(&Redaction::default(), &user, &principal(&["user"], None));
+
+        assert_eq!(REDACTED, value["email"]);
+    }
+}
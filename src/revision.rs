@@ -0,0 +1,135 @@
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+use crate::audit::AuditContext;
+use crate::user::User;
+
+/// Actor of revisions taken from JsonPlaceholder.
+pub const UPSTREAM_ACTOR: &str = "jsonplaceholder";
+
+/// Actor of baseline revisions of users created before revisions were tracked.
+pub const UNKNOWN_ACTOR: &str = "unknown";
+
+/// Full snapshot of a user after a change, as stored in the `revisions` collection.
+/// Revision numbers start from 1 for every user.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Revision {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub user_id: String,
+    pub revision: u32,
+    /// Missing for baseline revisions that existed before revisions were tracked.
+    pub timestamp: Option<DateTime>,
+    pub actor: String,
+    pub request_id: Option<String>,
+    pub user: User
+}
+
+/// Revision as shown by the REST apis. Timestamp is RFC 3339.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct RevisionInfo {
+    pub revision: u32,
+    pub timestamp: Option<String>,
+    pub actor: String,
+    pub request_id: Option<String>,
+    pub user: User
+}
+
+impl Revision {
+
+    /// Create a revision of a change made now.
+    ///
+    /// ## Arguments.
+    /// * `revision` - Revision number.
+    /// * `user` - User after the change. Must have an id.
+    /// * `context` - Who made the change.
+    pub fn new(revision: u32, user: &User, context: &AuditContext) -> Self {
+        Revision {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id: user.id.clone().unwrap(),
+            revision,
+            timestamp: Some(DateTime::now()),
+            actor: context.actor.clone(),
+            request_id: context.request_id.clone(),
+            user: user.clone()
+        }
+    }
+
+    /// Revision 1 of a user that existed before revisions were tracked.
+    ///
+    /// ## Arguments.
+    /// * `user` - User as it was. Must have an id.
+    /// * `actor` - Origin of the user, e.g. `UPSTREAM_ACTOR`.
+    pub fn baseline(user: &User, actor: &str) -> Self {
+        Revision {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id: user.id.clone().unwrap(),
+            revision: 1,
+            timestamp: None,
+            actor: actor.to_string(),
+            request_id: None,
+            user: user.clone()
+        }
+    }
+
+    /// Public view of the revision.
+    pub fn info(&self) -> RevisionInfo {
+        RevisionInfo {
+            revision: self.revision,
+            timestamp: self.timestamp.map(|timestamp| timestamp.try_to_rfc3339_string().unwrap_or_default()),
+            actor: self.actor.clone(),
+            request_id: self.request_id.clone(),
+            user: self.user.clone()
+        }
+    }
+}
+
+/// Find the revision that was current at the given time.
+///
+/// ## Arguments.
+/// * `revisions` - Revisions of a single user in revision order.
+/// * `as_of` - Point in time.
+///
+/// ## Returns.
+/// The latest revision made at or before `as_of`. Baseline revisions count as always existing.
+pub fn revision_as_of(revisions: &[Revision], as_of: DateTime) -> Option<&Revision> {
+    revisions.iter().rfind(|revision| revision.timestamp.is_none_or(|timestamp| timestamp <= as_of))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn revision(number: u32, timestamp: Option<i64>) -> Revision {
+        let mut user = User::_create_test_user(Some("101".to_string()));
+        user.name = format!("NAME {number}");
+        Revision {
+            revision: number,
+            timestamp: timestamp.map(DateTime::from_millis),
+            ..Revision::baseline(&user, UPSTREAM_ACTOR)
+        }
+    }
+
+    #[test]
+    fn test_revision_as_of() {
+        let revisions = vec![revision(1, None), revision(2, Some(1000)), revision(3, Some(2000))];
+
+        assert_eq!(1, revision_as_of(&revisions, DateTime::from_millis(999)).unwrap().revision);
+        assert_eq!(2, revision_as_of(&revisions, DateTime::from_millis(1000)).unwrap().revision);
+        assert_eq!(3, revision_as_of(&revisions, DateTime::from_millis(5000)).unwrap().revision);
+    }
+
+    #[test]
+    fn test_revision_as_of_before_creation() {
+        let revisions = vec![revision(1, Some(1000))];
+        assert_eq!(None, revision_as_of(&revisions, DateTime::from_millis(999)));
+    }
+
+    #[test]
+    fn test_info() {
+        let info = revision(2, Some(0)).info();
+
+        assert_eq!(2, info.revision);
+        assert_eq!(Some("1970-01-01T00:00:00Z".to_string()), info.timestamp);
+        assert_eq!("NAME 2", info.user.name);
+    }
+}
@@ -0,0 +1,71 @@
+use std::{fmt, fs, io};
+use serde::{Deserialize, Serialize, Serializer};
+
+/// Shown instead of secrets.
+pub const MASK: &str = "****";
+
+/// Value that must not end up in logs, e.g. a password or a connection string with credentials.
+/// `Debug`, `Display` and `Serialize` show a mask, only `expose` gives the value.
+#[derive(Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Secret(value.into())
+    }
+
+    /// Read a secret from a file, e.g. a mounted Docker or Kubernetes secret.
+    ///
+    /// ## Arguments.
+    /// * `path` - File holding the secret. A trailing line break is not part of the secret.
+    pub fn from_file(path: &str) -> io::Result<Self> {
+        let value = fs::read_to_string(path)?;
+        Ok(Secret(value.trim_end_matches(['\r', '\n']).to_string()))
+    }
+
+    /// The actual value. Don't log it.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(MASK)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(MASK)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(MASK)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_secret_is_masked() {
+        let secret = Secret::new("hunter2");
+
+        assert_eq!("hunter2", secret.expose());
+        assert_eq!(MASK, secret.to_string());
+        assert_eq!(MASK, format!("{secret:?}"));
+        assert_eq!("\"****\"", serde_json::to_string(&secret).unwrap());
+        assert_eq!(secret, serde_json::from_str("\"hunter2\"").unwrap());
+    }
+
+    #[test]
+    fn test_from_file() {
+        assert_eq!("test_pass", Secret::from_file("testdata/secrets/mongo_password").unwrap().expose());
+        assert!(Secret::from_file("MADE_UP_PATH").is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use std::future::Future;
+use actix_web::dev::Server;
+use actix_web::web;
+use log::{info, warn};
+use tokio::signal::unix::{signal, SignalKind};
+use crate::health::HealthChecker;
+
+/// Wait for `SIGTERM` or `SIGINT`.
+pub async fn signal_received() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => Some(terminate),
+        Err(e) => {
+            warn!("Could not listen for SIGTERM, only SIGINT stops the server: {e}");
+            None
+        }
+    };
+
+    tokio::select! {
+        Some(()) = async { terminate.as_mut()?.recv().await } => info!("Received SIGTERM, shutting down."),
+        Ok(()) = tokio::signal::ctrl_c() => info!("Received SIGINT, shutting down."),
+        else => {
+            warn!("Could not listen for SIGTERM or SIGINT, the server only stops when killed.");
+            std::future::pending().await
+        }
+    }
+}
+
+/// Run the server until `stop` resolves, then shut down gracefully: readiness fails, new connections are
+/// refused and in-flight requests get the server's shutdown timeout to finish.
+/// Build the server with `disable_signals`, so that it leaves signals to `stop`.
+///
+/// ## Arguments.
+/// * `server` - Server to run.
+/// * `stop` - Resolves when the server should stop, e.g. `signal_received()`.
+/// * `health_checker` - Readiness checks of the server.
+pub async fn run_until(server: Server, stop: impl Future<Output = ()>, health_checker: web::Data<HealthChecker>) -> std::io::Result<()> {
+    let handle = server.handle();
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => return result,
+        () = stop => ()
+    }
+
+    health_checker.shut_down();
+    info!("Draining in-flight requests...");
+    let (_, result) = tokio::join!(handle.stop(true), server);
+    info!("All requests finished.");
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use actix_web::{App, get, HttpResponse, HttpServer};
+    use tokio::sync::{Notify, oneshot};
+    use crate::health::Status;
+    use crate::health::test::{config, FakeDependency};
+    use super::*;
+
+    #[get("/slow")]
+    async fn slow(started: web::Data<Notify>) -> HttpResponse {
+        started.notify_one();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        HttpResponse::Ok().body("done")
+    }
+
+    #[actix_web::test]
+    async fn test_in_flight_request_completes() {
+        let started = web::Data::new(Notify::new());
+        let app_started = started.clone();
+        let server = HttpServer::new(move || App::new().app_data(app_started.clone()).service(slow))
+            .workers(1)
+            .disable_signals()
+            .shutdown_timeout(5)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let url = format!("http://{}/slow", server.addrs()[0]);
+        let health_checker = web::Data::new(HealthChecker::new(config(&[]), FakeDependency::up(), FakeDependency::up()));
+        let (stop, stopped) = oneshot::channel::<()>();
+        let running = actix_web::rt::spawn(run_until(server.run(), async { stopped.await.unwrap_or_default() }, health_checker.clone()));
+
+        let request = actix_web::rt::spawn(reqwest::get(url.clone()));
+        started.notified().await;
+        stop.send(()).unwrap();
+
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(200, response.status().as_u16());
+        assert_eq!("done", response.text().await.unwrap());
+        running.await.unwrap().unwrap();
+
+        assert_eq!(Status::Down, health_checker.readiness().await.status);
+        assert!(reqwest::get(url).await.is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::time::SystemTime;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderMap;
+use actix_web::middleware::Next;
+use futures::future::BoxFuture;
+use mongodb::bson::DateTime;
+use opentelemetry::{Context, global, KeyValue};
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::{SpanId, Status, TraceError, TracerProvider as _};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::runtime;
+use opentelemetry_sdk::trace::{Config, Sampler, Tracer, TracerProvider};
+use serde_json::{json, Map, Value};
+use tracing::{field, info_span, Instrument, Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+use crate::configuration::{TraceExporter, Tracing};
+
+/// Name of the tracer creating our spans.
+const TRACER_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// Name of the span every request runs in. Its fields are added to log lines.
+pub const REQUEST_SPAN: &str = "http_request";
+
+/// Layer turning `tracing` spans into OpenTelemetry spans, exported as configured. Call once at startup,
+/// from within the Tokio runtime.
+///
+/// ## Arguments.
+/// * `config` - Tracing configuration.
+pub fn layer<S>(config: &Tracing) -> Result<OpenTelemetryLayer<S, Tracer>, TraceError>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>
+{
+    let provider = tracer_provider(config)?;
+    let tracer = provider.tracer(TRACER_NAME);
+    global::set_tracer_provider(provider);
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Export spans that are still buffered and stop exporting. Spans ended afterwards are dropped.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+/// Create a provider exporting to the configured destination.
+///
+/// ## Arguments.
+/// * `config` - Tracing configuration.
+fn tracer_provider(config: &Tracing) -> Result<TracerProvider, TraceError> {
+    // Callers decide for the traces they started.
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+    let builder = TracerProvider::builder().with_config(
+        Config::default()
+            .with_sampler(sampler)
+            .with_resource(Resource::new([KeyValue::new("service.name", config.service_name.clone())]))
+    );
+
+    let builder = match config.exporter {
+        TraceExporter::Otlp => {
+            let exporter = opentelemetry_otlp::new_exporter().http().with_endpoint(&config.endpoint).build_span_exporter()?;
+            builder.with_batch_exporter(exporter, runtime::Tokio)
+        },
+        TraceExporter::File => {
+            let path = config.file.as_deref().unwrap_or_default();
+            let exporter = JsonSpanExporter::file(path).map_err(|e| TraceError::from(format!("Could not open {path}: {e}")))?;
+            builder.with_simple_exporter(exporter)
+        },
+        TraceExporter::Stdout => builder.with_simple_exporter(JsonSpanExporter::new(Box::new(io::stdout())))
+    };
+    Ok(builder.build())
+}
+
+/// Writes every span as a single line of JSON, for local testing without a collector.
+pub struct JsonSpanExporter {
+    output: Box<dyn Write + Send + Sync>
+}
+
+impl JsonSpanExporter {
+    pub fn new(output: Box<dyn Write + Send + Sync>) -> Self {
+        JsonSpanExporter { output }
+    }
+
+    /// Append spans to a file, creating it if needed.
+    ///
+    /// ## Arguments.
+    /// * `path` - Output file.
+    pub fn file(path: &str) -> io::Result<Self> {
+        Ok(Self::new(Box::new(File::options().create(true).append(true).open(path)?)))
+    }
+}
+
+impl fmt::Debug for JsonSpanExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("JsonSpanExporter")
+    }
+}
+
+impl SpanExporter for JsonSpanExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let result = batch.iter()
+            .try_for_each(|span| writeln!(self.output, "{}", span_to_json(span)))
+            .and_then(|_| self.output.flush())
+            .map_err(|e| TraceError::Other(Box::new(e)))
+        ;
+        Box::pin(std::future::ready(result))
+    }
+}
+
+/// A finished span as JSON.
+fn span_to_json(span: &SpanData) -> Value {
+    let attributes: Map<String, Value> = span.attributes.iter()
+        .map(|attribute| (attribute.key.to_string(), attribute_to_json(&attribute.value)))
+        .collect()
+    ;
+    let status = match &span.status {
+        Status::Unset => Value::Null,
+        Status::Ok => json!("ok"),
+        Status::Error { description } => json!(format!("error: {description}"))
+    };
+
+    json!({
+        "trace_id": span.span_context.trace_id().to_string(),
+        "span_id": span.span_context.span_id().to_string(),
+        "parent_span_id": (span.parent_span_id != SpanId::INVALID).then(|| span.parent_span_id.to_string()),
+        "name": span.name,
+        "kind": format!("{:?}", span.span_kind).to_lowercase(),
+        "start": rfc3339(span.start_time),
+        "end": rfc3339(span.end_time),
+        "duration_ms": span.end_time.duration_since(span.start_time).unwrap_or_default().as_secs_f64() * 1000.0,
+        "attributes": attributes,
+        "status": status
+    })
+}
+
+fn attribute_to_json(value: &opentelemetry::Value) -> Value {
+    match value {
+        opentelemetry::Value::Bool(value) => json!(value),
+        opentelemetry::Value::I64(value) => json!(value),
+        opentelemetry::Value::F64(value) => json!(value),
+        value => json!(value.to_string())
+    }
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    DateTime::from_system_time(time).try_to_rfc3339_string().unwrap_or_default()
+}
+
+/// Incoming request headers as a source of W3C trace context.
+struct RequestHeaders<'a>(&'a HeaderMap);
+
+impl Extractor for RequestHeaders<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Outgoing request headers as a target of W3C trace context.
+struct OutgoingHeaders<'a>(&'a mut reqwest::header::HeaderMap);
+
+impl Injector for OutgoingHeaders<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (reqwest::header::HeaderName::from_bytes(key.as_bytes()), value.parse()) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Trace context sent by the caller in `traceparent` and `tracestate` headers.
+///
+/// ## Arguments.
+/// * `headers` - Incoming request headers.
+///
+/// ## Returns.
+/// Context to continue the trace in, empty if the caller sent none or it is malformed.
+pub fn extract_context(headers: &HeaderMap) -> Context {
+    TraceContextPropagator::new().extract(&RequestHeaders(headers))
+}
+
+/// Add `traceparent` and `tracestate` headers continuing the trace of `span`.
+///
+/// ## Arguments.
+/// * `span` - Span of the outgoing request.
+/// * `headers` - Outgoing request headers.
+pub fn inject_context(span: &Span, headers: &mut reqwest::header::HeaderMap) {
+    TraceContextPropagator::new().inject_context(&span.context(), &mut OutgoingHeaders(headers));
+}
+
+/// Middleware running every request in a server span. The trace of the caller is continued when it sent
+/// a `traceparent` header. The request id and caller are recorded in the span once known.
+pub async fn trace_requests(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let method = req.method().to_string();
+    let route = req.match_pattern();
+    let span = info_span!(
+        REQUEST_SPAN,
+        otel.name = route.as_ref().map_or(method.clone(), |route| format!("{method} {route}")),
+        otel.kind = "server",
+        otel.status_code = field::Empty,
+        http.method = method.as_str(),
+        http.target = req.path(),
+        http.route = route.as_deref(),
+        http.status_code = field::Empty,
+        request_id = field::Empty,
+        user_id = field::Empty
+    );
+    span.set_parent(extract_context(req.headers()));
+
+    let result = next.call(req).instrument(span.clone()).await;
+    let status = match &result {
+        Ok(res) => res.status(),
+        Err(e) => e.as_response_error().status_code()
+    };
+    span.record("http.status_code", i64::from(status.as_u16()));
+    if status.is_server_error() {
+        span.record("otel.status_code", "ERROR");
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use actix_web::{App, get, HttpResponse, web};
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use httpmock::Method::GET;
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_subscriber::layer::SubscriberExt;
+    use crate::user_client::{JsonPlaceholderClient, UserClient};
+    use super::*;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const PARENT_ID: &str = "00f067aa0ba902b7";
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[get("/users/{id}")]
+    async fn user(client: web::Data<JsonPlaceholderClient>) -> HttpResponse {
+        match client.get_user("1").await {
+            Ok(user) => HttpResponse::Ok().json(user),
+            Err(_) => HttpResponse::BadGateway().finish()
+        }
+    }
+
+    #[test]
+    fn test_extract_context() {
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent".parse().unwrap(), TRACEPARENT.parse().unwrap());
+
+        let context = extract_context(&headers);
+        let span = context.span();
+        assert_eq!(TRACE_ID, span.span_context().trace_id().to_string());
+        assert_eq!(PARENT_ID, span.span_context().span_id().to_string());
+
+        assert!(!extract_context(&HeaderMap::new()).span().span_context().is_valid());
+    }
+
+    #[actix_web::test]
+    async fn test_trace_continues_upstream() {
+        let path = std::env::temp_dir().join(format!("traces-{}.jsonl", uuid::Uuid::new_v4()));
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(JsonSpanExporter::file(&path.to_string_lossy()).unwrap())
+            .build()
+        ;
+        let subscriber = tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(provider.tracer(TRACER_NAME)));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mock_server = httpmock::MockServer::start();
+        let upstream_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/users/1")
+                .matches(|req| req.headers.as_ref().is_some_and(|headers| {
+                    headers.iter().any(|(name, value)| name == "traceparent" && value.contains(TRACE_ID) && !value.contains(PARENT_ID))
+                }));
+            then.status(200)
+                .header("content-type", "application/json")
+                .body_from_file("testdata/get_user_response.json");
+        });
+
+        let client = web::Data::new(JsonPlaceholderClient::new(&mock_server.url("")));
+        let app = init_service(App::new().app_data(client).wrap(from_fn(trace_requests)).service(user)).await;
+        let request = TestRequest::get().uri("/users/1").insert_header(("traceparent", TRACEPARENT)).to_request();
+        assert!(call_service(&app, request).await.status().is_success());
+        upstream_mock.assert();
+
+        provider.force_flush();
+        let spans: Vec<Value> = fs::read_to_string(&path).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+        ;
+        let server = spans.iter().find(|span| span["name"] == "GET /users/{id}").unwrap();
+        assert_eq!(TRACE_ID, server["trace_id"]);
+        assert_eq!(PARENT_ID, server["parent_span_id"]);
+        assert_eq!("server", server["kind"]);
+        assert_eq!(200, server["attributes"]["http.status_code"]);
+
+        let upstream = spans.iter().find(|span| span["name"] == "get_user").unwrap();
+        assert_eq!(TRACE_ID, upstream["trace_id"]);
+        assert_eq!(server["span_id"], upstream["parent_span_id"]);
+        assert_eq!("client", upstream["kind"]);
+
+        fs::remove_file(path).unwrap();
+    }
+}
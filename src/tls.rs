@@ -0,0 +1,393 @@
+use std::any::Any;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use actix_tls::accept::rustls_0_21::TlsStream;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::TcpStream;
+use log::{info, warn};
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use rustls::server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientHello, ResolvesServerCert};
+use rustls::sign::{self, CertifiedKey};
+use sha2::{Digest, Sha256};
+use crate::configuration::Tls;
+
+/// Possible errors thrown by `tls` functions.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum TlsError {
+    /// File could not be read.
+    Io(String),
+    InvalidCertificate(String),
+    InvalidKey(String)
+}
+
+/// Verified client certificate of a mutual TLS connection.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct ClientCertificate {
+    /// Common name of the subject, or the whole subject if there is none.
+    pub subject: String,
+    /// SHA-256 of the DER encoded certificate as hex.
+    pub fingerprint: String
+}
+
+/// Certificate with the modification time of its files when it was loaded.
+struct LoadedCertificate {
+    key: Arc<CertifiedKey>,
+    modified: Option<SystemTime>
+}
+
+/// Serves the certificate from `cert_file` and `key_file`, swapping it when the files change.
+/// Connections already open keep the certificate they were started with.
+pub struct CertificateResolver {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    state: RwLock<LoadedCertificate>
+}
+
+impl ClientCertificate {
+
+    /// Read identity from a DER encoded certificate.
+    ///
+    /// ## Arguments.
+    /// * `der` - Certificate already verified by rustls.
+    ///
+    /// ## Returns.
+    /// The identity, or `None` if the certificate could not be parsed.
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(der).ok()?;
+        let subject = certificate.subject();
+        let common_name = subject.iter_common_name()
+            .next()
+            .and_then(|common_name| common_name.as_str().ok())
+            .map(str::to_string)
+        ;
+
+        Some(ClientCertificate {
+            subject: common_name.unwrap_or_else(|| subject.to_string()),
+            fingerprint: hex::encode(Sha256::digest(der))
+        })
+    }
+}
+
+impl CertificateResolver {
+
+    /// Load the certificate for the first time.
+    ///
+    /// ## Arguments.
+    /// * `cert_file` - PEM certificate chain, server certificate first.
+    /// * `key_file` - PEM private key.
+    pub fn new(cert_file: &str, key_file: &str) -> Result<Self, TlsError> {
+        let cert_file = PathBuf::from(cert_file);
+        let key_file = PathBuf::from(key_file);
+        let modified = modified(&cert_file, &key_file);
+        let key = load_certified_key(&cert_file, &key_file)?;
+
+        Ok(CertificateResolver {
+            cert_file,
+            key_file,
+            state: RwLock::new(LoadedCertificate { key: Arc::new(key), modified })
+        })
+    }
+
+    /// Reload the certificate if its files changed since the last load. A certificate that fails
+    /// to load leaves the previous one in use.
+    ///
+    /// ## Returns.
+    /// A result containing whether the certificate was swapped, or the error of the failed load.
+    pub fn reload_if_changed(&self) -> Result<bool, TlsError> {
+        let modified = modified(&self.cert_file, &self.key_file);
+        if modified == self.state.read().unwrap().modified {
+            return Ok(false)
+        }
+
+        let key = load_certified_key(&self.cert_file, &self.key_file);
+        let mut state = self.state.write().unwrap();
+        // Do not retry a broken pair until the files change again.
+        state.modified = modified;
+        state.key = Arc::new(key?);
+        Ok(true)
+    }
+
+    /// Check the certificate files for changes until the server stops.
+    ///
+    /// ## Arguments.
+    /// * `interval` - Time between checks.
+    pub async fn watch(self: Arc<Self>, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            match self.reload_if_changed() {
+                Ok(true) => info!("Reloaded TLS certificate from {}.", self.cert_file.display()),
+                Ok(false) => (),
+                Err(e) => warn!("Could not reload TLS certificate, keeping the previous one: {e:?}")
+            }
+        }
+    }
+
+    /// Certificate currently served.
+    pub fn current(&self) -> Arc<CertifiedKey> {
+        self.state.read().unwrap().key.clone()
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+/// Build the rustls server configuration.
+///
+/// ## Arguments.
+/// * `tls` - TLS configuration.
+///
+/// ## Returns.
+/// A result containing the server configuration and its certificate resolver, which should be
+/// `watch`ed for certificate changes, or an error.
+pub fn server_config(tls: &Tls) -> Result<(ServerConfig, Arc<CertificateResolver>), TlsError> {
+    let resolver = Arc::new(CertificateResolver::new(&tls.cert_file, &tls.key_file)?);
+    let builder = ServerConfig::builder().with_safe_defaults();
+
+    let builder = match &tls.client_auth {
+        Some(client_auth) => {
+            let roots = load_root_store(Path::new(&client_auth.ca_file))?;
+            let verifier = if client_auth.required {
+                AllowAnyAuthenticatedClient::new(roots).boxed()
+            } else {
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()
+            };
+            builder.with_client_cert_verifier(verifier)
+        },
+        None => builder.with_no_client_auth()
+    };
+
+    Ok((builder.with_cert_resolver(resolver.clone()), resolver))
+}
+
+/// Connection callback storing the verified client certificate, if any, as `ClientCertificate`
+/// in the connection data. Plain HTTP connections are left as is.
+///
+/// ## Arguments.
+/// * `connection` - Accepted connection.
+/// * `extensions` - Connection data, available to requests through `conn_data`.
+pub fn on_connect(connection: &dyn Any, extensions: &mut Extensions) {
+    let Some(stream) = connection.downcast_ref::<TlsStream<TcpStream>>() else {
+        return
+    };
+
+    let (_, session) = stream.get_ref();
+    let certificate = session.peer_certificates()
+        .and_then(|certificates| certificates.first())
+        .and_then(|certificate| ClientCertificate::from_der(&certificate.0))
+    ;
+
+    if let Some(certificate) = certificate {
+        extensions.insert(certificate);
+    }
+}
+
+/// Latest modification time of the two files, `None` if either can not be read.
+fn modified(cert_file: &Path, key_file: &Path) -> Option<SystemTime> {
+    let cert_modified = std::fs::metadata(cert_file).and_then(|metadata| metadata.modified()).ok()?;
+    let key_modified = std::fs::metadata(key_file).and_then(|metadata| metadata.modified()).ok()?;
+    Some(cert_modified.max(key_modified))
+}
+
+/// Open a file for PEM parsing.
+fn open(path: &Path) -> Result<BufReader<File>, TlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| TlsError::Io(format!("{}: {e}", path.display())))
+}
+
+/// Read every certificate from a PEM file.
+fn load_certificates(path: &Path) -> Result<Vec<Certificate>, TlsError> {
+    let certificates = rustls_pemfile::certs(&mut open(path)?)
+        .map_err(|e| TlsError::InvalidCertificate(format!("{}: {e}", path.display())))?
+    ;
+
+    if certificates.is_empty() {
+        return Err(TlsError::InvalidCertificate(format!("{}: no certificates found", path.display())))
+    }
+
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+/// Read the first private key from a PEM file.
+fn load_private_key(path: &Path) -> Result<PrivateKey, TlsError> {
+    let items = rustls_pemfile::read_all(&mut open(path)?)
+        .map_err(|e| TlsError::InvalidKey(format!("{}: {e}", path.display())))?
+    ;
+
+    items.into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key) | rustls_pemfile::Item::RSAKey(key) | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None
+        })
+        .ok_or(TlsError::InvalidKey(format!("{}: no private key found", path.display())))
+}
+
+/// Load a certificate chain and its private key.
+fn load_certified_key(cert_file: &Path, key_file: &Path) -> Result<CertifiedKey, TlsError> {
+    let certificates = load_certificates(cert_file)?;
+    let key = sign::any_supported_type(&load_private_key(key_file)?)
+        .map_err(|e| TlsError::InvalidKey(format!("{}: {e}", key_file.display())))?
+    ;
+
+    Ok(CertifiedKey::new(certificates, key))
+}
+
+/// Load trusted client certificate authorities.
+fn load_root_store(path: &Path) -> Result<RootCertStore, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certificates(path)? {
+        roots.add(&certificate).map_err(|e| TlsError::InvalidCertificate(format!("{}: {e}", path.display())))?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use actix_web::{App, get, HttpRequest, HttpResponse, HttpServer};
+    use rustls::{ClientConfig, ServerName};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+    use super::*;
+    use crate::authorization;
+    use crate::configuration::ClientAuth;
+
+    const CA_FILE: &str = "testdata/tls/ca.pem";
+    const SERVER_CERT: &str = "testdata/tls/server.pem";
+    const SERVER_KEY: &str = "testdata/tls/server_key.pem";
+    const CLIENT_CERT: &str = "testdata/tls/client.pem";
+    const CLIENT_KEY: &str = "testdata/tls/client_key.pem";
+
+    fn tls(client_auth: Option<ClientAuth>) -> Tls {
+        Tls {
+            cert_file: SERVER_CERT.to_string(),
+            key_file: SERVER_KEY.to_string(),
+            reload_interval_seconds: 30,
+            client_auth
+        }
+    }
+
+    #[test]
+    fn test_client_certificate_from_der() {
+        let certificate = load_certificates(Path::new(CLIENT_CERT)).unwrap().remove(0);
+        let identity = ClientCertificate::from_der(&certificate.0).unwrap();
+
+        assert_eq!("showcase-client", identity.subject);
+        assert_eq!(64, identity.fingerprint.len());
+        assert_eq!(None, ClientCertificate::from_der(b"NOT A CERTIFICATE"));
+    }
+
+    #[test]
+    fn test_load_errors() {
+        assert!(matches!(CertificateResolver::new("MADE_UP_PATH", SERVER_KEY), Err(TlsError::Io(_))));
+        assert!(matches!(CertificateResolver::new(SERVER_KEY, SERVER_KEY), Err(TlsError::InvalidCertificate(_))));
+        assert!(matches!(CertificateResolver::new(SERVER_CERT, SERVER_CERT), Err(TlsError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn test_reload_if_changed() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        let cert_file = directory.join("cert.pem");
+        let key_file = directory.join("key.pem");
+        std::fs::copy(SERVER_CERT, &cert_file).unwrap();
+        std::fs::copy(SERVER_KEY, &key_file).unwrap();
+
+        let resolver = CertificateResolver::new(cert_file.to_str().unwrap(), key_file.to_str().unwrap()).unwrap();
+        let original = resolver.current();
+        assert_eq!(Ok(false), resolver.reload_if_changed());
+
+        // Broken files keep the previous certificate.
+        std::fs::write(&cert_file, "BROKEN").unwrap();
+        touch(&cert_file, 1);
+        assert!(resolver.reload_if_changed().is_err());
+        assert_eq!(original.cert, resolver.current().cert);
+
+        std::fs::copy(CLIENT_CERT, &cert_file).unwrap();
+        std::fs::copy(CLIENT_KEY, &key_file).unwrap();
+        touch(&cert_file, 2);
+        assert_eq!(Ok(true), resolver.reload_if_changed());
+        assert_ne!(original.cert, resolver.current().cert);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    /// Set modification time explicitly, file systems may not tell quick writes apart.
+    fn touch(path: &Path, seconds_ahead: u64) {
+        let modified = SystemTime::now() + Duration::from_secs(seconds_ahead * 60);
+        File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    #[get("/whoami")]
+    async fn whoami(req: HttpRequest) -> HttpResponse {
+        let principal = authorization::principal(&req).unwrap();
+        HttpResponse::Ok().body(principal.client_certificate.map(|certificate| certificate.subject).unwrap_or_default())
+    }
+
+    /// Start a server with the given TLS configuration on a free port.
+    fn start_server(tls: &Tls) -> SocketAddr {
+        let (config, _) = server_config(tls).unwrap();
+        let server = HttpServer::new(|| App::new().service(whoami))
+            .on_connect(on_connect)
+            .workers(1)
+            .bind_rustls_021(("127.0.0.1", 0), config)
+            .unwrap()
+        ;
+        let address = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        address
+    }
+
+    /// Make a `GET /whoami` request, optionally with the test client certificate.
+    async fn request_whoami(address: SocketAddr, with_certificate: bool) -> std::io::Result<String> {
+        let mut roots = RootCertStore::empty();
+        roots.add(&load_certificates(Path::new(CA_FILE)).unwrap()[0]).unwrap();
+        let builder = ClientConfig::builder().with_safe_defaults().with_root_certificates(roots);
+        let config = if with_certificate {
+            let key = load_private_key(Path::new(CLIENT_KEY)).unwrap();
+            builder.with_client_auth_cert(load_certificates(Path::new(CLIENT_CERT)).unwrap(), key).unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        let stream = tokio::net::TcpStream::connect(address).await?;
+        let mut stream = TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await?
+        ;
+        stream.write_all(b"GET /whoami HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    #[actix_web::test]
+    async fn test_client_certificate_optional() {
+        let address = start_server(&tls(Some(ClientAuth { ca_file: CA_FILE.to_string(), required: false, roles: vec![] })));
+
+        let response = request_whoami(address, true).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("showcase-client"));
+
+        let response = request_whoami(address, false).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(!response.contains("showcase-client"));
+    }
+
+    #[actix_web::test]
+    async fn test_client_certificate_required() {
+        let address = start_server(&tls(Some(ClientAuth { ca_file: CA_FILE.to_string(), required: true, roles: vec![] })));
+
+        assert!(request_whoami(address, true).await.unwrap().ends_with("showcase-client"));
+        // A rejected handshake shows up either as an error or as a closed stream, depending on timing.
+        assert!(request_whoami(address, false).await.map_or(true, |response| response.is_empty()));
+    }
+}
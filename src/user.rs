@@ -1,11 +1,12 @@
 use std::collections::HashMap;
+use mongodb::bson::DateTime;
 use serde::{de, Deserialize, Serialize};
 use serde_json::Value;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Eq, Clone)]
 pub struct User {
 
-    #[serde(deserialize_with = "deserialize_id")]
+    #[serde(default, deserialize_with = "deserialize_id")]
     pub id: Option<String>,
     pub name: String,
     pub username: String,
@@ -16,6 +17,13 @@ pub struct User {
     pub company: Company,
 }
 
+/// Soft-deleted user and the time of deletion.
+#[derive(PartialEq, Debug, Clone)]
+pub struct DeletedUser {
+    pub user: User,
+    pub deleted_at: DateTime
+}
+
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub struct Address {
     pub street: String,
@@ -34,6 +42,35 @@ pub struct Company {
 
 impl User {
 
+    /// Check that the user info is complete enough to be saved.
+    ///
+    /// ## Returns.
+    /// An empty `Ok` or a list of human readable validation errors.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = vec![];
+
+        let required = [
+            ("name", &self.name),
+            ("username", &self.username),
+            ("email", &self.email),
+        ];
+        for (field, value) in required {
+            if value.trim().is_empty() {
+                errors.push(format!("Field `{field}` must not be empty."));
+            }
+        }
+
+        if !self.email.trim().is_empty() && !self.email.contains('@') {
+            errors.push("Field `email` is not a valid email address.".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
     /// Create a new user. Meant for testing.
     pub fn _create_test_user(id: Option<String>) -> User {
         User {
@@ -73,4 +110,26 @@ where
         Value::Null => Ok(None),
         _ => Err(de::Error::custom("Invalid type"))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate_success() {
+        assert_eq!(Ok(()), User::_create_test_user(None).validate());
+    }
+
+    #[test]
+    fn test_validate_failure() {
+        let mut user = User::_create_test_user(None);
+        user.name = " ".to_string();
+        user.email = "not-an-email".to_string();
+
+        let errors = user.validate().unwrap_err();
+        assert_eq!(2, errors.len());
+        assert!(errors[0].contains("name"));
+        assert!(errors[1].contains("email"));
+    }
+}
@@ -1,10 +1,17 @@
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use futures::future::{BoxFuture, FutureExt};
 use reqwest::header::{ACCEPT, CONTENT_TYPE};
-use reqwest::StatusCode;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use log::warn;
+use tracing::{field, info_span, Instrument};
 use url::Url;
-use crate::CONFIG;
+use crate::health::Dependency;
+use crate::metrics::Metrics;
+use crate::telemetry;
 use crate::user::User;
 
-/// Possible errors thrown by `user_client` functions.
+/// Possible errors thrown by `UserClient` implementations.
 #[derive(Eq, PartialEq, Debug)]
 pub enum UserClientError {
     UserNotFound(String),
@@ -18,244 +25,290 @@ pub enum UserClientError {
 
 const PATH: &str = "/users";
 
-/// Fetch all users.
-pub async fn get_users() -> Result<Vec<User>, UserClientError> {
-    get_users_with_url(&CONFIG.json_placeholder.url).await
-}
+/// Read access to the upstream users.
+pub trait UserClient: Send + Sync {
 
-/// Fetch all users from the given url.
-///
-/// ## Arguments.
-/// * `url` - Url where users should be fetched. "/users" will be added to the end of base url.
-async fn get_users_with_url(url: &str) -> Result<Vec<User>, UserClientError> {
-
-    // Parse url and handle possible error.
-    let url_result =
-        Url::parse(url).and_then(
-            |url|  url.join(PATH)
-        )
-    ;
-    let url = url_result.map_err(|_| UserClientError::UrlParseError)?;
-
-    // Create client.
-    let client = reqwest::Client::new();
-
-    // Create request and send it.
-    let response = client.get(url)
-        .header(ACCEPT, "application/json")
-        .send()
-        .await
-    ;
-
-    // Check for errors and status-codes other than 200 - OK.
-    if let Err(e) = response {
-        return Err(UserClientError::RestError(e.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)))
-    };
-
-    let response = response.unwrap();
-
-    match response.status() {
-        StatusCode::OK => (),
-        _ => return Err(UserClientError::RestError(response.status()))
-    };
-
-
-    let response_text = response.text().await;
-    // Deserialize and return.
-    match serde_json::from_str(response_text.unwrap().as_str()) {
-        Ok(user) => Ok(user),
-        Err(e) => {
-            println!("{}", e);
-            Err(UserClientError::SerdeError)
-        }
-    }
-}
+    /// Fetch all users.
+    fn get_users(&self) -> BoxFuture<'_, Result<Vec<User>, UserClientError>>;
 
-/// Get user with a specific id.
-///
-/// ## Arguments.
-/// * `id` - Id for the user to be fetched.
-pub async fn get_user(id: String) -> Result<User, UserClientError> {
-    get_user_with_url(id, &CONFIG.json_placeholder.url).await
+    /// Get user with a specific id.
+    ///
+    /// ## Arguments.
+    /// * `id` - Id for the user to be fetched.
+    fn get_user<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<User, UserClientError>>;
 }
 
-/// Get user with a specific id.
-///
-/// ## Arguments.
-/// * `id` - Id for the user to be fetched.
-/// * `url` - Url where users should be fetched. "/users" and the id will be added to the end of base url.
-async fn get_user_with_url(id: String, url: &str) -> Result<User, UserClientError> {
-
-    // Parse url and handle possible errors.
-    let url_result =
-        Url::parse(url).and_then(
-            |url|  url.join(format!("{}/{}", PATH, id).as_str())
-        )
-    ;
-    let url = url_result.map_err(|_| UserClientError::UrlParseError)?;
-
-    // Create client.
-    let client = reqwest::Client::new();
-
-    // Create request and send it.
-    let response = client.get(url)
-        .header(ACCEPT, "application/json")
-        .send()
-        .await
-    ;
-
-    // Check for errors and status-codes other than 200 - OK.
-    if let Err(e) = response {
-        return Err(UserClientError::RestError(e.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)))
-    };
-
-    let response = response.unwrap();
-
-    match response.status() {
-        StatusCode::OK => (),
-        StatusCode::NOT_FOUND => return Err(UserClientError::UserNotFound(id)),
-        _ => return Err(UserClientError::RestError(response.status()))
-    };
-
-
-    // Deserialize and return.
-    match serde_json::from_str(response.text().await.unwrap().as_str()) {
-        Ok(user) => Ok(user),
-        Err(_) => Err(UserClientError::SerdeError)
-    }
+/// Client for the JsonPlaceholder users api.
+pub struct JsonPlaceholderClient {
+    http: reqwest::Client,
+    /// Base url. "/users" will be added to the end of it.
+    url: RwLock<String>,
+    metrics: Arc<Metrics>
 }
 
-/// Post a new user.
-/// Not used. JsonPlaceholder does not really support `POST` or `PATCH`.
-///
-/// ## Arguments.
-/// * `user` - New user info.
-async fn _post_new_user(user: User) -> Result<User, UserClientError> {
-    _post_new_user_with_url(user, &CONFIG.json_placeholder.url).await
-}
+impl JsonPlaceholderClient {
 
-/// Post a new user.
-/// Not used. JsonPlaceholder does not really support `POST` or `PATCH`.
-///
-/// ## Arguments.
-/// * `user` - New user info.
-/// * `url` - Url where user should be posted. "/users" will be added to the end of base url.
-async fn _post_new_user_with_url(user: User, url: &str) -> Result<User, UserClientError> {
+    /// Create a client.
+    ///
+    /// ## Arguments.
+    /// * `url` - Base url of JsonPlaceholder.
+    pub fn new(url: &str) -> Self {
+        JsonPlaceholderClient { http: reqwest::Client::new(), url: RwLock::new(url.to_string()), metrics: Arc::new(Metrics::new()) }
+    }
 
-    // Parse url and handle possible errors.
-    let url_result = Url::parse(url).and_then(
-        |url| url.join(PATH)
-    );
+    /// Record latency and errors of requests in shared metrics.
+    ///
+    /// ## Arguments.
+    /// * `metrics` - Metrics of the service.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
 
-    let url = url_result.map_err(|_| UserClientError::UrlParseError)?;
+    /// Apply a reloaded base url. Requests already sent keep the old one.
+    ///
+    /// ## Arguments.
+    /// * `url` - Base url of JsonPlaceholder.
+    pub fn set_url(&self, url: &str) {
+        *self.url.write().unwrap() = url.to_string();
+    }
 
-    // Create client.
-    let client = reqwest::Client::new();
+    /// Send a request in a client span continuing the current trace, recording its latency and status.
+    ///
+    /// ## Arguments.
+    /// * `operation` - What the request is for, e.g. "get_user".
+    /// * `request` - Request to send.
+    async fn send(&self, operation: &str, request: RequestBuilder) -> reqwest::Result<Response> {
+        let (http, request) = request.build_split();
+        let mut request = request?;
+        let span = info_span!(
+            "jsonplaceholder",
+            otel.name = operation,
+            otel.kind = "client",
+            otel.status_code = field::Empty,
+            http.method = request.method().as_str(),
+            http.url = request.url().as_str(),
+            http.status_code = field::Empty
+        );
+        telemetry::inject_context(&span, request.headers_mut());
 
-    // Create request and send it.
-    let response = client.post(url)
-        .header(CONTENT_TYPE, "application/json")
-        .header(ACCEPT, "application/json")
-        .body(serde_json::to_string(&user).map_err(|_| UserClientError::SerdeError)?)
-        .send()
-        .await
-    ;
+        let started = Instant::now();
+        let response = http.execute(request).instrument(span.clone()).await;
+        let status = response.as_ref().ok().map(|response| response.status());
+        self.metrics.observe_upstream(operation, status.map(|status| status.as_u16()), started.elapsed());
 
-    // Handle possible errors and status codes other than 200 - OK.
-    if let Err(e) = response {
-        return Err(UserClientError::RestError(e.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)))
+        if let Some(status) = status {
+            span.record("http.status_code", i64::from(status.as_u16()));
+        }
+        if !status.is_some_and(|status| status.is_success()) {
+            span.record("otel.status_code", "ERROR");
+        }
+        response
     }
 
-    let response = response.unwrap();
+    /// Base url joined with the given path.
+    fn url(&self, path: &str) -> Result<Url, UserClientError> {
+        Url::parse(&self.url.read().unwrap())
+            .and_then(|url| url.join(path))
+            .map_err(|_| UserClientError::UrlParseError)
+    }
+
+    /// Fetch all users.
+    async fn fetch_users(&self) -> Result<Vec<User>, UserClientError> {
+        let url = self.url(PATH)?;
+
+        // Create request and send it.
+        let response = self.send("get_users", self.http.get(url)
+            .header(ACCEPT, "application/json")
+        ).await;
 
-    match response.status() {
-        StatusCode::OK => (),
-        _ => return Err(UserClientError::RestError(response.status()))
+        // Check for errors and status-codes other than 200 - OK.
+        if let Err(e) = response {
+            return Err(UserClientError::RestError(e.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)))
+        };
+
+        let response = response.unwrap();
+
+        match response.status() {
+            StatusCode::OK => (),
+            _ => return Err(UserClientError::RestError(response.status()))
+        };
+
+
+        let response_text = response.text().await;
+        // Deserialize and return.
+        match serde_json::from_str(response_text.unwrap().as_str()) {
+            Ok(user) => Ok(user),
+            Err(e) => {
+                warn!("Could not read JsonPlaceholder response: {e}");
+                Err(UserClientError::SerdeError)
+            }
+        }
     }
 
-    // Deserialize and return.
-    match serde_json::from_str(response.text().await.unwrap().as_str()) {
-        Ok(user) => Ok(user),
-        Err(e) => {
-            println!("{}", e);
-            Err(UserClientError::SerdeError)
+    /// Get user with a specific id.
+    ///
+    /// ## Arguments.
+    /// * `id` - Id for the user to be fetched.
+    async fn fetch_user(&self, id: &str) -> Result<User, UserClientError> {
+        let url = self.url(format!("{}/{}", PATH, id).as_str())?;
+
+        // Create request and send it.
+        let response = self.send("get_user", self.http.get(url)
+            .header(ACCEPT, "application/json")
+        ).await;
+
+        // Check for errors and status-codes other than 200 - OK.
+        if let Err(e) = response {
+            return Err(UserClientError::RestError(e.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)))
+        };
+
+        let response = response.unwrap();
+
+        match response.status() {
+            StatusCode::OK => (),
+            StatusCode::NOT_FOUND => return Err(UserClientError::UserNotFound(id.to_string())),
+            _ => return Err(UserClientError::RestError(response.status()))
+        };
+
+
+        // Deserialize and return.
+        match serde_json::from_str(response.text().await.unwrap().as_str()) {
+            Ok(user) => Ok(user),
+            Err(_) => Err(UserClientError::SerdeError)
         }
     }
-}
 
-/// Update an existing user info.
-/// Not used. JsonPlaceholder does not really support `POST` or `PATCH`.
-///
-/// ## Arguments.
-/// * `user` - Updated user info.
-pub async fn _update_existing_user(user: User) -> Result<User, UserClientError> {
-    if let None = &user.id {
-        return Err(UserClientError::_NoIdError);
-    };
-    _update_existing_user_with_url(user, &CONFIG.json_placeholder.url).await
-}
+    /// Post a new user.
+    /// Not used. JsonPlaceholder does not really support `POST` or `PATCH`.
+    ///
+    /// ## Arguments.
+    /// * `user` - New user info.
+    async fn _post_new_user(&self, user: User) -> Result<User, UserClientError> {
+        let url = self.url(PATH)?;
+
+        // Create request and send it.
+        let response = self.send("post_user", self.http.post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(ACCEPT, "application/json")
+            .body(serde_json::to_string(&user).map_err(|_| UserClientError::SerdeError)?)
+        ).await;
+
+        // Handle possible errors and status codes other than 200 - OK.
+        if let Err(e) = response {
+            return Err(UserClientError::RestError(e.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)))
+        }
 
-/// Update an existing user info.
-/// Not used. JsonPlaceholder does not really support `POST` or `PATCH`.
-///
-///
-/// ## Arguments.
-/// * `user` - Updated user info.
-/// * `url` - Url where users should be fetched. "/users" will be added to the end of base url.
-async fn _update_existing_user_with_url(user: User, url: &str) -> Result<User, UserClientError> {
+        let response = response.unwrap();
 
-    // Parse url and handle possible errors.
-    let url_result = Url::parse(url).and_then(
-        |url| url.join(PATH)
-    );
+        match response.status() {
+            StatusCode::OK => (),
+            _ => return Err(UserClientError::RestError(response.status()))
+        }
 
-    let url = url_result.map_err(|_| UserClientError::UrlParseError)?;
+        // Deserialize and return.
+        match serde_json::from_str(response.text().await.unwrap().as_str()) {
+            Ok(user) => Ok(user),
+            Err(e) => {
+                warn!("Could not read JsonPlaceholder response: {e}");
+                Err(UserClientError::SerdeError)
+            }
+        }
+    }
+
+    /// Update an existing user info.
+    /// Not used. JsonPlaceholder does not really support `POST` or `PATCH`.
+    ///
+    /// ## Arguments.
+    /// * `user` - Updated user info.
+    async fn _update_existing_user(&self, user: User) -> Result<User, UserClientError> {
+        let url = self.url(PATH)?;
+
+        // Create request and send it.
+        let response = self.send("update_user", self.http.patch(url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(ACCEPT, "application/json")
+            .body(serde_json::to_string(&user).map_err(|_| UserClientError::SerdeError)?)
+        ).await;
+
+        // Handle possible errors and status codes other than 200 - OK.
+        if let Err(e) = response {
+            return Err(UserClientError::RestError(e.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)))
+        }
 
-    // Create client.
-    let client = reqwest::Client::new();
+        let response = response.unwrap();
 
-    // Create request and send it.
-    let response = client.patch(url)
-        .header(CONTENT_TYPE, "application/json")
-        .header(ACCEPT, "application/json")
-        .body(serde_json::to_string(&user).map_err(|_| UserClientError::SerdeError)?)
-        .send()
-        .await
-    ;
+        match response.status() {
+            StatusCode::OK => (),
+            StatusCode::NOT_FOUND => return Err(user.id.map_or(UserClientError::_NoIdError, UserClientError::UserNotFound)),
+            _ => return Err(UserClientError::RestError(response.status()))
+        }
 
-    // Handle possible errors and status codes other than 200 - OK.
-    if let Err(e) = response {
-        return Err(UserClientError::RestError(e.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)))
+        // Deserialize and return.
+        match serde_json::from_str(response.text().await.unwrap().as_str()) {
+            Ok(user) => Ok(user),
+            Err(_) => Err(UserClientError::SerdeError)
+        }
     }
+}
 
-    let response = response.unwrap();
+impl UserClient for JsonPlaceholderClient {
+    fn get_users(&self) -> BoxFuture<'_, Result<Vec<User>, UserClientError>> {
+        self.fetch_users().boxed()
+    }
 
-    match response.status() {
-        StatusCode::OK => (),
-        StatusCode::NOT_FOUND => return Err(UserClientError::UserNotFound(user.id.unwrap().to_string())),
-        _ => return Err(UserClientError::RestError(response.status()))
+    fn get_user<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<User, UserClientError>> {
+        self.fetch_user(id).boxed()
     }
+}
 
-    // Deserialize and return.
-    match serde_json::from_str(response.text().await.unwrap().as_str()) {
-        Ok(user) => Ok(user),
-        Err(_) => Err(UserClientError::SerdeError)
+impl Dependency for JsonPlaceholderClient {
+    fn ping(&self) -> BoxFuture<'_, Result<(), String>> {
+        async move {
+            let url = self.url(PATH).map_err(|e| format!("{e:?}"))?;
+            let response = self.send("ping", self.http.get(url)).await.map_err(|e| e.to_string())?;
+            match response.status() {
+                status if status.is_success() => Ok(()),
+                status => Err(format!("Responded with {status}."))
+            }
+        }.boxed()
     }
 }
 
 #[cfg(test)]
-mod test {
+pub mod test {
     use httpmock::Method::{GET, PATCH, POST};
     use reqwest::header::CONTENT_TYPE;
     use serde_json::json;
     use super::*;
     use crate::user::User;
 
+    /// Upstream users kept in memory.
+    #[derive(Default)]
+    pub struct FakeUserClient {
+        pub users: Vec<User>
+    }
+
+    impl UserClient for FakeUserClient {
+        fn get_users(&self) -> BoxFuture<'_, Result<Vec<User>, UserClientError>> {
+            futures::future::ready(Ok(self.users.clone())).boxed()
+        }
+
+        fn get_user<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<User, UserClientError>> {
+            let user = self.users.iter()
+                .find(|user| user.id.as_deref() == Some(id))
+                .cloned()
+                .ok_or(UserClientError::UserNotFound(id.to_string()))
+            ;
+            futures::future::ready(user).boxed()
+        }
+    }
+
     #[tokio::test]
     async fn test_get_users_faulty_url() {
         assert_eq!(
             Err(UserClientError::UrlParseError),
-            get_users_with_url("THIS IS A FAULTY URL").await
+            JsonPlaceholderClient::new("THIS IS A FAULTY URL").get_users().await
         );
     }
 
@@ -272,7 +325,7 @@ mod test {
 
         assert_eq!(
             Err(UserClientError::RestError(StatusCode::BAD_REQUEST)),
-            get_users_with_url(mock_server.url("").as_str()).await
+            JsonPlaceholderClient::new(mock_server.url("").as_str()).get_users().await
         );
 
         get_users_mock.assert();
@@ -292,7 +345,7 @@ mod test {
 
         assert_eq!(
             Err(UserClientError::SerdeError),
-            get_users_with_url(mock_server.url("").as_str()).await
+            JsonPlaceholderClient::new(mock_server.url("").as_str()).get_users().await
         );
 
         get_users_mock.assert();
@@ -310,13 +363,13 @@ mod test {
                 .body_from_file("testdata/get_users_response.json");
         });
 
-        let response_result = get_users_with_url(mock_server.url("").as_str()).await;
+        let response_result = JsonPlaceholderClient::new(mock_server.url("").as_str()).get_users().await;
         assert!(response_result.is_ok());
 
         let response: Vec<User> = response_result.unwrap();
         assert_eq!(10, response.len());
-        assert!(response.get(0).unwrap().id.is_some());
-        assert_eq!("1".to_string(), response.get(0).unwrap().id.clone().unwrap());
+        assert!(response.first().unwrap().id.is_some());
+        assert_eq!("1".to_string(), response.first().unwrap().id.clone().unwrap());
 
         for (i, user) in response.iter().enumerate() {
             assert_eq!((i as i32 + 1).to_string(), user.id.clone().unwrap());
@@ -325,11 +378,29 @@ mod test {
         get_users_mock.assert();
     }
 
+    #[tokio::test]
+    async fn test_set_url() {
+        let mock_server = httpmock::MockServer::start();
+
+        let get_users_mock = mock_server.mock(|when, then| {
+            when.method(GET);
+            then.status(StatusCode::OK.into())
+                .header(CONTENT_TYPE.as_str(), "application/json")
+                .body_from_file("testdata/get_users_response.json");
+        });
+
+        let client = JsonPlaceholderClient::new("THIS IS A FAULTY URL");
+        client.set_url(mock_server.url("").as_str());
+        assert_eq!(10, client.get_users().await.unwrap().len());
+
+        get_users_mock.assert();
+    }
+
     #[tokio::test]
     async fn test_get_user_faulty_url() {
         assert_eq!(
             Err(UserClientError::UrlParseError),
-            get_user_with_url(String::from("TEST_ID"), "THIS IS A FAULTY URL").await
+            JsonPlaceholderClient::new("THIS IS A FAULTY URL").get_user("TEST_ID").await
         );
     }
 
@@ -347,7 +418,7 @@ mod test {
 
         assert_eq!(
             Err(UserClientError::RestError(StatusCode::BAD_REQUEST)),
-            get_user_with_url("TEST_ID".to_string(), mock_server.url("").as_str()).await
+            JsonPlaceholderClient::new(mock_server.url("").as_str()).get_user("TEST_ID").await
         );
 
         get_users_mock.assert();
@@ -366,7 +437,7 @@ mod test {
 
         assert_eq!(
             Err(UserClientError::UserNotFound(String::from("100"))),
-            get_user_with_url(String::from("100"), mock_server.url("").as_str()).await
+            JsonPlaceholderClient::new(mock_server.url("").as_str()).get_user("100").await
         );
 
         get_user_mock.assert();
@@ -387,7 +458,7 @@ mod test {
 
         assert_eq!(
             Err(UserClientError::SerdeError),
-            get_user_with_url("TEST_ID".to_string(), mock_server.url("").as_str()).await
+            JsonPlaceholderClient::new(mock_server.url("").as_str()).get_user("TEST_ID").await
         );
 
         get_users_mock.assert();
@@ -406,7 +477,7 @@ mod test {
                 .body_from_file("testdata/get_user_response.json");
         });
 
-        let response_result = get_user_with_url("TEST_ID".to_string(), mock_server.url("").as_str()).await;
+        let response_result = JsonPlaceholderClient::new(mock_server.url("").as_str()).get_user("TEST_ID").await;
         assert!(response_result.is_ok());
 
         let response: User = response_result.unwrap();
@@ -421,7 +492,7 @@ mod test {
     async fn test_post_new_user_faulty_url() {
         assert_eq!(
             Err(UserClientError::UrlParseError),
-            _post_new_user_with_url(User::_create_test_user(None), "THIS IS NOT A REAL URL").await
+            JsonPlaceholderClient::new("THIS IS NOT A REAL URL")._post_new_user(User::_create_test_user(None)).await
         );
     }
 
@@ -439,7 +510,7 @@ mod test {
 
         assert_eq!(
             Err(UserClientError::RestError(StatusCode::BAD_REQUEST)),
-            _post_new_user_with_url(User::_create_test_user(None), mock_server.url("").as_str()).await
+            JsonPlaceholderClient::new(mock_server.url("").as_str())._post_new_user(User::_create_test_user(None)).await
         );
 
         post_user_mock.assert();
@@ -460,7 +531,7 @@ mod test {
 
         assert_eq!(
             Err(UserClientError::SerdeError),
-            _post_new_user_with_url(User::_create_test_user(None), mock_server.url("").as_str()).await
+            JsonPlaceholderClient::new(mock_server.url("").as_str())._post_new_user(User::_create_test_user(None)).await
         );
 
         post_user_mock.assert();
@@ -482,7 +553,7 @@ mod test {
                 .body_from_file("testdata/get_user_response.json");
         });
 
-        let response_result = _post_new_user_with_url(new_user_info.clone(), mock_server.url("").as_str()).await;
+        let response_result = JsonPlaceholderClient::new(mock_server.url("").as_str())._post_new_user(new_user_info.clone()).await;
         dbg!(&response_result);
         assert!(response_result.is_ok());
 
@@ -497,7 +568,7 @@ mod test {
     async fn test_update_existing_user_faulty_url() {
         assert_eq!(
             Err(UserClientError::UrlParseError),
-            _update_existing_user_with_url(User::_create_test_user(None), "THIS IS NOT A PROPER URL.").await
+            JsonPlaceholderClient::new("THIS IS NOT A PROPER URL.")._update_existing_user(User::_create_test_user(None)).await
         );
     }
 
@@ -515,7 +586,7 @@ mod test {
 
         assert_eq!(
             Err(UserClientError::RestError(StatusCode::BAD_REQUEST)),
-            _update_existing_user_with_url(User::_create_test_user(None), mock_server.url("").as_str()).await
+            JsonPlaceholderClient::new(mock_server.url("").as_str())._update_existing_user(User::_create_test_user(None)).await
         );
 
         update_user_mock.assert();
@@ -535,7 +606,7 @@ mod test {
 
         assert_eq!(
             Err(UserClientError::UserNotFound(0.to_string())),
-            _update_existing_user_with_url(User::_create_test_user(Some(0.to_string())), mock_server.url("").as_str()).await
+            JsonPlaceholderClient::new(mock_server.url("").as_str())._update_existing_user(User::_create_test_user(Some(0.to_string()))).await
         );
 
         update_user_mock.assert();
@@ -556,7 +627,7 @@ mod test {
 
         assert_eq!(
             Err(UserClientError::SerdeError),
-            _update_existing_user_with_url(User::_create_test_user(None), mock_server.url("").as_str()).await
+            JsonPlaceholderClient::new(mock_server.url("").as_str())._update_existing_user(User::_create_test_user(None)).await
         );
 
         update_user_mock.assert();
@@ -578,7 +649,7 @@ mod test {
                 .body_from_file("testdata/get_user_response.json");
         });
 
-        let response_result = _update_existing_user_with_url(user_info_to_be_updated.clone(), mock_server.url("").as_str()).await;
+        let response_result = JsonPlaceholderClient::new(mock_server.url("").as_str())._update_existing_user(user_info_to_be_updated.clone()).await;
         assert!(response_result.is_ok());
 
         let response = response_result.unwrap();
@@ -1,141 +1,546 @@
-use actix_web::{get, HttpRequest, HttpResponse, patch, post, Responder, web};
+use actix_web::{delete, get, HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder, patch, post, Responder, web};
 use actix_web::http::header::{ACCEPT, CONTENT_TYPE};
+use futures::stream::{self, StreamExt};
 use log::{info, warn};
-use crate::user::User;
-use crate::user_service;
+use mongodb::bson::DateTime;
+use serde::Deserialize;
+use serde_json::Value;
+use crate::api_error::{ApiError, RequestId};
+use crate::audit::{AuditContext, AuditEntryInfo};
+use crate::authorization::{Authorized, Principal, RoleRequirement};
+use crate::configuration::Redaction;
+use crate::content_negotiation::{self, NegotiationError, ResponseFormat};
+use crate::redaction;
+use crate::user::{DeletedUser, User};
+use crate::user_export::{self, ExportFormat};
+use crate::user_import::{ImportFormat, parse_users};
+use crate::user_service::{DatabaseError, UserService};
+
+/// Operations allowed for roles "admin" and "user".
+pub struct Read;
+
+/// Operations allowed for role "admin" only.
+pub struct Write;
+
+impl RoleRequirement for Read {
+    const ROLES: &'static [&'static str] = &["admin", "user"];
+}
+
+impl RoleRequirement for Write {
+    const ROLES: &'static [&'static str] = &["admin"];
+}
+
+/// Query parameters for bulk imports.
+#[derive(Deserialize)]
+pub struct BulkImportQuery {
+    #[serde(default)]
+    all_or_nothing: bool
+}
 
 #[get("/")]
 async fn hello() -> impl Responder {
     HttpResponse::Ok().body("Hello you!")
 }
 
+/// Query parameters for listing users.
+#[derive(Deserialize)]
+pub struct UserListQuery {
+    /// Include soft-deleted users. Admin only.
+    #[serde(default)]
+    include_deleted: bool
+}
+
 #[get("/users")]
-pub async fn get_all_users(req: HttpRequest) -> impl Responder {
-    info!("Incoming request for all users.");
-    if let Err(()) = check_accept_header_json(&req) {
-        warn!("Request missing required headers. Responding with 400.");
-        return HttpResponse::BadRequest().body("Missing or incorrect headers.")
+pub async fn get_all_users(authorized: Authorized<Read>, req: HttpRequest, query: web::Query<UserListQuery>, service: web::Data<UserService>) -> Result<HttpResponse, ApiError> {
+    info!("Incoming request for all users from {}.", authorized.principal.subject);
+    let format = negotiate_response_format(&req)?;
+    if query.include_deleted {
+        require_admin_for_deleted(&authorized.principal)?;
+    }
+
+    let config = service.config();
+    let users = service.get_users().await;
+    let mut users = redaction::redact_users(&config.redaction, &users, &authorized.principal);
+    if query.include_deleted {
+        let deleted_users = service.get_deleted_users().await.map_err(|e| {
+            warn!("Could not get deleted users: {e:?}.");
+            ApiError::from(e)
+        })?;
+        users.extend(deleted_users.iter().map(|deleted| deleted_user_view(deleted, &config.redaction, &authorized.principal)));
     }
-    let users = user_service::get_users().await;
     info!("Found {} users. Responding with 200.", &users.len());
-    HttpResponse::Ok().json(users)
+    respond_with(HttpResponse::Ok(), format, content_negotiation::serialize_list(format, "users", "user", &users))
+}
+
+/// Query parameters for exports.
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    format: Option<String>
+}
+
+#[get("/users/export")]
+pub async fn export_users(authorized: Authorized<Read>, query: web::Query<ExportQuery>, service: web::Data<UserService>) -> Result<HttpResponse, ApiError> {
+    info!("Incoming request to export users from {}.", authorized.principal.subject);
+    let format = ExportFormat::from_name(query.format.as_deref().unwrap_or("ndjson"));
+    let Some(format) = format else {
+        warn!("Unsupported export format. Responding with 400.");
+        return Err(ApiError::BadRequest("Supported formats: ndjson, csv".to_string()))
+    };
+
+    let config = service.config();
+    let principal = authorized.principal;
+    let users = service.stream_users().await
+        .map(move |user| redaction::redact_user(&config.redaction, &user, &principal))
+    ;
+    let body = match format {
+        ExportFormat::Ndjson => users.map(|user| user_export::to_ndjson_line(&user)).boxed(),
+        ExportFormat::Csv => stream::once(async { user_export::csv_header() })
+            .chain(users.map(|user| user_export::to_csv_row(&user)))
+            .boxed()
+    };
+
+    info!("Streaming users. Responding with 200.");
+    Ok(
+        HttpResponse::Ok()
+            .content_type(format.content_type())
+            .streaming(body.map(|chunk| Ok::<_, actix_web::Error>(web::Bytes::from(chunk))))
+    )
+}
+
+/// Query parameters for getting a single user.
+#[derive(Deserialize)]
+pub struct UserQuery {
+    /// RFC 3339 timestamp for point-in-time reads.
+    as_of: Option<String>,
+    /// Return the user even if soft-deleted. Admin only.
+    #[serde(default)]
+    include_deleted: bool
 }
 
 #[get("/users/{id}")]
-pub async fn get_user_with_id(req: HttpRequest, id: web::Path<String>) -> impl Responder {
-    info!("Incoming request for user with id: {id}.");
-    if check_accept_header_json(&req).is_err() {
-        warn!("Request missing required headers. Responding with 400.");
-        return HttpResponse::BadRequest().body("Missing or incorrect headers")
+pub async fn get_user_with_id(authorized: Authorized<Read>, req: HttpRequest, query: web::Query<UserQuery>, id: web::Path<String>, service: web::Data<UserService>) -> Result<HttpResponse, ApiError> {
+    info!("Incoming request for user with id: {id} from {}.", authorized.principal.subject);
+    let format = negotiate_response_format(&req)?;
+
+    let as_of = match &query.as_of {
+        Some(as_of) => Some(DateTime::parse_rfc3339_str(as_of).map_err(|_| {
+            warn!("Invalid as_of timestamp. Responding with 400.");
+            ApiError::BadRequest("Parameter as_of must be an RFC 3339 timestamp.".to_string())
+        })?),
+        None => None
+    };
+    if query.include_deleted {
+        require_admin_for_deleted(&authorized.principal)?;
     }
 
-    match user_service::get_user(id.as_str()).await {
-        Ok(user) => {
-            info!("User found. Responding with 200.");
-            HttpResponse::Ok().json(user)
-        }
-        Err(user_service::DatabaseError::UserNotFound(_)) => {
-            warn!("User not found. Responding with 404.");
-            HttpResponse::NotFound().body("")
-        }
-        _ => {
-            warn!("Error occurred. Responding with 500.");
-            HttpResponse::InternalServerError().body("")
+    let config = service.config();
+    let user = match as_of {
+        Some(as_of) => service.get_user_as_of(id.as_str(), as_of, query.include_deleted).await,
+        None => service.get_user(id.as_str()).await
+    };
+    let user = match user {
+        Ok(user) => redaction::redact_user(&config.redaction, &user, &authorized.principal),
+        Err(DatabaseError::UserNotFound(_)) if query.include_deleted => {
+            let deleted = service.get_deleted_user(id.as_str()).await.map_err(|e| {
+                warn!("Could not get user with id: {id}: {e:?}.");
+                ApiError::from(e)
+            })?;
+            deleted_user_view(&deleted, &config.redaction, &authorized.principal)
+        },
+        Err(e) => {
+            warn!("Could not get user with id: {id}: {e:?}.");
+            return Err(ApiError::from(e))
         }
-    }
+    };
+
+    info!("User found. Responding with 200.");
+    respond_with(HttpResponse::Ok(), format, content_negotiation::serialize(format, "user", &user))
 }
 
 #[post("/users")]
-async fn create_new_user(req: HttpRequest, user: web::Data<User>) -> impl Responder {
-    info!("Incoming request to create a new user.");
-    if let Err(()) = check_headers(&req) {
-        warn!("Request missing required headers. Responding with 400.");
-        return HttpResponse::BadRequest().body("Missing or incorrect headers")
-    }
+pub async fn create_new_user(authorized: Authorized<Write>, req: HttpRequest, body: web::Bytes, service: web::Data<UserService>) -> Result<HttpResponse, ApiError> {
+    info!("Incoming request to create a new user from {}.", authorized.principal.subject);
+    let format = negotiate_response_format(&req)?;
+    let user = read_user(&req, &body)?;
 
     if user.id.is_some() {
         warn!("Info for new user already has an id. Responding with 400");
-        return HttpResponse::BadRequest().body("New user should not have an id present.");
+        return Err(ApiError::BadRequest("New user should not have an id present.".to_string()));
     }
 
-    match user_service::create_new_user(user.get_ref().clone()).await {
-        Ok(user) => {
-            info!("User created successfully. Responding with 200.");
-            HttpResponse::Ok().json(user)
-        },
-        _ => {
-            warn!("User creation failed.");
-            HttpResponse::InternalServerError().body("")
-        }
-    }
+    let user = service.create_new_user(user, &audit_context(&req, &authorized.principal)).await.map_err(|e| {
+        warn!("User creation failed: {e:?}.");
+        ApiError::from(e)
+    })?;
+
+    info!("User created successfully. Responding with 200.");
+    respond_with(HttpResponse::Ok(), format, content_negotiation::serialize(format, "user", &user))
+}
+
+#[post("/users:bulk")]
+pub async fn bulk_create_users(authorized: Authorized<Write>, req: HttpRequest, query: web::Query<BulkImportQuery>, body: web::Bytes, service: web::Data<UserService>) -> Result<HttpResponse, ApiError> {
+    info!("Incoming request to import users from {}.", authorized.principal.subject);
+    let response_format = negotiate_response_format(&req)?;
+    let format = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|header| header.to_str().ok())
+        .and_then(ImportFormat::from_content_type)
+    ;
+    let Some(format) = format else {
+        warn!("Unsupported content type for import. Responding with 415.");
+        return Err(ApiError::UnsupportedMediaType(vec!["application/json", "application/x-ndjson", "text/csv"]))
+    };
+
+    let rows = parse_users(&body, format).map_err(|e| {
+        warn!("Import document could not be read: {e:?}. Responding with 400.");
+        ApiError::BadRequest("Import document could not be read.".to_string())
+    })?;
+
+    let report = service.import_users(rows, query.all_or_nothing, &audit_context(&req, &authorized.principal)).await;
+    info!("Import finished. Responding with 200.");
+    respond_with(HttpResponse::Ok(), response_format, content_negotiation::serialize(response_format, "report", &report))
 }
 
 #[patch("/users/{id}")]
-async fn update_user(req: HttpRequest, user: web::Data<User>, id: web::Path<String>) -> impl Responder {
-    info!("Incoming request to update user info with id: {id}.");
-    if let Err(()) = check_headers(&req) {
-        warn!("Request missing required headers. Responding with 400.");
-        return HttpResponse::BadRequest().body("Missing or incorrect headers")
-    }
-    let mut user = user.get_ref().clone();
+pub async fn update_user(authorized: Authorized<Write>, req: HttpRequest, body: web::Bytes, id: web::Path<String>, service: web::Data<UserService>) -> Result<HttpResponse, ApiError> {
+    info!("Incoming request to update user info with id: {id} from {}.", authorized.principal.subject);
+    negotiate_response_format(&req)?;
+    let mut user = read_user(&req, &body)?;
     user.id = Some(id.to_string());
 
-    match user_service::update_user(user).await {
-        Ok(()) => {
-            info!("User with id: {id} updated successfully. Responding with 200.");
-            HttpResponse::Ok().body("")
-        },
-        Err(user_service::DatabaseError::UserNotFound(_)) => {
-            warn!("User with id: {id} not found. Responding with 404.");
-            HttpResponse::NotFound().body("User not found.")
-        },
-        _ => {
-            warn!("Error occurred when updating user. Responding with 500");
-            HttpResponse::InternalServerError().body("")
-        }
+    service.update_user(user, &audit_context(&req, &authorized.principal)).await.map_err(|e| {
+        warn!("Could not update user with id: {id}: {e:?}.");
+        ApiError::from(e)
+    })?;
+
+    info!("User with id: {id} updated successfully. Responding with 200.");
+    Ok(HttpResponse::Ok().body(""))
+}
+
+#[delete("/users/{id}")]
+pub async fn delete_user(authorized: Authorized<Write>, req: HttpRequest, id: web::Path<String>, service: web::Data<UserService>) -> Result<HttpResponse, ApiError> {
+    info!("Incoming request to delete user with id: {id} from {}.", authorized.principal.subject);
+
+    service.delete_user(&id, &audit_context(&req, &authorized.principal)).await.map_err(|e| {
+        warn!("Could not delete user with id: {id}: {e:?}.");
+        ApiError::from(e)
+    })?;
+
+    info!("User with id: {id} deleted. Responding with 204.");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/users/{id}:restore")]
+pub async fn restore_user(authorized: Authorized<Write>, req: HttpRequest, id: web::Path<String>, service: web::Data<UserService>) -> Result<HttpResponse, ApiError> {
+    info!("Incoming request to restore deleted user with id: {id} from {}.", authorized.principal.subject);
+    let format = negotiate_response_format(&req)?;
+
+    let user = service.restore_deleted_user(&id, &audit_context(&req, &authorized.principal)).await.map_err(|e| {
+        warn!("Could not restore deleted user with id: {id}: {e:?}.");
+        ApiError::from(e)
+    })?;
+
+    info!("Deleted user with id: {id} restored. Responding with 200.");
+    respond_with(HttpResponse::Ok(), format, content_negotiation::serialize(format, "user", &user))
+}
+
+/// Query parameters for listing revisions.
+#[derive(Deserialize)]
+pub struct RevisionsQuery {
+    /// Include revisions of soft-deleted users.
+    #[serde(default)]
+    include_deleted: bool
+}
+
+#[get("/users/{id}/revisions")]
+pub async fn get_user_revisions(authorized: Authorized<Write>, req: HttpRequest, query: web::Query<RevisionsQuery>, id: web::Path<String>, service: web::Data<UserService>) -> Result<HttpResponse, ApiError> {
+    info!("Incoming request for revisions of user with id: {id} from {}.", authorized.principal.subject);
+    let format = negotiate_response_format(&req)?;
+
+    let revisions = service.get_revisions(&id, query.include_deleted).await.map_err(|e| {
+        warn!("Could not get revisions of user with id: {id}: {e:?}.");
+        ApiError::from(e)
+    })?;
+
+    let config = service.config();
+    let revisions: Vec<Value> = revisions.iter()
+        .map(|revision| {
+            let mut value = serde_json::to_value(revision.info()).unwrap();
+            value["user"] = redaction::redact_user(&config.redaction, &revision.user, &authorized.principal);
+            value
+        })
+        .collect()
+    ;
+
+    info!("Found {} revisions. Responding with 200.", revisions.len());
+    respond_with(HttpResponse::Ok(), format, content_negotiation::serialize_list(format, "revisions", "revision", &revisions))
+}
+
+#[post("/users/{id}/revisions/{revision}:restore")]
+pub async fn restore_user_revision(authorized: Authorized<Write>, req: HttpRequest, path: web::Path<(String, u32)>, service: web::Data<UserService>) -> Result<HttpResponse, ApiError> {
+    let (id, revision) = path.into_inner();
+    info!("Incoming request to restore user with id: {id} to revision {revision} from {}.", authorized.principal.subject);
+    let format = negotiate_response_format(&req)?;
+
+    let user = service.restore_revision(&id, revision, &audit_context(&req, &authorized.principal)).await.map_err(|e| {
+        warn!("Could not restore user with id: {id} to revision {revision}: {e:?}.");
+        ApiError::from(e)
+    })?;
+
+    info!("User with id: {id} restored to revision {revision}. Responding with 200.");
+    respond_with(HttpResponse::Ok(), format, content_negotiation::serialize(format, "user", &user))
+}
+
+#[get("/users/{id}/audit")]
+pub async fn get_user_audit(authorized: Authorized<Write>, req: HttpRequest, id: web::Path<String>, service: web::Data<UserService>) -> Result<HttpResponse, ApiError> {
+    info!("Incoming request for audit log of user with id: {id} from {}.", authorized.principal.subject);
+    let format = negotiate_response_format(&req)?;
+
+    let entries: Vec<AuditEntryInfo> = service.get_audit_entries(&id).await
+        .map_err(|e| {
+            warn!("Could not get audit log of user with id: {id}: {e:?}.");
+            ApiError::from(e)
+        })?
+        .iter()
+        .map(|entry| entry.info())
+        .collect()
+    ;
+
+    info!("Found {} audit records. Responding with 200.", entries.len());
+    respond_with(HttpResponse::Ok(), format, content_negotiation::serialize_list(format, "audit", "entry", &entries))
+}
+
+/// Only admins may see soft-deleted users.
+fn require_admin_for_deleted(principal: &Principal) -> Result<(), ApiError> {
+    if principal.has_any_role(Write::ROLES) {
+        return Ok(())
     }
+
+    warn!("Deleted users requested without admin role. Responding with 403.");
+    Err(ApiError::Forbidden("Role admin is required to include deleted users.".to_string()))
 }
 
-fn check_headers(req: &HttpRequest) -> Result<(), ()> {
-    if check_accept_header_json(req).is_err() {
-        warn!("Request missing required headers. Responding with 400.");
-        Err(())
-    } else if check_content_type_header_json(req).is_err() {
-        warn!("Request missing required headers. Responding with 400.");
-        Err(())
-    } else {
-        Ok(())
+/// Redacted soft-deleted user with its RFC 3339 deletion time.
+fn deleted_user_view(deleted: &DeletedUser, policy: &Redaction, principal: &Principal) -> Value {
+    let mut user = redaction::redact_user(policy, &deleted.user, principal);
+    user["deleted_at"] = Value::String(deleted.deleted_at.try_to_rfc3339_string().unwrap_or_default());
+    user
+}
+
+/// Who is making a change, for the audit log.
+fn audit_context(req: &HttpRequest, principal: &Principal) -> AuditContext {
+    AuditContext {
+        actor: principal.subject.clone(),
+        request_id: req.extensions().get::<RequestId>().map(|request_id| request_id.0.clone())
     }
 }
 
-fn check_content_type_header_json(req: &HttpRequest) -> Result<(), ()> {
-    let header_content =
-        req
-            .headers()
-            .get(CONTENT_TYPE)
-            .ok_or(())?
-            .to_str()
-            .map_err(|_| ())?
+/// Deserialize user from the request body based on the `Content-Type` header.
+fn read_user(req: &HttpRequest, body: &[u8]) -> Result<User, ApiError> {
+    let content_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|header| header.to_str().ok())
     ;
 
-    if header_content == "application/json" {
-        return Err(())
-    }
-    Ok(())
+    content_negotiation::deserialize(content_type, body).map_err(|e| {
+        warn!("Request body could not be read: {e:?}.");
+        ApiError::from(e)
+    })
 }
 
-fn check_accept_header_json(req: &HttpRequest) -> Result<(), ()> {
-    let header_content =
-        req
-            .headers()
-            .get(ACCEPT)
-            .ok_or(())?
-            .to_str()
-            .map_err(|_| ())?
+/// Pick the response format based on the `Accept` header.
+pub fn negotiate_response_format(req: &HttpRequest) -> Result<ResponseFormat, ApiError> {
+    let accept = req
+        .headers()
+        .get(ACCEPT)
+        .and_then(|header| header.to_str().ok())
     ;
 
-    if header_content == "application/json" {
-        return Err(())
+    content_negotiation::negotiate(accept).map_err(|e| {
+        warn!("No acceptable response format. Responding with 406.");
+        ApiError::from(e)
+    })
+}
+
+/// Finish response with a body serialized in the negotiated format.
+///
+/// ## Arguments.
+/// * `response` - Response with status already set.
+/// * `format` - Negotiated response format.
+/// * `body` - Serialization result.
+pub fn respond_with(mut response: HttpResponseBuilder, format: ResponseFormat, body: Result<Vec<u8>, NegotiationError>) -> Result<HttpResponse, ApiError> {
+    Ok(response.content_type(format.content_type()).body(body?))
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{App, test};
+    use actix_web::http::StatusCode;
+    use crate::user_repository::test::InMemoryUserRepository;
+    use crate::user_service::test::test_service;
+    use super::*;
+
+    /// Users kept in memory, with the given users found in JsonPlaceholder.
+    fn users(jph_users: Vec<User>) -> web::Data<UserService> {
+        web::Data::new(test_service(InMemoryUserRepository::default(), jph_users))
+    }
+
+    #[actix_web::test]
+    async fn test_create_get_and_delete_users() {
+        let app = test::init_service(
+            App::new()
+                .app_data(users(vec![User::_create_test_user(Some("1".to_string()))]))
+                .service(get_all_users)
+                .service(get_user_with_id)
+                .service(create_new_user)
+                .service(delete_user)
+                .service(get_user_audit)
+        ).await;
+
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .insert_header((CONTENT_TYPE, "application/json"))
+            .set_payload(serde_json::to_vec(&User::_create_test_user(None)).unwrap())
+            .to_request();
+        let created: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!("101", created["id"]);
+
+        let users: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/users").to_request()).await;
+        assert_eq!(vec!["101", "1"], users.as_array().unwrap().iter().map(|user| user["id"].as_str().unwrap()).collect::<Vec<&str>>());
+
+        let response = test::call_service(&app, test::TestRequest::delete().uri("/users/1").to_request()).await;
+        assert_eq!(StatusCode::NO_CONTENT, response.status());
+        let response = test::call_service(&app, test::TestRequest::get().uri("/users/1").to_request()).await;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        let deleted: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/users/1?include_deleted=true").to_request()).await;
+        assert!(deleted["deleted_at"].is_string());
+
+        let audit: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/users/1/audit").to_request()).await;
+        assert_eq!("delete", audit[0]["action"]);
+    }
+
+    #[actix_web::test]
+    async fn test_get_user_invalid_as_of() {
+        let app = test::init_service(App::new().app_data(users(vec![])).service(get_user_with_id)).await;
+
+        let req = test::TestRequest::get().uri("/users/1?as_of=yesterday").to_request();
+        assert_eq!(StatusCode::BAD_REQUEST, test::call_service(&app, req).await.status());
     }
-    Ok(())
-}
\ No newline at end of file
+
+    #[actix_web::test]
+    async fn test_get_user_include_deleted_requires_admin() {
+        let app = test::init_service(
+            App::new().app_data(users(vec![])).service(get_all_users).service(get_user_with_id).service(get_user_revisions)
+        ).await;
+        let principal = Principal {
+            subject: "TEST_SUBJECT".to_string(),
+            roles: ["user".to_string()].into(),
+            user_id: None,
+            client_certificate: None,
+            unrestricted: false
+        };
+
+        for uri in ["/users?include_deleted=true", "/users/1?include_deleted=true", "/users/1/revisions?include_deleted=true"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            req.extensions_mut().insert(principal.clone());
+            assert_eq!(StatusCode::FORBIDDEN, test::call_service(&app, req).await.status());
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_export_users_redacted() {
+        let app = test::init_service(
+            App::new()
+                .app_data(users(vec![User::_create_test_user(Some("1".to_string())), User::_create_test_user(Some("2".to_string()))]))
+                .service(export_users)
+        ).await;
+        let principal = Principal {
+            subject: "TEST_SUBJECT".to_string(),
+            roles: ["user".to_string()].into(),
+            user_id: Some("1".to_string()),
+            client_certificate: None,
+            unrestricted: false
+        };
+
+        let req = test::TestRequest::get().uri("/users/export?format=ndjson").to_request();
+        req.extensions_mut().insert(principal);
+        let body = test::call_and_read_body(&app, req).await;
+        let lines: Vec<Value> = String::from_utf8_lossy(&body).lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+        // The test configuration hides phone numbers of other users.
+        assert_eq!(2, lines.len());
+        assert!(lines[0]["phone"].is_string());
+        assert!(lines[1].get("phone").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_restore_user_revision_invalid_revision() {
+        let app = test::init_service(App::new().app_data(users(vec![])).service(restore_user_revision)).await;
+
+        let req = test::TestRequest::post().uri("/users/1/revisions/latest:restore").to_request();
+        assert_eq!(StatusCode::NOT_FOUND, test::call_service(&app, req).await.status());
+    }
+
+    #[actix_web::test]
+    async fn test_create_new_user_body_too_large() {
+        let app = test::init_service(
+            App::new()
+                .app_data(users(vec![]))
+                .app_data(web::PayloadConfig::new(16))
+                .service(create_new_user)
+        ).await;
+
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .insert_header((CONTENT_TYPE, "application/json"))
+            .set_payload(serde_json::to_vec(&User::_create_test_user(None)).unwrap())
+            .to_request();
+
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, test::call_service(&app, req).await.status());
+    }
+
+    #[actix_web::test]
+    async fn test_create_new_user_unsupported_content_type() {
+        let app = test::init_service(App::new().app_data(users(vec![])).service(create_new_user)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .insert_header((CONTENT_TYPE, "text/plain"))
+            .set_payload("THIS IS NOT A USER")
+            .to_request();
+
+        assert_eq!(StatusCode::UNSUPPORTED_MEDIA_TYPE, test::call_service(&app, req).await.status());
+    }
+
+    #[actix_web::test]
+    async fn test_create_new_user_faulty_body() {
+        let app = test::init_service(App::new().app_data(users(vec![])).service(create_new_user)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .insert_header((CONTENT_TYPE, "application/cbor"))
+            .set_payload("THIS IS NOT CBOR")
+            .to_request();
+
+        assert_eq!(StatusCode::BAD_REQUEST, test::call_service(&app, req).await.status());
+    }
+
+    #[actix_web::test]
+    async fn test_update_user_not_acceptable() {
+        let app = test::init_service(App::new().app_data(users(vec![])).service(update_user)).await;
+
+        let req = test::TestRequest::patch()
+            .uri("/users/1")
+            .insert_header((ACCEPT, "text/html"))
+            .insert_header((CONTENT_TYPE, "application/json"))
+            .set_payload(serde_json::to_vec(&User::_create_test_user(None)).unwrap())
+            .to_request();
+
+        let response = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::NOT_ACCEPTABLE, response.status());
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert!(body["detail"].as_str().unwrap().contains("application/json"));
+    }
+}
@@ -0,0 +1,149 @@
+use serde_json::Value;
+
+/// Supported output formats for exports.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ExportFormat {
+    Ndjson,
+    Csv
+}
+
+/// CSV columns in the order they are written. Nested fields are flattened with dotted names,
+/// matching the headers accepted by bulk imports.
+pub const CSV_COLUMNS: [&str; 14] = [
+    "id",
+    "name",
+    "username",
+    "email",
+    "address.street",
+    "address.suite",
+    "address.city",
+    "address.geo.lat",
+    "address.geo.lng",
+    "phone",
+    "website",
+    "company.name",
+    "company.catchPhrase",
+    "company.bs",
+];
+
+impl ExportFormat {
+
+    /// Resolve export format from its name.
+    ///
+    /// ## Arguments.
+    /// * `name` - Format name, `ndjson` or `csv`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ndjson" | "jsonl" => Some(ExportFormat::Ndjson),
+            "csv" => Some(ExportFormat::Csv),
+            _ => None
+        }
+    }
+
+    /// Value for the `Content-Type` header.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv; charset=utf-8"
+        }
+    }
+}
+
+/// Serialize user as a single line of JSON, including the trailing newline.
+///
+/// ## Arguments.
+/// * `user` - User to be serialized, already redacted.
+pub fn to_ndjson_line(user: &Value) -> Vec<u8> {
+    let mut line = serde_json::to_vec(user).unwrap();
+    line.push(b'\n');
+    line
+}
+
+/// CSV header line, including the trailing newline.
+pub fn csv_header() -> Vec<u8> {
+    write_csv_record(CSV_COLUMNS)
+}
+
+/// Serialize user as a single CSV record with columns in the order of `CSV_COLUMNS`.
+/// Hidden fields are written as empty columns.
+///
+/// ## Arguments.
+/// * `user` - User to be serialized, already redacted.
+pub fn to_csv_row(user: &Value) -> Vec<u8> {
+    write_csv_record(CSV_COLUMNS.map(|column| {
+        column.split('.')
+            .try_fold(user, |value, key| value.get(key))
+            .and_then(Value::as_str)
+            .unwrap_or("")
+    }))
+}
+
+/// Write a single CSV record with proper quoting.
+fn write_csv_record(fields: [&str; CSV_COLUMNS.len()]) -> Vec<u8> {
+    let mut writer = csv::WriterBuilder::new().from_writer(vec![]);
+    writer.write_record(fields).unwrap();
+    writer.into_inner().unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::user::User;
+    use crate::user_import::{ImportFormat, parse_users};
+
+    #[test]
+    fn test_csv_header() {
+        assert_eq!(
+            "id,name,username,email,address.street,address.suite,address.city,address.geo.lat,address.geo.lng,phone,website,company.name,company.catchPhrase,company.bs\n",
+            String::from_utf8(csv_header()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_to_csv_row() {
+        let mut user = User::_create_test_user(Some("7".to_string()));
+        user.address.geo.insert("lng".to_string(), "15".to_string());
+        user.company.catch_phrase = "Truly, we are \"testing\"".to_string();
+
+        assert_eq!(
+            "7,TESTER,TESTER_69,testlover@testing.gov,Totallyrealstreet 6,a 12,Testington,12,15,123456789,testing.gov,Testing,\"Truly, we are \"\"testing\"\"\",To test\n",
+            String::from_utf8(to_csv_row(&serde_json::to_value(&user).unwrap())).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_csv_export_can_be_imported() {
+        let mut user = User::_create_test_user(None);
+        user.address.geo = [
+            ("lat".to_string(), "12".to_string()),
+            ("lng".to_string(), "15".to_string())
+        ].iter().cloned().collect();
+
+        let mut document = csv_header();
+        document.append(&mut to_csv_row(&serde_json::to_value(&user).unwrap()));
+
+        let rows = parse_users(&document, ImportFormat::Csv).unwrap();
+        assert_eq!(Ok(user), rows[0].result);
+    }
+
+    #[test]
+    fn test_to_ndjson_line() {
+        let user = User::_create_test_user(Some("7".to_string()));
+        let line = to_ndjson_line(&serde_json::to_value(&user).unwrap());
+
+        assert_eq!(Some(&b'\n'), line.last());
+        assert_eq!(user, serde_json::from_slice(&line).unwrap());
+    }
+
+    #[test]
+    fn test_to_csv_row_hidden_fields() {
+        let mut user = serde_json::to_value(User::_create_test_user(Some("7".to_string()))).unwrap();
+        user.as_object_mut().unwrap().remove("phone");
+        user["address"].as_object_mut().unwrap().remove("geo");
+
+        assert_eq!(
+            "7,TESTER,TESTER_69,testlover@testing.gov,Totallyrealstreet 6,a 12,Testington,,,,testing.gov,Testing,Truly we are testing,To test\n",
+            String::from_utf8(to_csv_row(&user)).unwrap()
+        );
+    }
+}
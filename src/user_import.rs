@@ -0,0 +1,296 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+use crate::user::User;
+
+/// Supported input formats for bulk imports.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ImportFormat {
+    Json,
+    Ndjson,
+    Csv
+}
+
+/// Possible errors thrown by `user_import` functions.
+#[derive(Eq, PartialEq, Debug)]
+pub enum ImportError {
+    /// The document as a whole could not be read, e.g. JSON body is not an array or CSV headers are broken.
+    InvalidDocument(String)
+}
+
+/// A single parsed input row. Row numbers start from 1.
+#[derive(PartialEq, Debug)]
+pub struct ParsedRow {
+    pub row: usize,
+    pub result: Result<User, Vec<String>>
+}
+
+/// Import outcome of a single row.
+#[derive(Serialize, Eq, PartialEq, Debug)]
+pub struct RowReport {
+    pub row: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>
+}
+
+/// Import outcome of a whole document.
+#[derive(Serialize, Eq, PartialEq, Debug)]
+pub struct ImportReport {
+    pub created: usize,
+    pub failed: usize,
+    pub rows: Vec<RowReport>
+}
+
+impl ImportFormat {
+
+    /// Resolve import format from a `Content-Type` header value. Parameters such as `charset` are ignored.
+    ///
+    /// ## Arguments.
+    /// * `content_type` - Value of the `Content-Type` header.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        match mime.as_str() {
+            "application/json" => Some(ImportFormat::Json),
+            "application/x-ndjson" | "application/ndjson" | "application/jsonl" => Some(ImportFormat::Ndjson),
+            "text/csv" => Some(ImportFormat::Csv),
+            _ => None
+        }
+    }
+
+    /// Resolve import format from a format name or file extension, e.g. `csv` or `jsonl`.
+    ///
+    /// ## Arguments.
+    /// * `name` - Format name or file extension.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Some(ImportFormat::Json),
+            "ndjson" | "jsonl" => Some(ImportFormat::Ndjson),
+            "csv" => Some(ImportFormat::Csv),
+            _ => None
+        }
+    }
+}
+
+impl ImportReport {
+
+    /// Build a report from row-level outcomes.
+    ///
+    /// ## Arguments.
+    /// * `rows` - Outcome of every input row.
+    pub fn from_rows(mut rows: Vec<RowReport>) -> Self {
+        rows.sort_by_key(|row| row.row);
+        let created = rows.iter().filter(|row| row.id.is_some()).count();
+        ImportReport {
+            created,
+            failed: rows.len() - created,
+            rows
+        }
+    }
+}
+
+/// Parse and validate users from the given document.
+///
+/// ## Arguments.
+/// * `body` - Raw document.
+/// * `format` - Format of the document.
+///
+/// ## Returns.
+/// A result containing one entry per input row or an error if the document itself could not be read.
+pub fn parse_users(body: &[u8], format: ImportFormat) -> Result<Vec<ParsedRow>, ImportError> {
+    match format {
+        ImportFormat::Json => parse_json_array(body),
+        ImportFormat::Ndjson => parse_ndjson(body),
+        ImportFormat::Csv => parse_csv(body)
+    }
+}
+
+/// Parse a JSON array of users.
+fn parse_json_array(body: &[u8]) -> Result<Vec<ParsedRow>, ImportError> {
+    let values: Vec<Value> = serde_json::from_slice(body)
+        .map_err(|e| ImportError::InvalidDocument(format!("Body is not a JSON array: {e}")))?
+    ;
+
+    Ok(
+        values.into_iter()
+            .enumerate()
+            .map(|(i, value)| ParsedRow { row: i + 1, result: value_to_user(value) })
+            .collect()
+    )
+}
+
+/// Parse newline delimited JSON. Blank lines are skipped but still counted as rows.
+fn parse_ndjson(body: &[u8]) -> Result<Vec<ParsedRow>, ImportError> {
+    let body = std::str::from_utf8(body)
+        .map_err(|_| ImportError::InvalidDocument("Body is not valid UTF-8.".to_string()))?
+    ;
+
+    Ok(
+        body.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                let result = serde_json::from_str(line)
+                    .map_err(|e| vec![format!("Invalid JSON: {e}")])
+                    .and_then(value_to_user)
+                ;
+                ParsedRow { row: i + 1, result }
+            })
+            .collect()
+    )
+}
+
+/// Parse CSV with dotted column headers, e.g. `address.geo.lat` or `company.catchPhrase`.
+fn parse_csv(body: &[u8]) -> Result<Vec<ParsedRow>, ImportError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body)
+    ;
+
+    let headers = reader.headers()
+        .map_err(|e| ImportError::InvalidDocument(format!("Could not read CSV headers: {e}")))?
+        .clone()
+    ;
+
+    Ok(
+        reader.records()
+            .enumerate()
+            .map(|(i, record)| {
+                let result = record
+                    .map_err(|e| vec![format!("Invalid CSV record: {e}")])
+                    .and_then(|record| {
+                        let mut object = Map::new();
+                        for (header, field) in headers.iter().zip(record.iter()) {
+                            // An empty id column means the row has no id.
+                            if header == "id" && field.is_empty() {
+                                continue
+                            }
+                            insert_dotted(&mut object, header, Value::String(field.to_string()));
+                        }
+                        value_to_user(Value::Object(object))
+                    })
+                ;
+                ParsedRow { row: i + 1, result }
+            })
+            .collect()
+    )
+}
+
+/// Insert `value` into `object` following a dotted `key`, creating nested objects along the way.
+pub fn insert_dotted(object: &mut Map<String, Value>, key: &str, value: Value) {
+    match key.split_once('.') {
+        None => {
+            object.insert(key.to_string(), value);
+        },
+        Some((head, rest)) => {
+            let child = object.entry(head.to_string()).or_insert_with(|| Value::Object(Map::new()));
+            if !child.is_object() {
+                *child = Value::Object(Map::new());
+            }
+            insert_dotted(child.as_object_mut().unwrap(), rest, value);
+        }
+    }
+}
+
+/// Turn a single JSON value into a validated new user.
+fn value_to_user(value: Value) -> Result<User, Vec<String>> {
+    let user: User = serde_json::from_value(value).map_err(|e| vec![format!("Invalid user: {e}")])?;
+
+    if user.id.is_some() {
+        return Err(vec!["New user should not have an id present.".to_string()]);
+    }
+
+    user.validate()?;
+    Ok(user)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CSV_HEADER: &str = "name,username,email,address.street,address.suite,address.city,address.geo.lat,address.geo.lng,phone,website,company.name,company.catchPhrase,company.bs";
+
+    #[test]
+    fn test_format_from_content_type() {
+        assert_eq!(Some(ImportFormat::Json), ImportFormat::from_content_type("application/json; charset=utf-8"));
+        assert_eq!(Some(ImportFormat::Ndjson), ImportFormat::from_content_type("application/x-ndjson"));
+        assert_eq!(Some(ImportFormat::Csv), ImportFormat::from_content_type("text/csv"));
+        assert_eq!(None, ImportFormat::from_content_type("text/plain"));
+    }
+
+    #[test]
+    fn test_parse_json_array() {
+        let mut invalid = serde_json::to_value(User::_create_test_user(None)).unwrap();
+        invalid["email"] = Value::String("".to_string());
+        let body = serde_json::to_vec(&vec![
+            serde_json::to_value(User::_create_test_user(None)).unwrap(),
+            serde_json::to_value(User::_create_test_user(Some("5".to_string()))).unwrap(),
+            invalid
+        ]).unwrap();
+
+        let rows = parse_users(&body, ImportFormat::Json).unwrap();
+        assert_eq!(3, rows.len());
+        assert_eq!(Ok(User::_create_test_user(None)), rows[0].result);
+        assert!(rows[1].result.is_err());
+        assert!(rows[2].result.as_ref().unwrap_err()[0].contains("email"));
+    }
+
+    #[test]
+    fn test_parse_json_not_array() {
+        assert!(parse_users(b"{}", ImportFormat::Json).is_err());
+    }
+
+    #[test]
+    fn test_parse_ndjson() {
+        let user = serde_json::to_string(&User::_create_test_user(None)).unwrap();
+        let body = format!("{user}\n\nTHIS IS NOT JSON\n{user}\n");
+
+        let rows = parse_users(body.as_bytes(), ImportFormat::Ndjson).unwrap();
+        assert_eq!(3, rows.len());
+        assert_eq!(1, rows[0].row);
+        assert!(rows[1].result.is_err());
+        assert_eq!(3, rows[1].row);
+        assert_eq!(4, rows[2].row);
+        assert!(rows[2].result.is_ok());
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let body = format!(
+            "{CSV_HEADER}\n\
+            TESTER,TESTER_69,testlover@testing.gov,Totallyrealstreet 6,a 12,Testington,12,15,123456789,testing.gov,Testing,\"Truly we are testing\",To test\n\
+            ,NO_NAME,noname@testing.gov,Street,,City,1,2,1,site,Company,Phrase,bs\n"
+        );
+
+        let rows = parse_users(body.as_bytes(), ImportFormat::Csv).unwrap();
+        assert_eq!(2, rows.len());
+
+        let user = rows[0].result.clone().unwrap();
+        assert_eq!("TESTER", user.name);
+        assert_eq!("Truly we are testing", user.company.catch_phrase);
+        assert_eq!(Some(&"15".to_string()), user.address.geo.get("lng"));
+
+        assert!(rows[1].result.as_ref().unwrap_err()[0].contains("name"));
+    }
+
+    #[test]
+    fn test_parse_csv_missing_column() {
+        let body = "name,username\nTESTER,TESTER_69\n";
+
+        let rows = parse_users(body.as_bytes(), ImportFormat::Csv).unwrap();
+        assert_eq!(1, rows.len());
+        assert!(rows[0].result.is_err());
+    }
+
+    #[test]
+    fn test_import_report_from_rows() {
+        let report = ImportReport::from_rows(vec![
+            RowReport { row: 2, id: None, errors: vec!["ERROR".to_string()] },
+            RowReport { row: 1, id: Some("101".to_string()), errors: vec![] }
+        ]);
+
+        assert_eq!(1, report.created);
+        assert_eq!(1, report.failed);
+        assert_eq!(1, report.rows[0].row);
+    }
+}
@@ -0,0 +1,1065 @@
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use log::warn;
+use mongodb::{Client, Collection, Database};
+use mongodb::bson::{doc, DateTime, Document};
+use mongodb::error::ErrorKind;
+use mongodb::IndexModel;
+use mongodb::options::{ClientOptions, FindOneAndUpdateOptions, FindOneOptions, FindOptions, IndexOptions, InsertManyOptions, ReturnDocument, UpdateOptions};
+use crate::audit::{AuditEntry, FieldChange};
+use crate::health::Dependency;
+use crate::revision::Revision;
+use crate::user::{DeletedUser, User};
+use crate::user_service::DatabaseError;
+
+/// Number of users inserted with a single `insert_many` during bulk imports.
+pub const IMPORT_BATCH_SIZE: usize = 100;
+
+/// Id of the counter document holding the last handed out user id.
+const USER_ID_COUNTER: &str = "user_id";
+
+/// Error code of MongoDB for a write breaking a unique index.
+const DUPLICATE_KEY: i32 = 11000;
+
+/// Storage of users along with their revisions and audit records. MongoDB is used when running,
+/// tests can plug in an in-memory repository.
+pub trait UserRepository: Send + Sync {
+
+    /// Get all users that are not soft-deleted.
+    fn get_users(&self) -> BoxFuture<'_, Result<Vec<User>, DatabaseError>>;
+
+    /// Stream all users that are not soft-deleted without collecting them in memory. Users that can not be read are skipped.
+    fn stream_users(&self) -> BoxFuture<'_, Result<BoxStream<'static, User>, DatabaseError>>;
+
+    /// Get user with specific id, unless soft-deleted.
+    ///
+    /// ## Arguments.
+    /// * `id` - User id.
+    fn get_user<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<User, DatabaseError>>;
+
+    /// Add new users. Ids of database users start from 101 to stay clear of JsonPlaceholder.
+    ///
+    /// ## Arguments.
+    /// * `users` - New user info. Ids are set for every user.
+    /// * `all_or_nothing` - If true, nothing is saved unless every user is.
+    ///
+    /// ## Returns.
+    /// One result per given user, containing the new id or an error.
+    fn create_users<'a>(&'a self, users: &'a mut [User], all_or_nothing: bool) -> BoxFuture<'a, Vec<Result<String, DatabaseError>>>;
+
+    /// Save the changed fields of a user.
+    ///
+    /// ## Arguments.
+    /// * `user` - Updated user info.
+    /// * `changes` - Differences to the stored user, see `diff_users`.
+    ///
+    /// ## Returns.
+    /// Result with an empty `OK` or `UserNotFound` if there is no stored user that is not deleted.
+    fn update_user<'a>(&'a self, user: &'a User, changes: &'a [FieldChange]) -> BoxFuture<'a, Result<(), DatabaseError>>;
+
+    /// Mark a stored user as deleted.
+    ///
+    /// ## Arguments.
+    /// * `id` - User id.
+    /// * `deleted_at` - Time of deletion.
+    ///
+    /// ## Returns.
+    /// A result containing `false` if there is no stored user that is not deleted yet.
+    fn mark_deleted<'a>(&'a self, id: &'a str, deleted_at: DateTime) -> BoxFuture<'a, Result<bool, DatabaseError>>;
+
+    /// Store a deleted copy of a user, e.g. one only found in JsonPlaceholder.
+    ///
+    /// ## Arguments.
+    /// * `user` - User with id.
+    /// * `deleted_at` - Time of deletion.
+    fn insert_deleted<'a>(&'a self, user: &'a User, deleted_at: DateTime) -> BoxFuture<'a, Result<(), DatabaseError>>;
+
+    /// Remove the deletion marker of a user.
+    ///
+    /// ## Arguments.
+    /// * `id` - User id.
+    ///
+    /// ## Returns.
+    /// Result with an empty `OK` or `UserNotFound` if the user is not deleted.
+    fn restore_deleted_user<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), DatabaseError>>;
+
+    /// Get all soft-deleted users.
+    fn get_deleted_users(&self) -> BoxFuture<'_, Result<Vec<DeletedUser>, DatabaseError>>;
+
+    /// Get soft-deleted user with specific id.
+    ///
+    /// ## Arguments.
+    /// * `id` - User id.
+    ///
+    /// ## Returns.
+    /// A result containing the deleted user or `UserNotFound` if the user is not deleted.
+    fn get_deleted_user<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<DeletedUser, DatabaseError>>;
+
+    /// Get ids of all soft-deleted users.
+    fn get_deleted_user_ids(&self) -> BoxFuture<'_, Result<Vec<String>, DatabaseError>>;
+
+    /// Permanently remove users that were soft-deleted before the given time.
+    ///
+    /// ## Arguments.
+    /// * `deleted_before` - Users deleted before this are removed.
+    ///
+    /// ## Returns.
+    /// A result containing the number of removed users or an error.
+    fn purge_deleted_users(&self, deleted_before: DateTime) -> BoxFuture<'_, Result<u64, DatabaseError>>;
+
+    /// Get stored revisions of a user in revision order.
+    ///
+    /// ## Arguments.
+    /// * `user_id` - User id.
+    fn get_revisions<'a>(&'a self, user_id: &'a str) -> BoxFuture<'a, Result<Vec<Revision>, DatabaseError>>;
+
+    /// Highest stored revision number of a user, 0 if there are none.
+    ///
+    /// ## Arguments.
+    /// * `user_id` - User id.
+    fn latest_revision_number<'a>(&'a self, user_id: &'a str) -> BoxFuture<'a, Result<u32, DatabaseError>>;
+
+    /// Store revisions in order, stopping at the first one that fails.
+    ///
+    /// ## Arguments.
+    /// * `revisions` - New revisions.
+    ///
+    /// ## Returns.
+    /// `DatabaseError::RevisionConflict` if a revision number of the user is already taken.
+    fn insert_revisions(&self, revisions: Vec<Revision>) -> BoxFuture<'_, Result<(), DatabaseError>>;
+
+    /// Get audit records of a user, oldest first.
+    ///
+    /// ## Arguments.
+    /// * `user_id` - User id.
+    fn get_audit_entries<'a>(&'a self, user_id: &'a str) -> BoxFuture<'a, Result<Vec<AuditEntry>, DatabaseError>>;
+
+    /// Store audit records.
+    ///
+    /// ## Arguments.
+    /// * `entries` - New audit records.
+    fn insert_audit_entries(&self, entries: Vec<AuditEntry>) -> BoxFuture<'_, Result<(), DatabaseError>>;
+}
+
+/// Connect to MongoDB. Shut the client down before exiting, so that connections are closed cleanly.
+///
+/// ## Arguments.
+/// * `connection_string` - Connection string that will be used to connect to MongoDB. Should contain username and password.
+///
+/// ## Returns.
+/// A result containing either the client or an error.
+pub async fn get_client(connection_string: &str) -> Result<Client, DatabaseError> {
+    // Parse options and attempt connection.
+    let client_options = match ClientOptions::parse(connection_string).await {
+        Ok(options) => options,
+        _ => return Err(DatabaseError::MongoConnectionFailed)
+    };
+
+    // Create client.
+    match Client::with_options(client_options) {
+        Ok(client) => Ok(client),
+        _ => Err(DatabaseError::MongoConnectionFailed)
+    }
+}
+
+/// Users stored in the "users", "revisions" and "audit" collections of MongoDB.
+pub struct MongoUserRepository {
+    database: Database
+}
+
+impl MongoUserRepository {
+    pub fn new(database: Database) -> Self {
+        MongoUserRepository { database }
+    }
+
+    /// Create the indexes the repository relies on. Existing indexes are left as is.
+    ///
+    /// # Returns.
+    /// A result containing possible `DatabaseError`.
+    pub async fn create_indexes(&self) -> Result<(), DatabaseError> {
+        // Concurrent updates of a user may pick the same revision number, only one of them gets it.
+        let index = IndexModel::builder()
+            .keys(doc! { "user_id": 1, "revision": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build()
+        ;
+        self.revisions().create_index(index, None).await.map(|_| ()).map_err(|e| {
+            warn!("Could not create revision index: {e:?}");
+            DatabaseError::OperationFailed
+        })
+    }
+
+    /// Collection with name "users".
+    fn users(&self) -> Collection<User> {
+        self.database.collection("users")
+    }
+
+    /// Collection with name "users" as raw documents, for the soft delete marker that is not part of `User`.
+    fn user_documents(&self) -> Collection<Document> {
+        self.database.collection("users")
+    }
+
+    /// Collection with name "revisions".
+    fn revisions(&self) -> Collection<Revision> {
+        self.database.collection("revisions")
+    }
+
+    /// Collection with name "audit".
+    fn audit(&self) -> Collection<AuditEntry> {
+        self.database.collection("audit")
+    }
+
+    /// Collection with name "counters", holding the last handed out user id.
+    fn counters(&self) -> Collection<Document> {
+        self.database.collection("counters")
+    }
+
+    /// Highest numeric user id in MongoDB.
+    ///
+    /// # Returns.
+    /// A result containing possible `DatabaseError` or the highest id, 0 if there are no users.
+    async fn get_max_user_id(&self) -> Result<i64, DatabaseError> {
+        // Highest id, counting soft-deleted users and ignoring non-numeric ids.
+        let pipeline = vec![doc! {
+            "$group": {
+                "_id": null,
+                "max_id": { "$max": { "$convert": { "input": "$id", "to": "long", "onError": null, "onNull": null } } }
+            }
+        }];
+        let mut cursor = match self.users().aggregate(pipeline, None).await {
+            Ok(cursor) => cursor,
+            _ => return Err(DatabaseError::MongoConnectionFailed)
+        };
+
+        match cursor.try_next().await {
+            Ok(Some(result)) => Ok(result.get_i64("max_id").unwrap_or(0)),
+            Ok(None) => Ok(0),
+            _ => Err(DatabaseError::OperationFailed)
+        }
+    }
+
+    /// Reserve ids for new users. Ids come from a counter that never goes back, so that ids of purged
+    /// users are not handed out again along with their revisions and audit records.
+    ///
+    /// ## Arguments.
+    /// * `count` - Number of ids to reserve.
+    ///
+    /// # Returns.
+    /// A result containing possible `DatabaseError` or the first of `count` consecutive ids.
+    async fn reserve_user_ids(&self, count: u64) -> Result<u64, DatabaseError> {
+        let filter = doc! { "_id": USER_ID_COUNTER };
+
+        // Databases from before the counter continue after their highest id.
+        let counters = self.counters().count_documents(filter.clone(), None).await.map_err(|_| DatabaseError::MongoConnectionFailed)?;
+        if counters == 0 {
+            let max_id = self.get_max_user_id().await?.max(100);
+            let options = UpdateOptions::builder().upsert(true).build();
+            self.counters().update_one(filter.clone(), doc! { "$max": { "last_id": max_id } }, options).await
+                .map_err(|_| DatabaseError::OperationFailed)?
+            ;
+        }
+
+        let options = FindOneAndUpdateOptions::builder().upsert(true).return_document(ReturnDocument::After).build();
+        let counter = self.counters().find_one_and_update(filter, doc! { "$inc": { "last_id": count as i64 } }, options).await
+            .map_err(|_| DatabaseError::OperationFailed)?
+        ;
+        match counter.and_then(|counter| counter.get_i64("last_id").ok()) {
+            Some(last_id) => Ok(last_id as u64 + 1 - count),
+            None => Err(DatabaseError::OperationFailed)
+        }
+    }
+
+    /// Delete user with given id from database.
+    /// Not used for now.
+    ///
+    /// ## Arguments.
+    /// * `id` - Id for the user to be deleted.
+    ///
+    /// # Returns.
+    /// A result containing possible `DatabaseError` an `Ok(())` if everything goes as it should.
+    async fn _remove_user(&self, id: &str) -> Result<(), DatabaseError> {
+        // Delete user.
+        let result = self.users().delete_one(
+            doc! {
+                    "id": id
+            },
+            None
+        ).await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(_) => Err(DatabaseError::OperationFailed)
+        }
+    }
+}
+
+impl UserRepository for MongoUserRepository {
+    fn get_users(&self) -> BoxFuture<'_, Result<Vec<User>, DatabaseError>> {
+        async move {
+            // Get users that are not soft-deleted.
+            let mut cursor = match self.users().find(doc! { "deleted_at": null }, None).await {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!("Could not find users: {e:?}");
+                    return Err(DatabaseError::OperationFailed)
+                }
+            };
+
+            // Iterate through found users and parse them from `RawDocument` to `User`.
+            // Return resulting vector.
+            let mut result= vec![];
+            while cursor.advance().await.map_err(|_| DatabaseError::OperationFailed)? {
+                let current: User = bson::from_slice(cursor.current().as_bytes()).unwrap();
+                result.push(current);
+            }
+
+            Ok(result)
+        }.boxed()
+    }
+
+    fn stream_users(&self) -> BoxFuture<'_, Result<BoxStream<'static, User>, DatabaseError>> {
+        async move {
+            // Open cursor for users that are not soft-deleted.
+            let cursor = self.users().find(doc! { "deleted_at": null }, None).await.map_err(|e| {
+                warn!("Could not query users: {e}");
+                DatabaseError::OperationFailed
+            })?;
+
+            Ok(cursor.filter_map(|result| async move {
+                match result {
+                    Ok(user) => Some(user),
+                    Err(e) => {
+                        warn!("Skipping user that could not be read: {e}");
+                        None
+                    }
+                }
+            }).boxed())
+        }.boxed()
+    }
+
+    fn get_user<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<User, DatabaseError>> {
+        async move {
+            // Do query with given filter, skipping soft-deleted users.
+            let user_result = self.users().find_one(
+                doc! {
+                    "id": id,
+                    "deleted_at": null
+                },
+                None
+            ).await;
+
+            // Handle and return result.
+            let user_option = match user_result {
+                Ok(option) => option,
+                Err(e) => {
+                    warn!("Could not find user {id}: {e:?}");
+                    return Err(DatabaseError::UserNotFound(id.to_string()))
+                }
+            };
+
+            match user_option {
+                Some(user) => Ok(user),
+                None => Err(DatabaseError::UserNotFound(id.to_string()))
+            }
+        }.boxed()
+    }
+
+    fn create_users<'a>(&'a self, users: &'a mut [User], all_or_nothing: bool) -> BoxFuture<'a, Vec<Result<String, DatabaseError>>> {
+        async move {
+            // Reserve ids for every user, failed inserts leave gaps.
+            let first_id = match self.reserve_user_ids(users.len() as u64).await {
+                Ok(id) => id as usize,
+                Err(e) => return vec![Err(e); users.len()]
+            };
+            let collection = self.users();
+
+            // Set new ids for users.
+            for (i, user) in users.iter_mut().enumerate() {
+                user.id = Some((first_id + i).to_string());
+            }
+            let ids: Vec<String> = users.iter().map(|user| user.id.clone().unwrap()).collect();
+
+            if all_or_nothing {
+                return match insert_users_in_transaction(&collection, users).await {
+                    Ok(()) => ids.into_iter().map(Ok).collect(),
+                    Err(e) => vec![Err(e); users.len()]
+                }
+            }
+
+            // Insert batches unordered, so a single failing user does not stop the rest.
+            let options = InsertManyOptions::builder().ordered(false).build();
+            let mut results = vec![];
+            for (batch, batch_ids) in users.chunks(IMPORT_BATCH_SIZE).zip(ids.chunks(IMPORT_BATCH_SIZE)) {
+                let mut batch_results: Vec<Result<String, DatabaseError>> = batch_ids.iter().cloned().map(Ok).collect();
+
+                if let Err(e) = collection.insert_many(batch, options.clone()).await {
+                    warn!("Error occurred when inserting a batch of users: {e}");
+                    match *e.kind {
+                        // Only the reported documents failed.
+                        ErrorKind::BulkWrite(failure) if failure.write_errors.is_some() => {
+                            for write_error in failure.write_errors.unwrap() {
+                                if let Some(result) = batch_results.get_mut(write_error.index) {
+                                    *result = Err(DatabaseError::OperationFailed);
+                                }
+                            }
+                        },
+                        _ => batch_results.iter_mut().for_each(|result| *result = Err(DatabaseError::OperationFailed))
+                    }
+                }
+
+                results.append(&mut batch_results);
+            }
+
+            results
+        }.boxed()
+    }
+
+    fn update_user<'a>(&'a self, user: &'a User, changes: &'a [FieldChange]) -> BoxFuture<'a, Result<(), DatabaseError>> {
+        async move {
+            // Update user info. Deleted users are left alone, also when deleted after they were read.
+            let update_result = self.users().update_one(
+                doc! {
+                    "id": user.id.clone().unwrap(),
+                    "deleted_at": null
+                },
+                generate_update_document(changes),
+                None
+            ).await;
+
+            match update_result {
+                Ok(result) if result.matched_count == 0 => Err(DatabaseError::UserNotFound(user.id.clone().unwrap())),
+                Ok(_) => Ok(()),
+                Err(e) => {
+                    warn!("Could not update user: {e:?}");
+                    Err(DatabaseError::OperationFailed)
+                }
+            }
+        }.boxed()
+    }
+
+    fn mark_deleted<'a>(&'a self, id: &'a str, deleted_at: DateTime) -> BoxFuture<'a, Result<bool, DatabaseError>> {
+        async move {
+            // Mark user as deleted.
+            let update_result = self.user_documents().update_one(
+                doc! {
+                    "id": id,
+                    "deleted_at": null
+                },
+                doc! { "$set": { "deleted_at": deleted_at } },
+                None
+            ).await;
+
+            match update_result {
+                Ok(result) => Ok(result.matched_count > 0),
+                Err(_) => Err(DatabaseError::OperationFailed)
+            }
+        }.boxed()
+    }
+
+    fn insert_deleted<'a>(&'a self, user: &'a User, deleted_at: DateTime) -> BoxFuture<'a, Result<(), DatabaseError>> {
+        async move {
+            let mut document = bson::to_document(user).map_err(|_| DatabaseError::OperationFailed)?;
+            document.insert("deleted_at", deleted_at);
+            match self.user_documents().insert_one(document, None).await {
+                Ok(_) => Ok(()),
+                Err(_) => Err(DatabaseError::OperationFailed)
+            }
+        }.boxed()
+    }
+
+    fn restore_deleted_user<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), DatabaseError>> {
+        async move {
+            // Remove deletion marker.
+            let update_result = self.user_documents().update_one(
+                doc! {
+                    "id": id,
+                    "deleted_at": { "$ne": null }
+                },
+                doc! { "$unset": { "deleted_at": "" } },
+                None
+            ).await;
+
+            match update_result {
+                Ok(result) if result.matched_count == 0 => Err(DatabaseError::UserNotFound(id.to_string())),
+                Ok(_) => Ok(()),
+                Err(_) => Err(DatabaseError::OperationFailed)
+            }
+        }.boxed()
+    }
+
+    fn get_deleted_users(&self) -> BoxFuture<'_, Result<Vec<DeletedUser>, DatabaseError>> {
+        async move {
+            let cursor = self.user_documents().find(doc! { "deleted_at": { "$ne": null } }, None).await.map_err(|_| DatabaseError::MongoConnectionFailed)?;
+            let documents: Vec<Document> = cursor.try_collect().await.map_err(|_| DatabaseError::OperationFailed)?;
+            documents.into_iter().map(to_deleted_user).collect()
+        }.boxed()
+    }
+
+    fn get_deleted_user<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<DeletedUser, DatabaseError>> {
+        async move {
+            match self.user_documents().find_one(doc! { "id": id, "deleted_at": { "$ne": null } }, None).await {
+                Ok(Some(document)) => to_deleted_user(document),
+                Ok(None) => Err(DatabaseError::UserNotFound(id.to_string())),
+                Err(_) => Err(DatabaseError::OperationFailed)
+            }
+        }.boxed()
+    }
+
+    fn get_deleted_user_ids(&self) -> BoxFuture<'_, Result<Vec<String>, DatabaseError>> {
+        async move {
+            match self.user_documents().distinct("id", doc! { "deleted_at": { "$ne": null } }, None).await {
+                Ok(ids) => Ok(ids.iter().filter_map(|id| id.as_str().map(str::to_string)).collect()),
+                Err(_) => Err(DatabaseError::MongoConnectionFailed)
+            }
+        }.boxed()
+    }
+
+    fn purge_deleted_users(&self, deleted_before: DateTime) -> BoxFuture<'_, Result<u64, DatabaseError>> {
+        async move {
+            match self.user_documents().delete_many(doc! { "deleted_at": { "$lt": deleted_before } }, None).await {
+                Ok(result) => Ok(result.deleted_count),
+                Err(_) => Err(DatabaseError::OperationFailed)
+            }
+        }.boxed()
+    }
+
+    fn get_revisions<'a>(&'a self, user_id: &'a str) -> BoxFuture<'a, Result<Vec<Revision>, DatabaseError>> {
+        async move {
+            let options = FindOptions::builder().sort(doc! { "revision": 1 }).build();
+            let cursor = self.revisions().find(doc! { "user_id": user_id }, options).await.map_err(|_| DatabaseError::OperationFailed)?;
+            cursor.try_collect().await.map_err(|_| DatabaseError::OperationFailed)
+        }.boxed()
+    }
+
+    fn latest_revision_number<'a>(&'a self, user_id: &'a str) -> BoxFuture<'a, Result<u32, DatabaseError>> {
+        async move {
+            let options = FindOneOptions::builder().sort(doc! { "revision": -1 }).build();
+            match self.revisions().find_one(doc! { "user_id": user_id }, options).await {
+                Ok(revision) => Ok(revision.map(|revision| revision.revision).unwrap_or(0)),
+                Err(_) => Err(DatabaseError::OperationFailed)
+            }
+        }.boxed()
+    }
+
+    fn insert_revisions(&self, revisions: Vec<Revision>) -> BoxFuture<'_, Result<(), DatabaseError>> {
+        async move {
+            if revisions.is_empty() {
+                return Ok(())
+            }
+            self.revisions().insert_many(&revisions, None).await.map(|_| ()).map_err(|e| match *e.kind {
+                ErrorKind::BulkWrite(ref failure) if failure.write_errors.iter().flatten().any(|error| error.code == DUPLICATE_KEY) => {
+                    DatabaseError::RevisionConflict
+                },
+                _ => {
+                    warn!("Could not write {} revisions: {e:?}", revisions.len());
+                    DatabaseError::OperationFailed
+                }
+            })
+        }.boxed()
+    }
+
+    fn get_audit_entries<'a>(&'a self, user_id: &'a str) -> BoxFuture<'a, Result<Vec<AuditEntry>, DatabaseError>> {
+        async move {
+            let options = FindOptions::builder().sort(doc! { "timestamp": 1 }).build();
+            let cursor = self.audit().find(doc! { "user_id": user_id }, options).await.map_err(|_| DatabaseError::OperationFailed)?;
+            cursor.try_collect().await.map_err(|_| DatabaseError::OperationFailed)
+        }.boxed()
+    }
+
+    fn insert_audit_entries(&self, entries: Vec<AuditEntry>) -> BoxFuture<'_, Result<(), DatabaseError>> {
+        async move {
+            if entries.is_empty() {
+                return Ok(())
+            }
+            self.audit().insert_many(&entries, None).await.map(|_| ()).map_err(|e| {
+                warn!("Could not write {} audit records: {e:?}", entries.len());
+                DatabaseError::OperationFailed
+            })
+        }.boxed()
+    }
+}
+
+impl Dependency for MongoUserRepository {
+    fn ping(&self) -> BoxFuture<'_, Result<(), String>> {
+        async move {
+            self.database.run_command(doc! { "ping": 1 }, None).await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }.boxed()
+    }
+}
+
+/// Insert all users in batches inside a single transaction. Requires a replica set.
+///
+/// ## Arguments.
+/// * `collection` - Collection users are inserted to.
+/// * `users` - New users with ids set.
+///
+/// # Returns.
+/// Result containing an empty `Ok` or an error. Nothing is saved on error.
+async fn insert_users_in_transaction(collection: &Collection<User>, users: &[User]) -> Result<(), DatabaseError> {
+    let mut session = collection.client().start_session(None).await
+        .map_err(|_| DatabaseError::MongoConnectionFailed)?
+    ;
+    session.start_transaction(None).await.map_err(|e| {
+        warn!("Could not start transaction: {e}");
+        DatabaseError::OperationFailed
+    })?;
+
+    for batch in users.chunks(IMPORT_BATCH_SIZE) {
+        if let Err(e) = collection.insert_many_with_session(batch, None, &mut session).await {
+            warn!("Error occurred when inserting users, aborting transaction: {e}");
+            let _ = session.abort_transaction().await;
+            return Err(DatabaseError::OperationFailed)
+        }
+    }
+
+    session.commit_transaction().await.map_err(|e| {
+        warn!("Could not commit transaction: {e}");
+        DatabaseError::OperationFailed
+    })
+}
+
+/// Generate an update `Document` setting every changed field.
+///
+/// ## Arguments.
+/// * `changes` - Differences between the original and the updated user, see `diff_users`.
+///
+/// ## Returns.
+/// A `Document` containing changes and a `set`-command.
+fn generate_update_document(changes: &[FieldChange]) -> Document {
+    let mut set_document = doc! {};
+
+    for change in changes {
+        if let Some(updated_value) = &change.after {
+            set_document.insert(change.field.as_str(), bson::to_bson(updated_value).unwrap());
+        }
+    }
+
+    doc! { "$set": set_document }
+}
+
+/// Split a stored user document into the user and its deletion time.
+fn to_deleted_user(document: Document) -> Result<DeletedUser, DatabaseError> {
+    let deleted_at = *document.get_datetime("deleted_at").map_err(|_| DatabaseError::OperationFailed)?;
+    let user = bson::from_document(document).map_err(|_| DatabaseError::OperationFailed)?;
+    Ok(DeletedUser { user, deleted_at })
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::sync::Mutex;
+    use testcontainers::GenericImage;
+    use testcontainers::clients::Cli;
+    use super::*;
+    use crate::audit::{AuditAction, AuditContext, diff_users};
+
+    // Database name used in tests.
+    const DB_NAME: &str = "showcase_test";
+    // Connection string -template.
+    const C_STRING: &str = "mongodb://localhost:";
+
+    /// Users kept in memory, along with their deletion time.
+    #[derive(Default)]
+    pub struct InMemoryUserRepository {
+        users: Mutex<Vec<(User, Option<DateTime>)>>,
+        /// Last handed out id, like the counter in MongoDB.
+        last_id: Mutex<u64>,
+        revisions: Mutex<Vec<Revision>>,
+        audit: Mutex<Vec<AuditEntry>>
+    }
+
+    impl InMemoryUserRepository {
+
+        /// Repository holding the given users.
+        pub fn with_users(users: Vec<User>) -> Self {
+            let repository = InMemoryUserRepository::default();
+            *repository.users.lock().unwrap() = users.into_iter().map(|user| (user, None)).collect();
+            repository
+        }
+
+        /// Users matching `filter` on their deletion time.
+        fn find(&self, filter: impl Fn(&(User, Option<DateTime>)) -> bool) -> Vec<(User, Option<DateTime>)> {
+            self.users.lock().unwrap().iter().filter(|stored| filter(stored)).cloned().collect()
+        }
+    }
+
+    impl UserRepository for InMemoryUserRepository {
+        fn get_users(&self) -> BoxFuture<'_, Result<Vec<User>, DatabaseError>> {
+            let users = self.find(|(_, deleted_at)| deleted_at.is_none()).into_iter().map(|(user, _)| user).collect();
+            futures::future::ready(Ok(users)).boxed()
+        }
+
+        fn stream_users(&self) -> BoxFuture<'_, Result<BoxStream<'static, User>, DatabaseError>> {
+            let users: Vec<User> = self.find(|(_, deleted_at)| deleted_at.is_none()).into_iter().map(|(user, _)| user).collect();
+            futures::future::ready(Ok(futures::stream::iter(users).boxed())).boxed()
+        }
+
+        fn get_user<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<User, DatabaseError>> {
+            let user = self.find(|(user, deleted_at)| user.id.as_deref() == Some(id) && deleted_at.is_none())
+                .pop()
+                .map(|(user, _)| user)
+                .ok_or(DatabaseError::UserNotFound(id.to_string()))
+            ;
+            futures::future::ready(user).boxed()
+        }
+
+        fn create_users<'a>(&'a self, users: &'a mut [User], _all_or_nothing: bool) -> BoxFuture<'a, Vec<Result<String, DatabaseError>>> {
+            let mut stored = self.users.lock().unwrap();
+            let mut last_id = self.last_id.lock().unwrap();
+            let max_id = stored.iter().filter_map(|(user, _)| user.id.as_ref()?.parse::<u64>().ok()).max().unwrap_or(0);
+            *last_id = (*last_id).max(max_id).max(100);
+            let results = users.iter_mut().map(|user| {
+                *last_id += 1;
+                let id = last_id.to_string();
+                user.id = Some(id.clone());
+                stored.push((user.clone(), None));
+                Ok(id)
+            }).collect();
+            futures::future::ready(results).boxed()
+        }
+
+        fn update_user<'a>(&'a self, user: &'a User, _changes: &'a [FieldChange]) -> BoxFuture<'a, Result<(), DatabaseError>> {
+            let mut stored = self.users.lock().unwrap();
+            let result = match stored.iter_mut().find(|(stored, deleted_at)| stored.id == user.id && deleted_at.is_none()) {
+                Some((stored, _)) => {
+                    *stored = user.clone();
+                    Ok(())
+                },
+                None => Err(DatabaseError::UserNotFound(user.id.clone().unwrap()))
+            };
+            futures::future::ready(result).boxed()
+        }
+
+        fn mark_deleted<'a>(&'a self, id: &'a str, deleted_at: DateTime) -> BoxFuture<'a, Result<bool, DatabaseError>> {
+            let mut stored = self.users.lock().unwrap();
+            let found = stored.iter_mut().find(|(user, deleted)| user.id.as_deref() == Some(id) && deleted.is_none());
+            let matched = found.map(|(_, deleted)| *deleted = Some(deleted_at)).is_some();
+            futures::future::ready(Ok(matched)).boxed()
+        }
+
+        fn insert_deleted<'a>(&'a self, user: &'a User, deleted_at: DateTime) -> BoxFuture<'a, Result<(), DatabaseError>> {
+            self.users.lock().unwrap().push((user.clone(), Some(deleted_at)));
+            futures::future::ready(Ok(())).boxed()
+        }
+
+        fn restore_deleted_user<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), DatabaseError>> {
+            let mut stored = self.users.lock().unwrap();
+            let found = stored.iter_mut().find(|(user, deleted)| user.id.as_deref() == Some(id) && deleted.is_some());
+            let result = found.map(|(_, deleted)| *deleted = None).ok_or(DatabaseError::UserNotFound(id.to_string()));
+            futures::future::ready(result).boxed()
+        }
+
+        fn get_deleted_users(&self) -> BoxFuture<'_, Result<Vec<DeletedUser>, DatabaseError>> {
+            let users = self.find(|(_, deleted_at)| deleted_at.is_some()).into_iter()
+                .map(|(user, deleted_at)| DeletedUser { user, deleted_at: deleted_at.unwrap() })
+                .collect()
+            ;
+            futures::future::ready(Ok(users)).boxed()
+        }
+
+        fn get_deleted_user<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<DeletedUser, DatabaseError>> {
+            let user = self.find(|(user, deleted_at)| user.id.as_deref() == Some(id) && deleted_at.is_some())
+                .pop()
+                .map(|(user, deleted_at)| DeletedUser { user, deleted_at: deleted_at.unwrap() })
+                .ok_or(DatabaseError::UserNotFound(id.to_string()))
+            ;
+            futures::future::ready(user).boxed()
+        }
+
+        fn get_deleted_user_ids(&self) -> BoxFuture<'_, Result<Vec<String>, DatabaseError>> {
+            let ids = self.find(|(_, deleted_at)| deleted_at.is_some()).into_iter().filter_map(|(user, _)| user.id).collect();
+            futures::future::ready(Ok(ids)).boxed()
+        }
+
+        fn purge_deleted_users(&self, deleted_before: DateTime) -> BoxFuture<'_, Result<u64, DatabaseError>> {
+            let mut stored = self.users.lock().unwrap();
+            let count = stored.len();
+            stored.retain(|(_, deleted_at)| deleted_at.is_none_or(|deleted_at| deleted_at >= deleted_before));
+            futures::future::ready(Ok((count - stored.len()) as u64)).boxed()
+        }
+
+        fn get_revisions<'a>(&'a self, user_id: &'a str) -> BoxFuture<'a, Result<Vec<Revision>, DatabaseError>> {
+            let mut revisions: Vec<Revision> = self.revisions.lock().unwrap().iter().filter(|revision| revision.user_id == user_id).cloned().collect();
+            revisions.sort_by_key(|revision| revision.revision);
+            futures::future::ready(Ok(revisions)).boxed()
+        }
+
+        fn latest_revision_number<'a>(&'a self, user_id: &'a str) -> BoxFuture<'a, Result<u32, DatabaseError>> {
+            let latest = self.revisions.lock().unwrap().iter()
+                .filter(|revision| revision.user_id == user_id)
+                .map(|revision| revision.revision)
+                .max()
+                .unwrap_or(0)
+            ;
+            futures::future::ready(Ok(latest)).boxed()
+        }
+
+        fn insert_revisions(&self, revisions: Vec<Revision>) -> BoxFuture<'_, Result<(), DatabaseError>> {
+            // Revision numbers are unique per user, like with the index in MongoDB.
+            let mut stored = self.revisions.lock().unwrap();
+            for revision in revisions {
+                if stored.iter().any(|other| other.user_id == revision.user_id && other.revision == revision.revision) {
+                    return futures::future::ready(Err(DatabaseError::RevisionConflict)).boxed()
+                }
+                stored.push(revision);
+            }
+            futures::future::ready(Ok(())).boxed()
+        }
+
+        fn get_audit_entries<'a>(&'a self, user_id: &'a str) -> BoxFuture<'a, Result<Vec<AuditEntry>, DatabaseError>> {
+            let entries = self.audit.lock().unwrap().iter().filter(|entry| entry.user_id == user_id).cloned().collect();
+            futures::future::ready(Ok(entries)).boxed()
+        }
+
+        fn insert_audit_entries(&self, mut entries: Vec<AuditEntry>) -> BoxFuture<'_, Result<(), DatabaseError>> {
+            self.audit.lock().unwrap().append(&mut entries);
+            futures::future::ready(Ok(())).boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_client_faulty_connection_string() {
+        assert!(get_client("NOT_URL").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_collection() {
+        let client = Cli::default();
+        let container = client.run(get_mongo_image());
+
+        let repository = repository(container.get_host_port_ipv4(27017)).await;
+        assert_eq!("users", repository.users().name());
+
+        container.stop();
+    }
+
+    #[tokio::test]
+    async fn test_get_user_from_database_not_found() {
+        let client = Cli::default();
+        let container = client.run(get_mongo_image());
+
+        let repository = repository(container.get_host_port_ipv4(27017)).await;
+        assert_eq!(Err(DatabaseError::UserNotFound("666".to_string())), repository.get_user("666").await);
+
+        container.stop();
+    }
+
+    #[tokio::test]
+    async fn test_add_and_get_user_and_get_all_users_from_database() {
+        let client = Cli::default();
+        let container = client.run(get_mongo_image());
+
+        let repository = repository(container.get_host_port_ipv4(27017)).await;
+
+        let inserted_id = repository.create_users(&mut [User::_create_test_user(None)], false).await.remove(0);
+        assert!(inserted_id.is_ok());
+        let inserted_id = inserted_id.unwrap();
+
+        let search_result = repository.get_user(&inserted_id).await;
+        assert!(search_result.is_ok());
+        assert_eq!(&inserted_id, &search_result.unwrap().id.unwrap());
+
+        let get_all_result = repository.get_users().await;
+        assert!(get_all_result.is_ok());
+        let user_list = get_all_result.unwrap();
+        assert!(!user_list.is_empty());
+
+        assert_eq!(inserted_id, user_list.first().cloned().unwrap().id.unwrap());
+
+        container.stop();
+    }
+
+    #[tokio::test]
+    async fn test_add_and_get_and_remove_user_from_database() {
+        let client = Cli::default();
+        let container = client.run(get_mongo_image());
+
+        let repository = repository(container.get_host_port_ipv4(27017)).await;
+
+        let user_id = repository.create_users(&mut [User::_create_test_user(None)], false).await.remove(0).unwrap();
+        assert!(repository.get_user(&user_id).await.is_ok());
+
+        assert!(repository._remove_user(&user_id).await.is_ok());
+        assert_eq!(Err(DatabaseError::UserNotFound(user_id.clone())), repository.get_user(&user_id).await);
+
+        container.stop();
+    }
+
+    #[tokio::test]
+    async fn test_add_and_update() {
+        let client = Cli::default();
+        let container = client.run(get_mongo_image());
+
+        let repository = repository(container.get_host_port_ipv4(27017)).await;
+
+        let inserted_id = repository.create_users(&mut [User::_create_test_user(None)], false).await.remove(0).unwrap();
+
+        let original = User::_create_test_user(Some(inserted_id.clone()));
+        let mut user = original.clone();
+        user.name = "NEW NAME".to_string();
+
+        assert!(repository.update_user(&user, &diff_users(Some(&original), &user)).await.is_ok());
+        assert_eq!("NEW NAME".to_string(), repository.get_user(&inserted_id).await.unwrap().name);
+
+        container.stop();
+    }
+
+    #[tokio::test]
+    async fn test_stream_all_users_from_database() {
+        let client = Cli::default();
+        let container = client.run(get_mongo_image());
+
+        let repository = repository(container.get_host_port_ipv4(27017)).await;
+
+        for _ in 0..3 {
+            repository.create_users(&mut [User::_create_test_user(None)], false).await.remove(0).unwrap();
+        }
+
+        let users: Vec<User> = repository.stream_users().await.unwrap().collect().await;
+        assert_eq!(3, users.len());
+
+        container.stop();
+    }
+
+    #[tokio::test]
+    async fn test_create_users_in_batches() {
+        let client = Cli::default();
+        let container = client.run(get_mongo_image());
+
+        let repository = repository(container.get_host_port_ipv4(27017)).await;
+
+        let mut users: Vec<User> = (0..IMPORT_BATCH_SIZE + 5).map(|_| User::_create_test_user(None)).collect();
+        let results = repository.create_users(&mut users, false).await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(Ok("101".to_string()), results[0]);
+        assert_eq!(IMPORT_BATCH_SIZE + 5, repository.get_users().await.unwrap().len());
+
+        container.stop();
+    }
+
+    #[tokio::test]
+    async fn test_delete_restore_and_purge() {
+        let client = Cli::default();
+        let container = client.run(get_mongo_image());
+
+        let repository = repository(container.get_host_port_ipv4(27017)).await;
+
+        let id = repository.create_users(&mut [User::_create_test_user(None)], false).await.remove(0).unwrap();
+
+        // Deleted user is hidden from reads.
+        assert_eq!(Ok(true), repository.mark_deleted(&id, DateTime::now()).await);
+        assert_eq!(Err(DatabaseError::UserNotFound(id.clone())), repository.get_user(&id).await);
+        assert!(repository.get_users().await.unwrap().is_empty());
+        assert_eq!(vec![id.clone()], repository.get_deleted_user_ids().await.unwrap());
+        assert_eq!(Ok(false), repository.mark_deleted(&id, DateTime::now()).await);
+        let mut user = User::_create_test_user(Some(id.clone()));
+        user.name = "NEW NAME".to_string();
+        let changes = diff_users(None, &user);
+        assert_eq!(Err(DatabaseError::UserNotFound(id.clone())), repository.update_user(&user, &changes).await);
+
+        // Restored user is visible again.
+        assert_eq!(Ok(()), repository.restore_deleted_user(&id).await);
+        assert!(repository.get_user(&id).await.is_ok());
+
+        // Purge only removes users deleted before the cutoff.
+        repository.mark_deleted(&id, DateTime::now()).await.unwrap();
+        assert_eq!(0, repository.purge_deleted_users(DateTime::from_millis(0)).await.unwrap());
+        let deleted_at = repository.get_deleted_users().await.unwrap()[0].deleted_at;
+        let cutoff = DateTime::from_millis(deleted_at.timestamp_millis() + 1);
+        assert_eq!(1, repository.purge_deleted_users(cutoff).await.unwrap());
+        assert_eq!(Err(DatabaseError::UserNotFound(id.clone())), repository.restore_deleted_user(&id).await);
+
+        // Neither deleted nor purged ids are reused.
+        let new_id = repository.create_users(&mut [User::_create_test_user(None)], false).await.remove(0).unwrap();
+        assert_eq!("102", new_id);
+
+        container.stop();
+    }
+
+    #[tokio::test]
+    async fn test_record_and_get_revisions() {
+        let client = Cli::default();
+        let container = client.run(get_mongo_image());
+
+        let repository = repository(container.get_host_port_ipv4(27017)).await;
+        let context = test_context();
+
+        let before = User::_create_test_user(Some("101".to_string()));
+        let mut after = before.clone();
+        after.name = "NEW NAME".to_string();
+        repository.insert_revisions(vec![Revision::baseline(&before, "unknown"), Revision::new(2, &after, &context)]).await.unwrap();
+
+        let revisions = repository.get_revisions("101").await.unwrap();
+        assert_eq!(2, revisions.len());
+        assert_eq!(before, revisions[0].user);
+        assert_eq!(None, revisions[0].timestamp);
+        assert_eq!("NEW NAME", revisions[1].user.name);
+        assert_eq!(2, repository.latest_revision_number("101").await.unwrap());
+        assert_eq!(0, repository.latest_revision_number("102").await.unwrap());
+
+        // Revision numbers are not handed out twice.
+        assert_eq!(
+            Err(DatabaseError::RevisionConflict),
+            repository.insert_revisions(vec![Revision::new(2, &after, &context)]).await
+        );
+
+        container.stop();
+    }
+
+    #[tokio::test]
+    async fn test_record_and_get_audit_entries() {
+        let client = Cli::default();
+        let container = client.run(get_mongo_image());
+
+        let repository = repository(container.get_host_port_ipv4(27017)).await;
+
+        let user = User::_create_test_user(Some("101".to_string()));
+        let context = AuditContext { actor: "TEST_SUBJECT".to_string(), request_id: Some("REQUEST".to_string()) };
+        repository.insert_audit_entries(vec![
+            AuditEntry::new(AuditAction::Create, "101", &context, diff_users(None, &user)),
+            AuditEntry::new(AuditAction::Create, "102", &context, vec![])
+        ]).await.unwrap();
+
+        let entries = repository.get_audit_entries("101").await.unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!("TEST_SUBJECT", entries[0].actor);
+        assert_eq!(diff_users(None, &user), entries[0].changes);
+
+        container.stop();
+    }
+
+    #[test]
+    fn test_generate_update_document() {
+        let original = User::_create_test_user(Some("101".to_string()));
+        let mut updated = original.clone();
+        updated.name = "NEW NAME".to_string();
+        updated.phone = "987654321".to_string();
+
+        let update_document = generate_update_document(&diff_users(Some(&original), &updated));
+        let set_document = update_document.get_document("$set").unwrap();
+        assert_eq!(2, set_document.len());
+        assert_eq!("NEW NAME", set_document.get_str("name").unwrap());
+        assert_eq!("987654321", set_document.get_str("phone").unwrap());
+    }
+
+    async fn repository(port: u16) -> MongoUserRepository {
+        let repository = MongoUserRepository::new(get_client(&format!("{}{}", C_STRING, port)).await.unwrap().database(DB_NAME));
+        repository.create_indexes().await.unwrap();
+        repository
+    }
+
+    fn test_context() -> AuditContext {
+        AuditContext {
+            actor: "TEST_SUBJECT".to_string(),
+            request_id: None
+        }
+    }
+
+    fn get_mongo_image() -> GenericImage {
+        GenericImage::new("mongo", "latest")
+            .with_env_var("MONGO_INITDB_DATABASE", "showcase_test")
+            .with_exposed_port(27017)
+    }
+}
@@ -1,568 +1,741 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
 use log::{info, warn};
-use mongodb::{Client, Collection};
-use mongodb::bson::{doc, Document};
-use mongodb::options::ClientOptions;
-use crate::{CONFIG, user_client};
-use crate::user::User;
-use crate::user_client::UserClientError;
-
-#[derive(Eq, PartialEq, Debug)]
+use mongodb::bson::DateTime;
+use tracing::{field, info_span, instrument, Instrument};
+use crate::audit::{AuditAction, AuditContext, AuditEntry, deleted_at_change, diff_users};
+use crate::configuration::Configuration;
+use crate::configuration_reload::ConfigurationHandle;
+use crate::metrics::Metrics;
+use crate::revision::{Revision, revision_as_of, UNKNOWN_ACTOR, UPSTREAM_ACTOR};
+use crate::user::{DeletedUser, User};
+use crate::user_client::{UserClient, UserClientError};
+use crate::user_import::{ImportReport, ParsedRow, RowReport};
+use crate::user_repository::UserRepository;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
 pub enum DatabaseError {
     UserNotFound(String),
+    RevisionNotFound(String, u32),
+    RevisionConflict,
     MongoConnectionFailed,
     OperationFailed
 }
 
-/// Get all users across the database and JsonPlaceholder.
-///
-/// ## Returns.
-/// Vector containing all found users.
-pub async fn get_users() -> Vec<User> {
-    let database_result =
-        get_all_users_from_db_with_config(
-            &CONFIG.database.url,
-            &CONFIG.database.database_name
-        ).await
-    ;
-
-    let mut users = match database_result {
-        Ok(users) => users,
-        _ => vec![]
-    };
-
-    let database_ids: Vec<String> = users.clone().iter().map(|user| user.id.clone().unwrap()).collect();
-
-    let jph_users = match user_client::get_users().await {
-        Ok(users) => users,
-        _ => vec![]
-    };
-
-    jph_users.iter().for_each(|user| {
-        if !database_ids.contains(&user.id.clone().unwrap()) {
-            users.push(user.clone());
-        };
-    });
+/// How often recording a revision is tried when concurrent updates take its number.
+const REVISION_ATTEMPTS: u32 = 3;
 
-    users
+/// Users across the database and JsonPlaceholder. Created once at startup and shared with
+/// handlers as app data.
+pub struct UserService {
+    repository: Arc<dyn UserRepository>,
+    client: Arc<dyn UserClient>,
+    config: Arc<ConfigurationHandle>,
+    metrics: Arc<Metrics>
 }
 
-/// Create a new user.
-///
-/// ## Arguments.
-/// * `user` - New user info without id.
-///
-/// ## Returns.
-/// A result containing the user info enriched with id or an error.
-pub async fn create_new_user(mut user: User) -> Result<User, DatabaseError> {
-    let creation_result =
-        create_user_to_db_with_config(
-            &mut user,
-            &CONFIG.database.url,
-            &CONFIG.database.database_name
-        ).await
-    ;
-
-    if creation_result.is_err() {
-        return Err(DatabaseError::OperationFailed)
-    };
-
-    user.id = Some(creation_result?);
-
-    Ok(user)
+/// Whether a repository result counts as a successful database operation in metrics.
+trait Outcome {
+    fn succeeded(&self) -> bool;
 }
 
-/// Get user with specific id.
-///
-/// ## Arguments.
-/// * `id` - User id.
-///
-/// ## Returns.
-/// A result containing the found user or an occurred error.
-pub async fn get_user(id: &str) -> Result<User, DatabaseError> {
-    let database_result =
-        get_user_from_db_with_config(
-            id,
-            &CONFIG.database.url,
-            &CONFIG.database.database_name
-        ).await
-    ;
-
-    match database_result {
-        Ok(user) => return Ok(user),
-        Err(DatabaseError::MongoConnectionFailed) => info!("Could not establish connection with mongoDB!"),
-        Err(DatabaseError::UserNotFound(_)) => info!("Could not find user in mongoDB, attempting JsonPlaceholder!"),
-        _ => warn!("Error occurred when searching user from mongoDB")
-    }
-
-    info!("Checking JsonPlaceholder for user with id: {id}");
-    let jph_result = user_client::get_user(id.to_string()).await;
-
-    match jph_result {
-        Ok(user) => Ok(user),
-        Err(UserClientError::UserNotFound(_)) => Err(DatabaseError::UserNotFound(id.to_string())),
-        _ => Err(DatabaseError::OperationFailed)
+impl<T> Outcome for Result<T, DatabaseError> {
+    /// Missing users and revisions are answers too, only failures to talk to the database count.
+    fn succeeded(&self) -> bool {
+        !matches!(self, Err(DatabaseError::MongoConnectionFailed | DatabaseError::OperationFailed))
     }
 }
 
-/// Update user info.
-///
-/// ## Arguments.
-/// * `user` - Updated user info.
-///
-/// ## Returns.
-/// Result with an empty `OK` or an error.
-pub async fn update_user(user: User) -> Result<(), DatabaseError> {
-    update_user_in_db_with_config(
-        user,
-        &CONFIG.database.url,
-        &CONFIG.database.database_name
-    ).await
+impl<T> Outcome for Vec<Result<T, DatabaseError>> {
+    fn succeeded(&self) -> bool {
+        self.iter().all(Outcome::succeeded)
+    }
 }
 
-/// Get MongoDB collection based on the given configuration.
-///
-/// ## Arguments.
-/// * `connection_string` - Connection string that will be used to connect to MongoDB. Should contain username and password.
-/// * `database_name` - Database we are using.
-///
-/// ## Returns.
-/// A result containing possible `DatabaseError` or the collection with name "users".
-async fn get_user_collection(connection_string: &str, database_name: &str) -> Result<Collection<User>, DatabaseError> {
-    // Parse options and attempt connection.
-    let client_options = match ClientOptions::parse(connection_string).await {
-        Ok(options) => options,
-        _ => return Err(DatabaseError::MongoConnectionFailed)
-    };
-
-    // Create client.
-    let client = match Client::with_options(client_options) {
-        Ok(client) => client,
-        _ => return Err(DatabaseError::MongoConnectionFailed)
-    };
-
-    // Get collection.
-    let collection_name = "users";
-    Ok(client.database(database_name).collection(collection_name))
-}
+impl UserService {
+
+    /// Create a service.
+    ///
+    /// ## Arguments.
+    /// * `repository` - Storage of users, their revisions and audit records.
+    /// * `client` - Source of JsonPlaceholder users.
+    /// * `config` - Configuration in use.
+    /// * `metrics` - Metrics recording the latency of database operations.
+    pub fn new(
+        repository: Arc<dyn UserRepository>,
+        client: Arc<dyn UserClient>,
+        config: Arc<ConfigurationHandle>,
+        metrics: Arc<Metrics>
+    ) -> Self {
+        UserService { repository, client, config, metrics }
+    }
 
-/// Get all users from database.
-///
-/// ## Arguments.
-/// * `connection_string` - Connection string that will be used to connect to MongoDB. Should contain username and password.
-/// * `database_name` - Database we are using.
-///
-/// ## Returns.
-/// A result containing either a  vector consisting of the fetched users or an error.
-async fn get_all_users_from_db_with_config(connection_string: &str, database_name: &str) -> Result<Vec<User>, DatabaseError> {
-    // Get collection.
-    let collection = get_user_collection(connection_string, database_name).await?;
-
-    // Get users.
-    let mut cursor = match collection.find(None, None).await {
-        Ok(c) => c,
-        Err(e) => {
-            println!("{:?}", e);
-            return Err(DatabaseError::OperationFailed)
-        }
-    };
+    /// Configuration in use. Hold on to it for the duration of a request.
+    pub fn config(&self) -> Arc<Configuration> {
+        self.config.current()
+    }
+
+    /// Get all users across the database and JsonPlaceholder.
+    ///
+    /// ## Returns.
+    /// Vector containing all found users.
+    #[instrument(skip_all)]
+    pub async fn get_users(&self) -> Vec<User> {
+        let mut users = self.observe("get_users", self.repository.get_users()).await.unwrap_or_default();
+
+        // Soft-deleted users hide their JsonPlaceholder counterparts too.
+        let mut database_ids: Vec<String> = users.clone().iter().map(|user| user.id.clone().unwrap()).collect();
+        database_ids.append(&mut self.observe("get_deleted_user_ids", self.repository.get_deleted_user_ids()).await.unwrap_or_default());
 
-    // Iterate through found users and parse them from `RawDocument` to `User`.
-    // Return resulting vector.
-    let mut result= vec![];
-    while cursor.advance().await.map_err(|_| DatabaseError::OperationFailed)? {
-        let current: User = bson::from_slice(cursor.current().as_bytes()).unwrap();
-        result.push(current);
+        let jph_users = self.client.get_users().await.unwrap_or_default();
+
+        jph_users.iter().for_each(|user| {
+            if !database_ids.contains(&user.id.clone().unwrap()) {
+                users.push(user.clone());
+            };
+        });
+
+        users
     }
 
-    Ok(result)
-}
+    /// Stream all users across the database and JsonPlaceholder without collecting database users in memory.
+    ///
+    /// ## Returns.
+    /// Stream of all found users. Database users come first, followed by JsonPlaceholder users not overridden in the database.
+    #[instrument(skip_all)]
+    pub async fn stream_users(&self) -> BoxStream<'static, User> {
+        // Soft-deleted users hide their JsonPlaceholder counterparts too.
+        let deleted_ids = self.observe("get_deleted_user_ids", self.repository.get_deleted_user_ids()).await.unwrap_or_default();
+        let jph_users: Vec<User> = self.client.get_users().await.unwrap_or_default()
+            .into_iter()
+            .filter(|user| !deleted_ids.contains(user.id.as_ref().unwrap()))
+            .collect()
+        ;
 
-/// Get user info from database with id.
-///
-/// ## Arguments.
-/// * `id` - User id.
-/// * `connection_string` - Connection string that will be used to connect to MongoDB. Should contain username and password.
-/// * `database_name` - Database we are using.
-///
-/// ## Returns.
-/// A result containing possible `DatabaseError` or the user info with given `id`.
-async fn get_user_from_db_with_config(id: &str, connection_string: &str, database_name: &str) -> Result<User, DatabaseError> {
-    // Get collection.
-    let collection = get_user_collection(connection_string, database_name).await?;
-
-    // Do query with given filter.
-    let user_result = collection.find_one(
-        doc! {
-            "id": id
-        },
-        None
-    ).await;
-
-    // Handle and return result.
-    let user_option = match user_result {
-        Ok(option) => option,
-        Err(e) => {
-            println!("{:?}", e);
+        let database_users = match self.observe("stream_users", self.repository.stream_users()).await {
+            Ok(users) => users,
+            Err(e) => {
+                warn!("Could not stream users from mongoDB: {e:?}");
+                stream::empty().boxed()
+            }
+        };
+
+        merge_user_streams(database_users, jph_users).boxed()
+    }
+
+    /// Create a new user.
+    ///
+    /// ## Arguments.
+    /// * `user` - New user info without id.
+    /// * `context` - Who is making the change, for the audit log.
+    ///
+    /// ## Returns.
+    /// A result containing the user info enriched with id or an error.
+    #[instrument(skip_all)]
+    pub async fn create_new_user(&self, mut user: User, context: &AuditContext) -> Result<User, DatabaseError> {
+        let new_id = self.observe("create_users", self.repository.create_users(std::slice::from_mut(&mut user), false)).await.remove(0)?;
+
+        // Audit and return result.
+        let audit_entry = AuditEntry::new(AuditAction::Create, &new_id, context, diff_users(None, &user));
+        self.record_audit_entries(vec![audit_entry]).await;
+        self.record_created_revisions(&[&user], context).await;
+        Ok(user)
+    }
+
+    /// Get user with specific id.
+    ///
+    /// ## Arguments.
+    /// * `id` - User id.
+    ///
+    /// ## Returns.
+    /// A result containing the found user or an occurred error.
+    #[instrument(skip_all, fields(user.id = id))]
+    pub async fn get_user(&self, id: &str) -> Result<User, DatabaseError> {
+        match self.observe("get_user", self.repository.get_user(id)).await {
+            Ok(user) => return Ok(user),
+            Err(DatabaseError::MongoConnectionFailed) => info!("Could not establish connection with mongoDB!"),
+            Err(DatabaseError::UserNotFound(_)) => info!("Could not find user in mongoDB, attempting JsonPlaceholder!"),
+            _ => warn!("Error occurred when searching user from mongoDB")
+        }
+
+        // Soft-deleted users hide their JsonPlaceholder counterparts too.
+        if self.observe("get_deleted_user", self.repository.get_deleted_user(id)).await.is_ok() {
             return Err(DatabaseError::UserNotFound(id.to_string()))
         }
-    };
 
-    match user_option {
-        Some(user) => Ok(user),
-        None => Err(DatabaseError::UserNotFound(id.to_string()))
+        info!("Checking JsonPlaceholder for user with id: {id}");
+        match self.client.get_user(id).await {
+            Ok(user) => Ok(user),
+            Err(UserClientError::UserNotFound(_)) => Err(DatabaseError::UserNotFound(id.to_string())),
+            _ => Err(DatabaseError::OperationFailed)
+        }
     }
-}
 
-/// Add new user info to database.
-///
-/// ## Arguments.
-/// * `user` - New user info.
-/// * `connection_string` - Connection string that will be used to connect to MongoDB. Should contain username and password.
-/// * `database_name` - Database we are using.
-///
-/// # Returns.
-/// A result containing possible `DatabaseError` or the new id generated by MongoDB.
-async fn create_user_to_db_with_config(user: &mut User, connection_string: &str, database_name: &str) -> Result<String, DatabaseError> {
-    // Get collection.
-    let collection = get_user_collection(connection_string, database_name).await?;
-    let new_id = (get_users_count_with_config(connection_string, database_name).await? as i32 + 101).to_string();
+    /// Update user info in database.
+    ///
+    /// ## Arguments.
+    /// * `user` - Updated user info.
+    /// * `context` - Who is making the change, for the audit log.
+    ///
+    /// ## Returns.
+    /// Result with an empty `OK` or an error.
+    #[instrument(skip_all, fields(user.id = user.id.as_deref()))]
+    pub async fn update_user(&self, user: User, context: &AuditContext) -> Result<(), DatabaseError> {
+        // Fetch info stored in the database.
+        let existing_user = self.observe("get_user", self.repository.get_user(user.id.as_deref().unwrap())).await?;
+
+        // Nothing to do if nothing changed.
+        let changes = diff_users(Some(&existing_user), &user);
+        if changes.is_empty() {
+            return Ok(())
+        }
 
-    // Set a new id for user.
-    user.id = Some(new_id.clone());
+        self.observe("update_user", self.repository.update_user(&user, &changes)).await?;
 
-    // Insert new user.
-    let insert_result = collection.insert_one(user, None).await;
+        // Audit and return result.
+        let audit_entry = AuditEntry::new(AuditAction::Update, user.id.as_deref().unwrap(), context, changes);
+        self.record_audit_entries(vec![audit_entry]).await;
+        self.record_updated_revision(&existing_user, &user, context).await;
+        Ok(())
+    }
 
+    /// Get all revisions of a user. Users without stored revisions get their current state as the
+    /// baseline revision, which for JsonPlaceholder users is the upstream snapshot.
+    ///
+    /// ## Arguments.
+    /// * `id` - User id.
+    /// * `include_deleted` - Also get revisions of soft-deleted users. Admin only.
+    ///
+    /// ## Returns.
+    /// A result containing revisions in revision order or an error.
+    #[instrument(skip_all, fields(user.id = id))]
+    pub async fn get_revisions(&self, id: &str, include_deleted: bool) -> Result<Vec<Revision>, DatabaseError> {
+        // Soft-deleted users are hidden along with their history.
+        if !include_deleted && self.observe("get_deleted_user", self.repository.get_deleted_user(id)).await.is_ok() {
+            return Err(DatabaseError::UserNotFound(id.to_string()))
+        }
+
+        let revisions = self.observe("get_revisions", self.repository.get_revisions(id)).await?;
+        if !revisions.is_empty() {
+            return Ok(revisions)
+        }
 
-    // Handle and return result.
-    match insert_result {
-        Ok(_) => Ok(new_id),
-        Err(_) => Err(DatabaseError::OperationFailed)
+        match self.observe("get_user", self.repository.get_user(id)).await {
+            Ok(user) => return Ok(vec![Revision::baseline(&user, UNKNOWN_ACTOR)]),
+            Err(DatabaseError::UserNotFound(_)) => info!("No revisions for user with id: {id}, checking JsonPlaceholder."),
+            Err(e) => return Err(e)
+        }
+
+        match self.client.get_user(id).await {
+            Ok(user) => Ok(vec![Revision::baseline(&user, UPSTREAM_ACTOR)]),
+            Err(UserClientError::UserNotFound(_)) => Err(DatabaseError::UserNotFound(id.to_string())),
+            _ => Err(DatabaseError::OperationFailed)
+        }
     }
-}
 
-/// Update user info in database.
-///
-/// ## Arguments.
-/// * `user` - Updated user info.
-/// * `connection_string` - Connection string that will be used to connect to MongoDB. Should contain username and password.
-/// * `database_name` - Database we are using.
-///
-/// # Returns.
-/// Result containing an empty `Ok` or an error.
-async fn update_user_in_db_with_config(user: User, connection_string: &str, database_name: &str) -> Result<(), DatabaseError> {
-    // Get collection.
-    let collection = get_user_collection(connection_string, database_name).await?;
-    // Fetch info stored in the database.
-    let existing_user =
-        get_user_from_db_with_config(
-            user.id.clone().unwrap().as_str(),
-            connection_string,
-            database_name
-        ).await?
-    ;
-
-    // Update user info.
-    let update_result = collection.update_one(
-        doc! {
-            "id": user.id.clone().unwrap()
-        },
-        generate_update_document(existing_user, user),
-        None
-    ).await;
-
-    // Return result.
-    match update_result {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            println!("{:?}", e);
-            Err(DatabaseError::OperationFailed)
+    /// Get user as it was at the given time.
+    ///
+    /// ## Arguments.
+    /// * `id` - User id.
+    /// * `as_of` - Point in time.
+    /// * `include_deleted` - Also read soft-deleted users. Admin only.
+    ///
+    /// ## Returns.
+    /// A result containing the user or `UserNotFound` if the user did not exist yet.
+    #[instrument(skip_all, fields(user.id = id))]
+    pub async fn get_user_as_of(&self, id: &str, as_of: DateTime, include_deleted: bool) -> Result<User, DatabaseError> {
+        let revisions = self.get_revisions(id, include_deleted).await?;
+
+        match revision_as_of(&revisions, as_of) {
+            Some(revision) => Ok(revision.user.clone()),
+            None => Err(DatabaseError::UserNotFound(id.to_string()))
         }
     }
-}
 
+    /// Restore user to the state of a revision. The restore itself is saved as a new revision.
+    ///
+    /// ## Arguments.
+    /// * `id` - User id.
+    /// * `revision` - Revision number.
+    /// * `context` - Who is making the change, for the audit log.
+    ///
+    /// ## Returns.
+    /// A result containing the restored user or an error.
+    #[instrument(skip_all, fields(user.id = id, revision))]
+    pub async fn restore_revision(&self, id: &str, revision: u32, context: &AuditContext) -> Result<User, DatabaseError> {
+        // Soft-deleted users have to be restored first.
+        let revisions = self.get_revisions(id, false).await?;
+        let Some(revision) = revisions.into_iter().find(|stored| stored.revision == revision) else {
+            return Err(DatabaseError::RevisionNotFound(id.to_string(), revision))
+        };
 
-/// Generate an update `Document` based on the differences between given users.
-///
-/// ## Arguments.
-/// * `original_user` - The original user we are comparing against.
-/// * `updated_user` - User with changes made to it.
-///
-/// ## Returns.
-/// A `Document` containing changes and a `set`-command.
-fn generate_update_document(original_user: User, updated_user: User) -> Document {
-    let mut update_document = doc! {};
-
-    // Serialize the original and update structs to JSON value.
-    let original_json = serde_json::to_value(original_user).unwrap();
-    let updated_json = serde_json::to_value(updated_user).unwrap();
-
-    // Iterate over the fields and compare.
-    for (field, updated_value) in updated_json.as_object().unwrap() {
-        if let Some(original_value) = original_json.get(&field) {
-            // Skip if values match.
-            if &original_value == &updated_value {
-                continue
-            }
+        let mut user = revision.user;
+        user.id = Some(id.to_string());
+
+        match self.update_user(user.clone(), context).await {
+            Ok(()) => Ok(user),
+            // Users only found in JsonPlaceholder are always at their upstream snapshot.
+            Err(DatabaseError::UserNotFound(_)) if revision.actor == UPSTREAM_ACTOR => Ok(user),
+            Err(e) => Err(e)
         }
-        update_document.insert("$set", doc! { field: bson::to_bson(updated_value).unwrap() });
     }
 
-    update_document
-}
+    /// Soft delete a user. The user is hidden from every read until restored or purged.
+    /// Users only found in JsonPlaceholder are stored as deleted copies.
+    ///
+    /// ## Arguments.
+    /// * `id` - User id.
+    /// * `context` - Who is making the change, for the audit log.
+    ///
+    /// ## Returns.
+    /// Result with an empty `OK` or an error.
+    #[instrument(skip_all, fields(user.id = id))]
+    pub async fn delete_user(&self, id: &str, context: &AuditContext) -> Result<(), DatabaseError> {
+        let deleted_at = DateTime::now();
+
+        if !self.observe("mark_deleted", self.repository.mark_deleted(id, deleted_at)).await? {
+            // Already deleted users are hidden like any other missing user.
+            if self.observe("get_deleted_user", self.repository.get_deleted_user(id)).await.is_ok() {
+                return Err(DatabaseError::UserNotFound(id.to_string()))
+            }
 
-/// Delete user with given id from database.
-/// Not used for now.
-///
-/// ## Arguments.
-/// * `id` - Id for the user to be deleted.
-/// * `connection_string` - Connection string that will be used to connect to MongoDB. Should contain username and password.
-/// * `database_name` - Database we are using.
-///
-/// # Returns.
-/// A result containing possible `DatabaseError` an `Ok(())` if everything goes as it should.
-async fn _remove_user_from_db_with_config(id: &str, connection_string: &str, database_name: &str) -> Result<(), DatabaseError> {
-    // Get collection.
-    let collection = get_user_collection(connection_string, database_name).await?;
-
-    // Delete user.
-    let result = collection.delete_one(
-        doc! {
-                "id": id
-        },
-        None
-    ).await;
-
-    match result {
-        Ok(_) => Ok(()),
-        Err(_) => Err(DatabaseError::OperationFailed)
+            let user = match self.client.get_user(id).await {
+                Ok(user) => user,
+                Err(UserClientError::UserNotFound(_)) => return Err(DatabaseError::UserNotFound(id.to_string())),
+                _ => return Err(DatabaseError::OperationFailed)
+            };
+            self.observe("insert_deleted", self.repository.insert_deleted(&user, deleted_at)).await?;
+        }
+
+        // Audit and return result.
+        let audit_entry = AuditEntry::new(AuditAction::Delete, id, context, deleted_at_change(None, Some(deleted_at)));
+        self.record_audit_entries(vec![audit_entry]).await;
+        Ok(())
     }
-}
 
-/// Get document count from database.
-///
-/// ## Arguments.
-/// * `connection_string` - Connection string that will be used to connect to MongoDB. Should contain username and password.
-/// * `database_name` - Database we are using.
-///
-/// # Returns.
-/// A result containing possible `DatabaseError` or the count of users in MongoDB.
-async fn get_users_count_with_config(database_name: &str, collection_name: &str) -> Result<u64, DatabaseError> {
-    // Get collection.
-    let collection = get_user_collection(database_name, collection_name).await?;
+    /// Restore a soft-deleted user.
+    ///
+    /// ## Arguments.
+    /// * `id` - User id.
+    /// * `context` - Who is making the change, for the audit log.
+    ///
+    /// ## Returns.
+    /// A result containing the restored user or an error.
+    #[instrument(skip_all, fields(user.id = id))]
+    pub async fn restore_deleted_user(&self, id: &str, context: &AuditContext) -> Result<User, DatabaseError> {
+        let deleted = self.observe("get_deleted_user", self.repository.get_deleted_user(id)).await?;
+        self.observe("restore_deleted_user", self.repository.restore_deleted_user(id)).await?;
+
+        // Audit and return result.
+        let audit_entry = AuditEntry::new(AuditAction::Restore, id, context, deleted_at_change(Some(deleted.deleted_at), None));
+        self.record_audit_entries(vec![audit_entry]).await;
+        Ok(deleted.user)
+    }
 
-    // Get user count.
-    match collection.count_documents(None, None).await {
-        Ok(count) => Ok(count),
-        _ => Err(DatabaseError::MongoConnectionFailed)
+    /// Get all soft-deleted users.
+    ///
+    /// ## Returns.
+    /// A result containing the deleted users or an error.
+    #[instrument(skip_all)]
+    pub async fn get_deleted_users(&self) -> Result<Vec<DeletedUser>, DatabaseError> {
+        self.observe("get_deleted_users", self.repository.get_deleted_users()).await
     }
-}
 
-#[cfg(test)]
-mod test {
-    use testcontainers::GenericImage;
-    use testcontainers::clients::Cli;
-    use super::*;
+    /// Get soft-deleted user with specific id.
+    ///
+    /// ## Arguments.
+    /// * `id` - User id.
+    ///
+    /// ## Returns.
+    /// A result containing the deleted user or `UserNotFound` if the user is not deleted.
+    #[instrument(skip_all, fields(user.id = id))]
+    pub async fn get_deleted_user(&self, id: &str) -> Result<DeletedUser, DatabaseError> {
+        self.observe("get_deleted_user", self.repository.get_deleted_user(id)).await
+    }
 
-    // Database name used in tests.
-    const DB_NAME: &str = "showcase_test";
-    // Connection string -template.
-    const C_STRING: &str = "mongodb://localhost:";
+    /// Permanently remove users that were soft-deleted before the given time.
+    ///
+    /// ## Arguments.
+    /// * `deleted_before` - Users deleted before this are removed.
+    ///
+    /// ## Returns.
+    /// A result containing the number of removed users or an error.
+    #[instrument(skip_all)]
+    pub async fn purge_deleted_users(&self, deleted_before: DateTime) -> Result<u64, DatabaseError> {
+        self.observe("purge_deleted_users", self.repository.purge_deleted_users(deleted_before)).await
+    }
 
-    #[tokio::test]
-    async fn test_get_collection_faulty_connection_string() {
-        assert!(get_user_collection(&"NOT_URL".to_string(), &"LOL".to_string()).await.is_err());
+    /// Get audit records of a user, oldest first.
+    ///
+    /// ## Arguments.
+    /// * `id` - User id.
+    #[instrument(skip_all, fields(user.id = id))]
+    pub async fn get_audit_entries(&self, id: &str) -> Result<Vec<AuditEntry>, DatabaseError> {
+        self.observe("get_audit_entries", self.repository.get_audit_entries(id)).await
     }
 
-    #[tokio::test]
-    async fn test_get_collection() {
-        let client = Cli::default();
-        let container = client.run(get_mongo_image());
+    /// Import parsed users in batches.
+    ///
+    /// ## Arguments.
+    /// * `rows` - Parsed input rows. Rows that failed parsing or validation are reported as is.
+    /// * `all_or_nothing` - If true, nothing is saved unless every row is valid and the whole import succeeds.
+    /// * `context` - Who is making the change, for the audit log.
+    ///
+    /// ## Returns.
+    /// A report with the created id or errors for every row.
+    #[instrument(skip_all, fields(rows = rows.len(), all_or_nothing))]
+    pub async fn import_users(&self, rows: Vec<ParsedRow>, all_or_nothing: bool, context: &AuditContext) -> ImportReport {
+        // Separate valid users from invalid rows.
+        let mut reports = vec![];
+        let mut row_numbers = vec![];
+        let mut users = vec![];
+        for parsed in rows {
+            match parsed.result {
+                Ok(user) => {
+                    row_numbers.push(parsed.row);
+                    users.push(user);
+                },
+                Err(errors) => reports.push(RowReport { row: parsed.row, id: None, errors })
+            }
+        }
 
-        let port = container.get_host_port_ipv4(27017);
-        let connection_string = format!("{}{}", C_STRING, port);
+        // In all-or-nothing mode a single invalid row cancels the whole import.
+        if all_or_nothing && !reports.is_empty() {
+            info!("Bulk import cancelled, {} rows failed validation.", reports.len());
+            row_numbers.into_iter().for_each(|row| reports.push(RowReport {
+                row,
+                id: None,
+                errors: vec!["Not imported because other rows failed validation.".to_string()]
+            }));
+            return ImportReport::from_rows(reports)
+        }
 
-        let collection_result =
-            get_user_collection(&connection_string, DB_NAME).await
-        ;
+        let results = self.observe("create_users", self.repository.create_users(&mut users, all_or_nothing)).await;
 
-        assert!(collection_result.is_ok());
+        // Audit every created user.
+        let audit_entries = users.iter()
+            .zip(&results)
+            .filter_map(|(user, result)| result.as_ref().ok().map(|id| {
+                AuditEntry::new(AuditAction::Create, id, context, diff_users(None, user))
+            }))
+            .collect()
+        ;
+        self.record_audit_entries(audit_entries).await;
 
-        let collection = collection_result.unwrap();
-        assert_eq!("users", collection.name());
+        let created_users: Vec<&User> = users.iter()
+            .zip(&results)
+            .filter(|(_, result)| result.is_ok())
+            .map(|(user, _)| user)
+            .collect()
+        ;
+        self.record_created_revisions(&created_users, context).await;
+
+        for (row, result) in row_numbers.into_iter().zip(results) {
+            reports.push(match result {
+                Ok(id) => RowReport { row, id: Some(id), errors: vec![] },
+                Err(DatabaseError::MongoConnectionFailed) => RowReport { row, id: None, errors: vec!["Could not connect to database.".to_string()] },
+                Err(_) => RowReport { row, id: None, errors: vec!["Saving user failed.".to_string()] }
+            });
+        }
 
-        container.stop();
+        let report = ImportReport::from_rows(reports);
+        info!("Bulk import finished. Created {} users, {} rows failed.", report.created, report.failed);
+        report
     }
 
-    #[tokio::test]
-    async fn test_get_user_from_database_not_found() {
-        let client = Cli::default();
-        let container = client.run(get_mongo_image());
-
-        let port = container.get_host_port_ipv4(27017);
-        let connection_string = format!("{}{}", C_STRING, port);
+    /// Run a repository operation in a client span, recording its latency and outcome.
+    ///
+    /// ## Arguments.
+    /// * `operation` - Name of the repository operation.
+    /// * `future` - The operation.
+    async fn observe<T: Outcome>(&self, operation: &str, future: impl Future<Output = T>) -> T {
+        let span = info_span!(
+            "mongodb",
+            otel.name = operation,
+            otel.kind = "client",
+            otel.status_code = field::Empty,
+            db.system = "mongodb",
+            db.operation = operation
+        );
 
-        assert_eq!(
-            Err(DatabaseError::UserNotFound("666".to_string())),
+        let started = Instant::now();
+        let result = future.instrument(span.clone()).await;
+        self.metrics.observe_database(operation, result.succeeded(), started.elapsed());
 
-            get_user_from_db_with_config(
-                &"666".to_string(),
-                &connection_string,
-                DB_NAME
-            ).await
-        );
+        if !result.succeeded() {
+            span.record("otel.status_code", "ERROR");
+        }
+        result
+    }
 
-        container.stop();
+    /// Store audit records. Failures are logged but never fail the change being audited.
+    ///
+    /// ## Arguments.
+    /// * `entries` - Audit records.
+    async fn record_audit_entries(&self, entries: Vec<AuditEntry>) {
+        if let Err(e) = self.observe("insert_audit_entries", self.repository.insert_audit_entries(entries)).await {
+            warn!("Could not record audit entries: {e:?}");
+        }
     }
 
-    #[tokio::test]
-    async fn test_add_and_get_user_and_get_all_users_from_database() {
-        let client = Cli::default();
-        let container = client.run(get_mongo_image());
-
-        let port = container.get_host_port_ipv4(27017);
-        let connection_string = format!("{}{}", C_STRING, port);
-
-        let insert_result =
-            create_user_to_db_with_config(
-                &mut User::_create_test_user(None),
-                &connection_string,
-                DB_NAME
-            ).await
-        ;
+    /// Store revision 1 of newly created users. Failures are logged but never fail the change itself.
+    ///
+    /// ## Arguments.
+    /// * `users` - Created users with ids.
+    /// * `context` - Who made the change.
+    async fn record_created_revisions(&self, users: &[&User], context: &AuditContext) {
+        let revisions: Vec<Revision> = users.iter().map(|user| Revision::new(1, user, context)).collect();
+        if let Err(e) = self.observe("insert_revisions", self.repository.insert_revisions(revisions)).await {
+            warn!("Could not record revisions: {e:?}");
+        }
+    }
 
-        assert!(insert_result.is_ok());
-        let inserted_id = insert_result.unwrap();
+    /// Store the next revision of an updated user. Users without revisions get the state before
+    /// the update as their baseline revision 1 first.
+    ///
+    /// ## Arguments.
+    /// * `before` - User before the update.
+    /// * `after` - User after the update.
+    /// * `context` - Who made the change.
+    async fn record_updated_revision(&self, before: &User, after: &User, context: &AuditContext) {
+        let id = after.id.as_deref().unwrap();
+
+        // Concurrent updates may pick the same number. Only one gets it, the others try the next one.
+        for _ in 0..REVISION_ATTEMPTS {
+            let latest = match self.observe("latest_revision_number", self.repository.latest_revision_number(id)).await {
+                Ok(latest) => latest,
+                Err(e) => {
+                    warn!("Could not read revisions: {e:?}");
+                    return
+                }
+            };
+
+            let mut revisions = vec![];
+            if latest == 0 {
+                revisions.push(Revision::baseline(before, UNKNOWN_ACTOR));
+            }
+            revisions.push(Revision::new(latest.max(1) + 1, after, context));
+            match self.observe("insert_revisions", self.repository.insert_revisions(revisions)).await {
+                Ok(()) => return,
+                Err(DatabaseError::RevisionConflict) => info!("Revision {} of user {id} was taken, retrying.", latest.max(1) + 1),
+                Err(e) => {
+                    warn!("Could not record revisions: {e:?}");
+                    return
+                }
+            }
+        }
+        warn!("Could not record revision of user {id}, {REVISION_ATTEMPTS} attempts were taken by concurrent updates.");
+    }
+}
 
-        let search_result =
-            get_user_from_db_with_config(
-                &inserted_id,
-                &connection_string,
-               DB_NAME
-            ).await
-        ;
+/// Merge database users with JsonPlaceholder users. JsonPlaceholder users are only emitted
+/// after the database stream ends and only if no database user had the same id.
+///
+/// ## Arguments.
+/// * `database_users` - Stream of users saved in the database.
+/// * `jph_users` - Users fetched from JsonPlaceholder.
+fn merge_user_streams(database_users: impl Stream<Item = User> + Send + Unpin + 'static, jph_users: Vec<User>) -> impl Stream<Item = User> + Send + 'static {
+    let state = (Some(database_users), VecDeque::from(jph_users));
+
+    stream::unfold(state, |(mut database_users, mut jph_users)| async move {
+        // Emit database users first and drop overridden JsonPlaceholder users as we go.
+        if let Some(users) = database_users.as_mut() {
+            match users.next().await {
+                Some(user) => {
+                    jph_users.retain(|jph_user| jph_user.id != user.id);
+                    return Some((user, (database_users, jph_users)))
+                },
+                None => database_users = None
+            }
+        }
 
-        assert!(search_result.is_ok());
-        let user = search_result.unwrap();
+        jph_users.pop_front().map(|user| (user, (database_users, jph_users)))
+    })
+}
 
-        assert_eq!(&inserted_id, &user.id.unwrap());
+#[cfg(test)]
+pub mod test {
+    use crate::configuration::ConfigSources;
+    use crate::user_client::test::FakeUserClient;
+    use crate::user_repository::test::InMemoryUserRepository;
+    use super::*;
 
-        let get_all_result =
-            get_all_users_from_db_with_config(
-                &connection_string,
-                DB_NAME
-            ).await
-        ;
+    /// Service using the test configuration, in-memory storage and the given upstream users.
+    ///
+    /// ## Arguments.
+    /// * `repository` - Storage of users.
+    /// * `jph_users` - Users found in JsonPlaceholder.
+    pub fn test_service(repository: InMemoryUserRepository, jph_users: Vec<User>) -> UserService {
+        let sources = ConfigSources { path: "resources/test/config.toml".to_string(), ..ConfigSources::default() };
+        let config = ConfigurationHandle::new(Configuration::read_from_config_file(&sources).unwrap(), sources);
+        UserService::new(Arc::new(repository), Arc::new(FakeUserClient { users: jph_users }), Arc::new(config), Arc::new(Metrics::new()))
+    }
 
-        assert!(get_all_result.is_ok());
-        let user_list = get_all_result.unwrap();
-        assert!(!user_list.is_empty());
+    fn jph_users() -> Vec<User> {
+        (1..=3).map(|id| User::_create_test_user(Some(id.to_string()))).collect()
+    }
 
-        assert_eq!(inserted_id, user_list.get(0).cloned().unwrap().id.unwrap());
+    #[tokio::test]
+    async fn test_merge_user_streams() {
+        let database_users = vec![
+            User::_create_test_user(Some("2".to_string())),
+            User::_create_test_user(Some("101".to_string()))
+        ];
+
+        let ids: Vec<String> = merge_user_streams(stream::iter(database_users), jph_users())
+            .map(|user| user.id.unwrap())
+            .collect()
+            .await
+        ;
 
-        container.stop();
+        assert_eq!(vec!["2", "101", "1", "3"], ids);
     }
 
     #[tokio::test]
-    async fn test_add_and_get_and_remove_user_from_database() {
-        let client = Cli::default();
-        let container = client.run(get_mongo_image());
-
-        let port = container.get_host_port_ipv4(27017);
-        let connection_string = format!("{}{}", C_STRING, port);
-
-        let insert_result =
-            create_user_to_db_with_config(
-                &mut User::_create_test_user(None),
-                &connection_string.clone(),
-                DB_NAME
-            ).await
-        ;
+    async fn test_get_users() {
+        let mut overridden = User::_create_test_user(Some("2".to_string()));
+        overridden.name = "NEW NAME".to_string();
+        let service = test_service(InMemoryUserRepository::with_users(vec![overridden]), jph_users());
 
-        assert!(insert_result.is_ok());
-        let inserted_id = insert_result.unwrap();
+        let users = service.get_users().await;
+        assert_eq!(3, users.len());
+        assert_eq!("NEW NAME", users[0].name);
 
-        let search_result =
-            get_user_from_db_with_config(
-                &inserted_id,
-                &connection_string,
-                DB_NAME
-            ).await
-        ;
+        assert_eq!(users, service.stream_users().await.collect::<Vec<User>>().await);
+    }
 
-        assert!(search_result.is_ok());
-        let user_id = search_result.unwrap().id.unwrap();
+    #[tokio::test]
+    async fn test_create_and_update_user() {
+        let service = test_service(InMemoryUserRepository::default(), jph_users());
 
-        assert_eq!(&inserted_id, &user_id);
+        let user = service.create_new_user(User::_create_test_user(None), &test_context()).await.unwrap();
+        assert_eq!(Some("101".to_string()), user.id);
 
-        let delete_result =
-            _remove_user_from_db_with_config(
-                &user_id,
-                &connection_string,
-                DB_NAME
-            ).await
-        ;
+        let mut updated = user.clone();
+        updated.name = "NEW NAME".to_string();
+        service.update_user(updated, &test_context()).await.unwrap();
+        assert_eq!("NEW NAME", service.get_user("101").await.unwrap().name);
 
-        assert!(delete_result.is_ok());
+        let revisions = service.get_revisions("101", false).await.unwrap();
+        assert_eq!(vec![1, 2], revisions.iter().map(|revision| revision.revision).collect::<Vec<u32>>());
+        assert_eq!(2, service.get_audit_entries("101").await.unwrap().len());
+
+        // Upstream users can not be updated.
         assert_eq!(
-            get_user_from_db_with_config(
-                &user_id,
-                &connection_string,
-                DB_NAME
-            ).await,
-            Err(DatabaseError::UserNotFound(user_id))
+            Err(DatabaseError::UserNotFound("1".to_string())),
+            service.update_user(User::_create_test_user(Some("1".to_string())), &test_context()).await
         );
+    }
 
-        container.stop();
+    #[tokio::test]
+    async fn test_delete_and_restore_upstream_user() {
+        let service = test_service(InMemoryUserRepository::default(), jph_users());
+
+        service.delete_user("1", &test_context()).await.unwrap();
+        assert_eq!(Err(DatabaseError::UserNotFound("1".to_string())), service.get_user("1").await);
+        assert_eq!(2, service.get_users().await.len());
+        assert_eq!(Err(DatabaseError::UserNotFound("1".to_string())), service.delete_user("1", &test_context()).await);
+        assert_eq!(Err(DatabaseError::UserNotFound("666".to_string())), service.delete_user("666", &test_context()).await);
+
+        let restored = service.restore_deleted_user("1", &test_context()).await.unwrap();
+        assert_eq!(Some("1".to_string()), restored.id);
+        assert_eq!(3, service.get_users().await.len());
     }
 
     #[tokio::test]
-    async fn test_add_and_update() {
-        let client = Cli::default();
-        let container = client.run(get_mongo_image());
-
-        let port = container.get_host_port_ipv4(27017);
-        let connection_string = format!("{}{}", C_STRING, port);
-
-        let insert_result =
-            create_user_to_db_with_config(
-                &mut User::_create_test_user(None),
-                &connection_string,
-                DB_NAME
-            ).await
-        ;
+    async fn test_get_revisions_of_upstream_user() {
+        let service = test_service(InMemoryUserRepository::default(), jph_users());
 
-        assert!(insert_result.is_ok());
-        let inserted_id = insert_result.unwrap();
+        let revisions = service.get_revisions("1", false).await.unwrap();
+        assert_eq!(1, revisions.len());
+        assert_eq!(UPSTREAM_ACTOR, revisions[0].actor);
 
-        let mut user = User::_create_test_user(Some(inserted_id.clone()));
-        user.name = "NEW NAME".to_string();
+        // Restoring the upstream snapshot changes nothing.
+        assert!(service.restore_revision("1", 1, &test_context()).await.is_ok());
+        assert_eq!(
+            Err(DatabaseError::RevisionNotFound("1".to_string(), 2)),
+            service.restore_revision("1", 2, &test_context()).await
+        );
+    }
 
-        let update_result =
-            update_user_in_db_with_config(
-                user,
-                &connection_string,
-                DB_NAME
-            ).await
-        ;
+    #[tokio::test]
+    async fn test_history_of_deleted_user() {
+        let service = test_service(InMemoryUserRepository::default(), jph_users());
+        service.create_new_user(User::_create_test_user(None), &test_context()).await.unwrap();
+        let as_of = DateTime::now();
+        service.delete_user("101", &test_context()).await.unwrap();
+        service.delete_user("1", &test_context()).await.unwrap();
+
+        let not_found = DatabaseError::UserNotFound("101".to_string());
+        assert_eq!(Some(not_found.clone()), service.get_revisions("101", false).await.err());
+        assert_eq!(Err(not_found.clone()), service.get_user_as_of("101", as_of, false).await);
+        assert_eq!(Err(not_found), service.restore_revision("101", 1, &test_context()).await);
+        // Upstream users were not reported as restored either.
+        assert_eq!(
+            Err(DatabaseError::UserNotFound("1".to_string())),
+            service.restore_revision("1", 1, &test_context()).await
+        );
 
-        assert!(update_result.is_ok());
+        // Admins can still read the history.
+        assert_eq!(1, service.get_revisions("101", true).await.unwrap().len());
+        assert_eq!(Some("101".to_string()), service.get_user_as_of("101", as_of, true).await.unwrap().id);
+    }
 
-        let find_result =
-            get_user_from_db_with_config(
-                inserted_id.as_str(),
-                &connection_string,
-                DB_NAME
-            ).await
-        ;
+    #[tokio::test]
+    async fn test_purged_ids_are_not_reused() {
+        let service = test_service(InMemoryUserRepository::default(), vec![]);
+        service.create_new_user(User::_create_test_user(None), &test_context()).await.unwrap();
+        service.delete_user("101", &test_context()).await.unwrap();
+        assert_eq!(1, service.purge_deleted_users(DateTime::from_millis(i64::MAX)).await.unwrap());
+
+        let user = service.create_new_user(User::_create_test_user(None), &test_context()).await.unwrap();
+        assert_eq!(Some("102".to_string()), user.id);
+        assert_eq!(1, service.get_revisions("102", false).await.unwrap().len());
+        assert_eq!(1, service.get_audit_entries("102").await.unwrap().len());
+    }
 
-        assert!(find_result.is_ok());
-        assert_eq!("NEW NAME".to_string(), find_result.unwrap().name);
+    #[tokio::test]
+    async fn test_import_users_all_or_nothing_invalid_row() {
+        let service = test_service(InMemoryUserRepository::default(), vec![]);
+        let rows = vec![
+            ParsedRow { row: 1, result: Ok(User::_create_test_user(None)) },
+            ParsedRow { row: 2, result: Err(vec!["ERROR".to_string()]) }
+        ];
+
+        let report = service.import_users(rows, true, &test_context()).await;
+
+        assert_eq!(0, report.created);
+        assert_eq!(2, report.failed);
+        assert_eq!(vec!["ERROR".to_string()], report.rows[1].errors);
+        assert!(service.get_users().await.is_empty());
     }
 
-    fn get_mongo_image() -> GenericImage {
-        GenericImage::new("mongo", "latest")
-            .with_env_var("MONGO_INITDB_DATABASE", "showcase_test")
-            .with_exposed_port(27017)
+    #[tokio::test]
+    async fn test_import_users() {
+        let service = test_service(InMemoryUserRepository::default(), vec![]);
+        let rows = vec![
+            ParsedRow { row: 1, result: Ok(User::_create_test_user(None)) },
+            ParsedRow { row: 2, result: Err(vec!["ERROR".to_string()]) },
+            ParsedRow { row: 3, result: Ok(User::_create_test_user(None)) }
+        ];
+
+        let report = service.import_users(rows, false, &test_context()).await;
+
+        assert_eq!(2, report.created);
+        assert_eq!(1, report.failed);
+        assert_eq!(Some("101".to_string()), report.rows[0].id);
+        assert_eq!(2, service.get_users().await.len());
+        assert_eq!(1, service.get_revisions("102", false).await.unwrap().len());
+        assert_eq!(1, service.get_audit_entries("102").await.unwrap().len());
+    }
+
+    fn test_context() -> AuditContext {
+        AuditContext {
+            actor: "TEST_SUBJECT".to_string(),
+            request_id: None
+        }
     }
 }